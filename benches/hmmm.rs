@@ -0,0 +1,124 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use hmmm_rs::{compile, read_compiled_hmmm, Instruction, Simulator};
+
+// Counts down from 100 inside a loop counting down from 100,
+// adding as it goes, so takes around 30,000 steps
+static COMPUTE_LOOP: &str = "0 setn r1 100
+1 setn r2 100
+2 add r3 r3 r2
+3 addn r2 -1
+4 jnezn r2 2
+5 addn r1 -1
+6 jnezn r1 1
+7 halt";
+
+/// Cycles through a few instruction types, with comments
+/// so each line takes some work to split
+fn generated_source(lines: usize) -> Vec<String> {
+    (0..lines)
+        .map(|i| match i % 4 {
+            0 => format!("{} setn r{} {} # set", i, i % 16, i % 128),
+            1 => format!("{} add r1, r2, r{} # add", i, i % 16),
+            2 => format!("{} storen r3 {} # store", i, i % 256),
+            _ => format!("{} jnezn r4 {} # jump", i, i % 256),
+        })
+        .collect()
+}
+
+fn compile_256_lines(c: &mut Criterion) {
+    let source = generated_source(256);
+
+    c.bench_function("compile 256 lines", |b| {
+        b.iter(|| Simulator::compile_hmmm(black_box(source.clone()), true).unwrap())
+    });
+}
+
+/// A program filling memory, padded out to around 10,000 lines with
+/// comments, as only 256 instructions fit in memory
+fn generated_file() -> Vec<String> {
+    generated_source(256)
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, line)| {
+            (0..38)
+                .map(move |j| format!("# instruction {}, note {}: counting down", i, j))
+                .chain(std::iter::once(line))
+        })
+        .collect()
+}
+
+fn compile_10k_lines(c: &mut Criterion) {
+    let source = generated_file();
+
+    c.bench_function("compile 10k lines", |b| {
+        b.iter(|| Simulator::compile_hmmm(black_box(source.clone()), true).unwrap())
+    });
+}
+
+#[cfg(feature = "parallel")]
+fn compile_10k_lines_in_parallel(c: &mut Criterion) {
+    let source = generated_file();
+
+    c.bench_function("compile 10k lines in parallel", |b| {
+        b.iter(|| Simulator::compile_hmmm_parallel(black_box(source.clone()), true).unwrap())
+    });
+}
+
+#[cfg(not(feature = "parallel"))]
+fn compile_10k_lines_in_parallel(_: &mut Criterion) {}
+
+fn assemble_10k_instructions(c: &mut Criterion) {
+    // Already cleaned lines, as new_from_text takes them
+    // after the compiler has split off the line number
+    let lines: Vec<String> = (0..10_000)
+        .map(|i| match i % 4 {
+            0 => format!("setn r{} {}", i % 16, i % 128),
+            1 => format!("add r1 r2 r{}", i % 16),
+            2 => format!("storen r3 {}", i % 256),
+            _ => format!("jnezn r4 {}", i % 256),
+        })
+        .collect();
+
+    c.bench_function("assemble 10k instructions", |b| {
+        b.iter(|| {
+            for line in &lines {
+                Instruction::new_from_text(black_box(line)).unwrap();
+            }
+        })
+    });
+}
+
+fn decode_256_lines(c: &mut Criterion) {
+    // One line per word of memory, cycling through every
+    // instruction type so each lookup is exercised
+    let raw_binary: Vec<String> = (0..256_u32)
+        .map(|i| format!("{:016b}", (i * 257) as u16))
+        .collect();
+
+    c.bench_function("decode 256 lines", |b| {
+        b.iter(|| read_compiled_hmmm(black_box(raw_binary.clone())).unwrap())
+    });
+}
+
+fn run_compute_loop(c: &mut Criterion) {
+    let program = compile(COMPUTE_LOOP).unwrap().instructions;
+
+    c.bench_function("run compute loop", |b| {
+        b.iter(|| {
+            let mut simulator = Simulator::new_headless(black_box(program.clone()));
+            while simulator.step().is_ok() {}
+            simulator.get_register(3)
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    compile_256_lines,
+    compile_10k_lines,
+    compile_10k_lines_in_parallel,
+    assemble_10k_instructions,
+    decode_256_lines,
+    run_compute_loop
+);
+criterion_main!(benches);
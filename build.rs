@@ -0,0 +1,33 @@
+use std::path::Path;
+use std::process::Command;
+
+// Passes the git commit being built to --version, if
+// building from a git checkout
+fn main() {
+    let git_hash = Command::new("git")
+        .args(&["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+
+    if let Some(git_hash) = git_hash {
+        println!("cargo:rustc-env=HMMM_GIT_HASH={}", git_hash.trim());
+    }
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+
+    // HEAD only changes on checkout, so also watch the branch it
+    // points to, which moves with every commit. Paths that don't
+    // exist would make cargo rerun this on every build
+    let head = std::fs::read_to_string(".git/HEAD").unwrap_or_default();
+    if let Some(branch) = head.trim().strip_prefix("ref: ") {
+        let branch_path = Path::new(".git").join(branch);
+        if branch_path.exists() {
+            println!("cargo:rerun-if-changed={}", branch_path.display());
+        }
+    }
+    if Path::new(".git/logs/HEAD").exists() {
+        println!("cargo:rerun-if-changed=.git/logs/HEAD");
+    }
+}
@@ -0,0 +1,9 @@
+#![no_main]
+use hmmm_rs::read_compiled_hmmm;
+use libfuzzer_sys::fuzz_target;
+
+// Any input should either decode or return a CompileErr, never panic
+fuzz_target!(|data: &str| {
+    let raw_binary: Vec<String> = data.lines().map(|line| line.trim().to_string()).collect();
+    let _ = read_compiled_hmmm(raw_binary);
+});
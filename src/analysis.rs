@@ -0,0 +1,360 @@
+use super::simulator::*;
+
+/// A run of instructions that, once entered, always
+/// executes from its start to its end
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BasicBlock {
+    /// Address of the first instruction in the block
+    pub start: usize,
+    /// Address of the last instruction in the block
+    pub end: usize,
+    /// Start addresses of the blocks control can flow to next
+    pub successors: Vec<usize>,
+}
+
+/// Returns the address an instruction jumps or calls to,
+/// if it is known without running the program
+pub fn jump_target(instruction: &Instruction) -> Option<usize> {
+    match instruction.instruction_type.names[0] {
+        "jumpn" | "jeqzn" | "jnezn" | "jgtzn" | "jltzn" | "calln" => {
+            Some(instruction.byte(2) as usize)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the address an instruction jumps, calls, loads,
+/// or stores to, if it is written into the instruction itself
+pub fn address_argument(instruction: &Instruction) -> Option<usize> {
+    match instruction.instruction_type.names[0] {
+        "loadn" | "storen" => Some(instruction.byte(2) as usize),
+        _ => jump_target(instruction),
+    }
+}
+
+/// Function to find every instruction whose address argument is
+/// past the end of the program, as (address, target) pairs. Loads
+/// and stores there are often deliberate, to keep data after the
+/// code, but jumps and calls there will run into data
+pub fn out_of_range_addresses(compiled_text: &[Instruction]) -> Vec<(usize, usize)> {
+    compiled_text
+        .iter()
+        .enumerate()
+        .filter_map(|(address, instruction)| {
+            address_argument(instruction)
+                .filter(|target| *target >= compiled_text.len())
+                .map(|target| (address, target))
+        })
+        .collect()
+}
+
+/// Function to describe an address argument past the end
+/// of a program of the given length
+pub fn out_of_range_message(instruction: &Instruction, target: usize, length: usize) -> String {
+    format!(
+        "{} uses address {}, past the end of the program at address {}",
+        instruction.instruction_type.names[0],
+        target,
+        length.saturating_sub(1)
+    )
+}
+
+/// Returns true if the program has a halt anywhere. Without
+/// one, it can only stop by running into data or the step limit
+pub fn has_halt(compiled_text: &[Instruction]) -> bool {
+    compiled_text
+        .iter()
+        .any(|instruction| instruction.instruction_type.names[0] == "halt")
+}
+
+/// Message for a program with no halt
+pub static MISSING_HALT_MESSAGE: &str =
+    "program has no halt, so it will run past its last instruction";
+
+/// Returns true if control can continue on to the next
+/// address after the instruction. Calls count, as they
+/// are expected to return there
+pub fn falls_through(instruction: &Instruction) -> bool {
+    !matches!(
+        instruction.instruction_type.names[0],
+        "halt" | "jumpn" | "jumpr"
+    )
+}
+
+/// Returns true if the instruction can send control
+/// anywhere other than the next address
+fn ends_block(instruction: &Instruction) -> bool {
+    jump_target(instruction).is_some() || !falls_through(instruction)
+}
+
+/// Function to split a program into basic blocks, starting new
+/// blocks at jump targets and after any jump, call, or halt.
+/// Blocks are in address order, and targets past the end of the
+/// program are left out of the successors
+pub fn basic_blocks(compiled_text: &[Instruction]) -> Vec<BasicBlock> {
+    if compiled_text.is_empty() {
+        return Vec::new();
+    }
+
+    let mut leaders: Vec<usize> = vec![0];
+
+    for (address, instruction) in compiled_text.iter().enumerate() {
+        if let Some(target) = jump_target(instruction) {
+            if target < compiled_text.len() {
+                leaders.push(target);
+            }
+        }
+
+        if ends_block(instruction) && address + 1 < compiled_text.len() {
+            leaders.push(address + 1);
+        }
+    }
+
+    leaders.sort_unstable();
+    leaders.dedup();
+
+    let mut blocks: Vec<BasicBlock> = Vec::new();
+
+    for (index, start) in leaders.iter().enumerate() {
+        let end = match leaders.get(index + 1) {
+            Some(next_start) => next_start - 1,
+            None => compiled_text.len() - 1,
+        };
+        let last = &compiled_text[end];
+        let mut successors: Vec<usize> = Vec::new();
+
+        if let Some(target) = jump_target(last) {
+            if target < compiled_text.len() {
+                successors.push(target);
+            }
+        }
+
+        let next = end + 1;
+        if falls_through(last) && next < compiled_text.len() && !successors.contains(&next) {
+            successors.push(next);
+        }
+
+        blocks.push(BasicBlock {
+            start: *start,
+            end,
+            successors,
+        });
+    }
+
+    blocks
+}
+
+/// Returns true if control can leave the program from the end of
+/// the block: by halting, returning (or jumping) through a
+/// register, or going past the last instruction
+fn block_exits(compiled_text: &[Instruction], block: &BasicBlock) -> bool {
+    let last = &compiled_text[block.end];
+
+    matches!(last.instruction_type.names[0], "halt" | "jumpr")
+        || jump_target(last).map_or(false, |target| target >= compiled_text.len())
+        || (falls_through(last) && block.end + 1 >= compiled_text.len())
+}
+
+/// Function to find the blocks reachable from the block at start,
+/// including itself, as indexes into blocks
+fn reachable_blocks(blocks: &[BasicBlock], start: usize) -> Vec<usize> {
+    let mut visited: Vec<usize> = Vec::new();
+    let mut to_visit: Vec<usize> = vec![start];
+
+    while let Some(address) = to_visit.pop() {
+        if let Some(index) = blocks.iter().position(|block| block.start == address) {
+            if !visited.contains(&index) {
+                visited.push(index);
+                to_visit.extend(blocks[index].successors.iter());
+            }
+        }
+    }
+
+    visited
+}
+
+/// Function to find likely infinite loops: cycles of basic blocks
+/// that nothing reachable from can halt, return, or leave. Returns
+/// the start address of each, as the lowest address in the cycle.
+/// Jumps through registers are not followed, so this only finds
+/// loops that are certain to never end
+pub fn infinite_loops(compiled_text: &[Instruction]) -> Vec<usize> {
+    let blocks = basic_blocks(compiled_text);
+    let reachable: Vec<Vec<usize>> = blocks
+        .iter()
+        .map(|block| reachable_blocks(&blocks, block.start))
+        .collect();
+    let mut loops: Vec<usize> = Vec::new();
+
+    for (index, block) in blocks.iter().enumerate() {
+        let in_cycle = block
+            .successors
+            .iter()
+            .filter_map(|successor| blocks.iter().position(|other| other.start == *successor))
+            .any(|successor| reachable[successor].contains(&index));
+        let escapes = reachable[index]
+            .iter()
+            .any(|other| block_exits(compiled_text, &blocks[*other]));
+
+        // Only the first block of each cycle is reported
+        let first_in_cycle = !(0..index)
+            .any(|other| reachable[index].contains(&other) && reachable[other].contains(&index));
+
+        if in_cycle && !escapes && first_in_cycle {
+            loops.push(block.start);
+        }
+    }
+
+    loops
+}
+
+/// Message for a likely infinite loop starting at the given address
+pub fn infinite_loop_message(start: usize) -> String {
+    format!(
+        "the loop at address {} can never halt or leave, so it will run forever",
+        start
+    )
+}
+
+/// The subroutines of a program and the calls between them
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CallGraph {
+    /// Entry address of each subroutine, starting with 0 for
+    /// the main program
+    pub entries: Vec<usize>,
+    /// (caller, callee) pairs of subroutine entry addresses
+    pub calls: Vec<(usize, usize)>,
+}
+
+/// Function to build a program's call graph from its calln targets.
+/// Each subroutine is the code reachable from its entry without
+/// following calls, and every calln in it adds an edge
+pub fn call_graph(compiled_text: &[Instruction]) -> CallGraph {
+    let blocks = basic_blocks(compiled_text);
+    let mut entries: Vec<usize> = vec![0];
+
+    for instruction in compiled_text.iter() {
+        if instruction.instruction_type.names[0] == "calln" {
+            if let Some(target) = jump_target(instruction) {
+                if target < compiled_text.len() {
+                    entries.push(target);
+                }
+            }
+        }
+    }
+
+    entries.sort_unstable();
+    entries.dedup();
+
+    let mut calls: Vec<(usize, usize)> = Vec::new();
+
+    for entry in entries.iter() {
+        for block in subroutine_blocks(compiled_text, &blocks, *entry) {
+            for instruction in compiled_text[block.start..=block.end].iter() {
+                if instruction.instruction_type.names[0] == "calln" {
+                    if let Some(target) = jump_target(instruction) {
+                        if target < compiled_text.len() && !calls.contains(&(*entry, target)) {
+                            calls.push((*entry, target));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    calls.sort_unstable();
+
+    CallGraph { entries, calls }
+}
+
+/// Function to find the basic blocks of the subroutine starting at
+/// entry: those reachable from it without following calls
+fn subroutine_blocks<'a>(
+    compiled_text: &[Instruction],
+    blocks: &'a [BasicBlock],
+    entry: usize,
+) -> Vec<&'a BasicBlock> {
+    let mut visited: Vec<&BasicBlock> = Vec::new();
+    let mut to_visit: Vec<usize> = vec![entry];
+
+    while let Some(start) = to_visit.pop() {
+        let block = match blocks.iter().find(|block| block.start == start) {
+            Some(block) => block,
+            None => continue,
+        };
+
+        if visited.contains(&block) {
+            continue;
+        }
+        visited.push(block);
+
+        let last = &compiled_text[block.end];
+
+        // Calls are edges between subroutines, so only
+        // follow them back to where they return
+        for successor in block.successors.iter() {
+            let is_call_target = last.instruction_type.names[0] == "calln"
+                && jump_target(last) == Some(*successor)
+                && *successor != block.end + 1;
+
+            if !is_call_target {
+                to_visit.push(*successor);
+            }
+        }
+    }
+
+    visited
+}
+
+/// Returns the registers an instruction writes. r0 is left out,
+/// as writing it does nothing
+pub fn written_registers(instruction: &Instruction) -> Vec<u8> {
+    let registers: Vec<u8> = instruction
+        .arguments()
+        .iter()
+        .filter_map(|argument| argument.strip_prefix('r')?.parse::<u8>().ok())
+        .collect();
+
+    let written: Vec<u8> = match instruction.instruction_type.names[0] {
+        "read" | "setn" | "loadn" | "loadr" | "addn" | "copy" | "neg" | "add" | "sub" | "mul"
+        | "div" | "mod" | "calln" => registers.into_iter().take(1).collect(),
+        // Both the value and the stack pointer change
+        "popr" => registers,
+        "pushr" => registers.into_iter().skip(1).collect(),
+        _ => Vec::new(),
+    };
+
+    written.into_iter().filter(|register| *register != 0).collect()
+}
+
+/// Function to find which registers the subroutine starting at entry
+/// can change before it returns, including through the subroutines
+/// it calls, so callers know which of theirs to save first
+pub fn clobbered_registers(compiled_text: &[Instruction], entry: usize) -> Vec<u8> {
+    let blocks = basic_blocks(compiled_text);
+    let mut clobbered: Vec<u8> = Vec::new();
+    let mut visited_entries: Vec<usize> = Vec::new();
+    let mut to_visit: Vec<usize> = vec![entry];
+
+    while let Some(entry) = to_visit.pop() {
+        if visited_entries.contains(&entry) {
+            continue;
+        }
+        visited_entries.push(entry);
+
+        for block in subroutine_blocks(compiled_text, &blocks, entry) {
+            for instruction in compiled_text[block.start..=block.end].iter() {
+                clobbered.extend(written_registers(instruction));
+
+                if instruction.instruction_type.names[0] == "calln" {
+                    to_visit.extend(jump_target(instruction));
+                }
+            }
+        }
+    }
+
+    clobbered.sort_unstable();
+    clobbered.dedup();
+
+    clobbered
+}
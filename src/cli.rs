@@ -0,0 +1,1140 @@
+use super::autograder::*;
+use super::formats::*;
+use super::*;
+use clap::{App, Arg, ArgMatches, Shell, SubCommand};
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use std::sync::mpsc;
+use std::{thread, time};
+use terminal::*;
+
+// Most steps each program may take when diffing runs,
+// so programs that never halt still finish
+const DIFF_STEP_LIMIT: u64 = 100_000;
+// Most rows the debug step table prints, unless verbose
+const DEBUG_TABLE_ROW_LIMIT: u64 = 1000;
+// How long to wait for saves to settle before recompiling in watch mode
+const WATCH_DEBOUNCE_MS: u64 = 250;
+
+/// Function to pretty-print a runtime error and exit
+/// the program gracefully
+pub fn raise_runtime_error(sim: &Simulator, error: &RuntimeErr) {
+    // Easy way to display information: show the debug screen!
+    let _debug_result = print_debug_screen(sim);
+    let current_line = sim.get_program_counter();
+
+    let w = terminal::stdout();
+    w.act(Action::MoveCursorTo(0, 29)).unwrap();
+    println!("{}", format_banner("SIMULATION UNSUCCESSFUL", false));
+
+    println!(
+        "{} {:?}",
+        format!("{} {}:", " ERROR EXECUTING ADDRESS", current_line)
+            .on_red()
+            .white()
+            .bold(),
+        error
+    );
+    let current_line_contents = sim.get_memory(current_line).unwrap();
+    println!(
+        "{} {}\n",
+        " MEMORY ADDRESS CONTENTS:".on_red().white().bold(),
+        current_line_contents
+    );
+}
+
+/// IoHandler for debug mode, which asks for numbers and prints
+/// them in their own spots on the debug screen
+pub struct DebugScreenHandler {
+    pub read_mode: ReadMode,
+    pub write_mode: WriteMode,
+}
+
+impl IoHandler for DebugScreenHandler {
+    fn read(&mut self) -> Result<i16, RuntimeErr> {
+        let w = terminal::stdout();
+
+        loop {
+            let mut line = String::new();
+            let _ = w.act(Action::ShowCursor);
+            let _ = w.act(Action::EnableBlinking);
+            let _ = w.act(Action::MoveCursorTo(0, 28));
+            print!("{}", "Enter number:".on_yellow().black());
+            let _ = w.act(Action::MoveCursorTo(14, 28));
+            print!("                                 ");
+            let _ = w.act(Action::MoveCursorTo(14, 28));
+            let bytes_read = io::stdin().lock().read_line(&mut line).unwrap_or(0);
+            let _ = w.act(Action::DisableBlinking);
+            let _ = w.act(Action::HideCursor);
+
+            if bytes_read == 0 {
+                return Err(RuntimeErr::TooManyInputs);
+            }
+
+            let line = line.trim();
+            if line == "q" {
+                return Err(RuntimeErr::Halt);
+            }
+
+            let _ = w.act(Action::MoveCursorTo(16, 29));
+            match self.read_mode.parse(line) {
+                Some(number) => {
+                    print!("                                        ");
+                    return Ok(number);
+                }
+                None => print!("Invalid number! Please try again..."),
+            }
+        }
+    }
+
+    fn write(&mut self, value: i16) {
+        let _ = terminal::stdout().act(Action::MoveCursorTo(50, 8));
+        print!("{:<10}", self.write_mode.format(value));
+    }
+}
+
+/// Function to print the current state of the simulator
+/// (registers, memory, etc.) to the screen without flickering
+/// (i.e. no flicker when the screen is updated)
+pub fn print_debug_screen(sim: &Simulator) -> terminal::error::Result<()> {
+    let mut debug_screen_lines: Vec<String> = Vec::new();
+
+    debug_screen_lines.push(format!(
+        "{}{}{}",
+        "█▀▀▀▀▀▀▀▀▀▀█",
+        "  REGISTER CONTENTS  ".bold().on_blue(),
+        "█▀▀▀▀▀▀▀▀▀▀█\n",
+    ));
+
+    for row in 0..4 {
+        debug_screen_lines.push(format!(
+            "█    R{: <2}   █    R{: <2}   █    R{: <2}   █    R{: <2}   █\n",
+            row * 4,
+            (row * 4) + 1,
+            (row * 4) + 2,
+            (row * 4) + 3
+        ));
+
+        debug_screen_lines.push(format!(
+            "█ {:8} █ {:8} █ {:8} █ {:8} █\n",
+            &sim.get_register(row * 4).unwrap_or(0),
+            &sim.get_register((row * 4) + 1).unwrap_or(0),
+            &sim.get_register((row * 4) + 2).unwrap_or(0),
+            &sim.get_register((row * 4) + 3).unwrap_or(0),
+        ));
+    }
+    debug_screen_lines.push("█▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄\n".to_string());
+    debug_screen_lines.push("█    █   0  █   1  █   2  █   3  █   4  █   5  █   6  █   7  █   8  █   9  █   A  █   B  █   C  █   D  █   E  █   F  █\n".to_string());
+    let address_chars = vec![
+        '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'A', 'B', 'C', 'D', 'E', 'F',
+    ];
+
+    let current_pc = &sim.get_program_counter();
+
+    for (i, address_rows) in address_chars.iter().enumerate() {
+        let mut to_print = format!("█  {} █", address_rows);
+
+        for (j, _address_columns) in address_chars.iter().enumerate() {
+            let memory_index = (i * 16) + j;
+
+            // Memory may be smaller than the 256 words shown,
+            // so show anything past the end as blank data
+            let current_instruction = sim
+                .get_memory(memory_index)
+                .unwrap_or_else(Instruction::new_blank_data);
+
+            let instruction_text;
+            if current_pc == &memory_index {
+                instruction_text = current_instruction.as_hex().on_green();
+            } else if current_instruction.instruction_type.names[0] == "data" {
+                if current_instruction.to_u16() == 0 {
+                    instruction_text = current_instruction.as_hex().on_black();
+                } else {
+                    instruction_text = current_instruction.as_hex().on_yellow().black();
+                }
+            } else {
+                instruction_text = current_instruction.as_hex().on_purple();
+            }
+
+            to_print = format!("{} {} █", to_print, instruction_text);
+        }
+
+        debug_screen_lines.push(format!("{}\n", to_print));
+    }
+
+    debug_screen_lines.push("▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀\n".to_string());
+
+    // Create terminal object to print out the debug screen
+    let mut w = terminal::stdout();
+    // Make sure the cursor is at the top of the screen
+    w.act(Action::MoveCursorTo(0, 0))?;
+    // Print line by line to avoid having to strobe the screen
+    for line in debug_screen_lines {
+        print!("{}", line);
+    }
+
+    // Line by line printing done, now to print out program counter,
+    // IR, human-readable output, and HMMM output.
+
+    // Print program counter
+    w.act(Action::MoveCursorTo(50, 1)).unwrap();
+    let to_print = format!("{}", " PROGRAM COUNTER: ".on_red().white().bold());
+    print!("{}", to_print);
+    w.act(Action::MoveCursorTo(50, 2)).unwrap();
+    let to_print = format!("{:<10}", sim.get_program_counter());
+    print!("{}", to_print);
+
+    // Print IR
+    w.act(Action::MoveCursorTo(50, 4)).unwrap();
+    let to_print = format!("{}", " INSTRUCTION REGISTER: ".on_red().white().bold());
+    print!("{}", to_print);
+    let memory_ir = sim.get_memory(sim.get_program_counter());
+
+    if memory_ir.is_some() {
+        let memory_ir = memory_ir.unwrap();
+        w.act(Action::MoveCursorTo(50, 5)).unwrap();
+        let to_print = format!("{:<15}", memory_ir.to_string());
+        print!("{}", to_print);
+
+        // Print human-readable output
+        w.act(Action::MoveCursorTo(75, 1)).unwrap();
+        let to_print = format!("{}", " HUMAN-READABLE CODE: ".on_green().white().bold());
+        print!("{}", to_print);
+
+        w.act(Action::MoveCursorTo(75, 2)).unwrap();
+
+        let mut to_print = String::from(memory_ir.instruction_type.human_explanation);
+
+        for argument in memory_ir.arguments() {
+            to_print = to_print.replacen("_", argument.trim_start_matches('r'), 1);
+        }
+        if to_print.len() > 45 {
+            print!(
+                "{:<45}",
+                to_print.drain(..40).collect::<String>().trim().bold()
+            );
+            w.act(Action::MoveCursorTo(75, 3)).unwrap();
+            print!("{:<45}", to_print.trim().bold());
+        } else {
+            print!("{:<45}", to_print.bold());
+            w.act(Action::MoveCursorTo(75, 3)).unwrap();
+            print!("{:<45}", "");
+        }
+    }
+
+    // Print HMMM output
+    w.act(Action::MoveCursorTo(50, 7)).unwrap();
+    let to_print = format!("{}", " HMMM OUT: ".on_green().white().bold());
+    print!("{}", to_print);
+
+    w.flush()?;
+
+    Ok(())
+}
+
+lazy_static::lazy_static! {
+    /// Shown by --version, built once as clap needs it to outlive the App
+    static ref VERSION: String = version_string();
+}
+
+/// Function to build the command line definition, shared
+/// by the argument parser and the completion generator
+pub fn build_cli() -> App<'static, 'static> {
+    App::new("HMMM Compiler")
+        .version(VERSION.as_str())
+        .author("Ethan Vazquez <edv121@outlook.com>")
+        .about("A compiler, decompiler, debugger, and simulator for Harvey Mudd Miniature Machine (HMMM)")
+        .arg(Arg::with_name("input")
+                 .short("i")
+                 .long("input")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .help("Input .hmmm, .hb, or .b64 file, or - to read .hmmm source from stdin. Can be given more than once to link several .hmmm files together, in order"))
+        .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .takes_value(true)
+                 .help("Output location of either .hmmm or .hb file, or - to write to stdout"))
+        .arg(Arg::with_name("format")
+                 .short("f")
+                 .long("format")
+                 .takes_value(true)
+                 .possible_values(&["hmmm", "hb", "carray", "verilog", "ihex", "srec", "raw", "base64", "csv",
+                                    "md", "mif", "coe"])
+                 .help("Output format to use instead of inferring it from the output extension"))
+        .arg(Arg::with_name("endian")
+                 .long("endian")
+                 .takes_value(true)
+                 .possible_values(&["big", "little"])
+                 .default_value("big")
+                 .help("Byte order of each word when using --format raw, ihex, srec, or carray"))
+        .arg(Arg::with_name("module-name")
+                 .long("module-name")
+                 .takes_value(true)
+                 .help("Name of the generated module when using --format verilog (default: hmmm_rom)"))
+        .arg(Arg::with_name("hb-header")
+                 .long("hb-header")
+                 .takes_value(false)
+                 .help("Start .hb output with # comments recording the compiler version, source file, and time"))
+        .arg(Arg::with_name("emit-listing")
+                 .long("emit-listing")
+                 .takes_value(true)
+                 .help("Write a listing (.lst) file of addresses, binary, source, and warnings to the given path"))
+        .arg(Arg::with_name("break-on-self-modify")
+                 .long("break-on-self-modify")
+                 .takes_value(false)
+                 .help("Stop with an error when the program writes into its own code, instead of warning once"))
+        .arg(Arg::with_name("emit-cfg")
+                 .long("emit-cfg")
+                 .takes_value(true)
+                 .help("Write the program's control-flow graph of basic blocks as a GraphViz DOT file to the given path"))
+        .arg(Arg::with_name("emit-callgraph")
+                 .long("emit-callgraph")
+                 .takes_value(true)
+                 .help("Write the program's call graph of subroutines as a GraphViz DOT file to the given path"))
+        .arg(Arg::with_name("debug")
+                 .short("d")
+                 .long("debug")
+                 .takes_value(false)
+                 .help("Use debug mode for stepping through simulator. When not writing to a terminal, prints a table of each step and the registers instead"))
+        .arg(Arg::with_name("no-run")
+                 .short("n")
+                 .long("no-run")
+                 .takes_value(false)
+                 .help("Do not simulate (run) the program on compilation"))
+        .arg(Arg::with_name("check")
+                 .long("check")
+                 .takes_value(false)
+                 .help("Only compile, printing every error and warning, without running or writing any output. Exits with 1 on any error"))
+        .arg(Arg::with_name("message-format")
+                 .long("message-format")
+                 .takes_value(true)
+                 .possible_values(&["human", "json"])
+                 .default_value("human")
+                 .help("How --check prints errors and warnings, json prints one object per line (with file, line, column, severity, and message) and implies --check"))
+        .arg(Arg::with_name("completions")
+                 .long("completions")
+                 .takes_value(true)
+                 .possible_values(&["bash", "zsh", "fish"])
+                 .help("Print a completion script for the given shell, then exit"))
+        .arg(Arg::with_name("color")
+                 .long("color")
+                 .takes_value(true)
+                 .possible_values(&["auto", "always", "never"])
+                 .default_value("auto")
+                 .help("When to color the output, auto respects NO_COLOR and only colors a terminal"))
+        .arg(Arg::with_name("set-reg")
+                 .long("set-reg")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .help("Set a register before running (eg: r3=42). Can be given more than once"))
+        .arg(Arg::with_name("set-mem")
+                 .long("set-mem")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .help("Set a memory address before running (eg: 10=7). Can be given more than once"))
+        .arg(Arg::with_name("data-file")
+                 .long("data-file")
+                 .takes_value(true)
+                 .help("Load memory from a file before running. Each line is an address followed by one or more values for it and the addresses after it"))
+        .arg(Arg::with_name("cycle-costs")
+                 .long("cycle-costs")
+                 .takes_value(true)
+                 .help("Load how many cycles instructions take from a file. Each line is an instruction name followed by its cycles (eg: mul 10)"))
+        .arg(Arg::with_name("memory-size")
+                 .long("memory-size")
+                 .takes_value(true)
+                 .help("Number of words of memory, from 1 to 256 (the default). Reading, writing, or jumping past it is an error"))
+        .arg(Arg::with_name("stack-pointer")
+                 .long("stack-pointer")
+                 .takes_value(true)
+                 .help("Start this register (eg: r15) at the first address after the program, for pushr and popr. The stack grows upwards, towards the end of memory"))
+        .arg(Arg::with_name("strict")
+                 .long("strict")
+                 .takes_value(false)
+                 .help("Stop with an error when the program reads memory that was never written, runs into data, or jumps past its end, instead of warning"))
+        .arg(Arg::with_name("checksum")
+                 .long("checksum")
+                 .takes_value(false)
+                 .help("Print a CRC-32 checksum of the compiled program, to check two builds are identical"))
+        .arg(Arg::with_name("verbose")
+                 .short("v")
+                 .long("verbose")
+                 .takes_value(false)
+                 .help("Show every compiled line in the compile summary instead of just the first 10, and every step in the debug table"))
+        .arg(Arg::with_name("quiet")
+                 .short("q")
+                 .long("quiet")
+                 .takes_value(false)
+                 .help("Print nothing but the program's own output, with any errors going to stderr"))
+        .arg(Arg::with_name("repl")
+                 .long("repl")
+                 .takes_value(false)
+                 .help("Type instructions one at a time and see their binary straight away, without an input file"))
+        .arg(Arg::with_name("watch")
+                 .short("w")
+                 .long("watch")
+                 .takes_value(false)
+                 .help("Recompile (and rerun, unless --no-run is given) whenever an input file changes"))
+        .arg(Arg::with_name("run-steps")
+                 .long("run-steps")
+                 .takes_value(true)
+                 .help("Run at most N instructions, then print the registers and memory instead of waiting for a halt"))
+        .arg(Arg::with_name("max-steps")
+                 .long("max-steps")
+                 .takes_value(true)
+                 .help("Stop with MaximumIterationsReached after this many instructions, so a program that never halts cannot hang (default: 10000000, or 0 for no limit)"))
+        .arg(Arg::with_name("exit-register")
+                 .long("exit-register")
+                 .takes_value(true)
+                 .help("Exit with the value of this register (eg: r1) when the program halts, clamped into 0..=255"))
+        .arg(Arg::with_name("hex-input")
+                 .long("hex-input")
+                 .takes_value(false)
+                 .help("Read numbers as hex words, with or without 0x (eg: 0x1F or 1F for 31)"))
+        .arg(Arg::with_name("ascii-output")
+                 .long("ascii-output")
+                 .takes_value(false)
+                 .conflicts_with("unsigned-output")
+                 .help("Print the numbers from write as ASCII characters (eg: 65 prints as A)"))
+        .arg(Arg::with_name("unsigned-output")
+                 .long("unsigned-output")
+                 .takes_value(false)
+                 .help("Print the numbers from write as unsigned (eg: -1 prints as 65535)"))
+        .arg(Arg::with_name("save-state")
+                 .long("save-state")
+                 .takes_value(true)
+                 .help("Save the simulator state as JSON to the given path once the program halts or --run-steps ends"))
+        .arg(Arg::with_name("load-state")
+                 .long("load-state")
+                 .takes_value(true)
+                 .help("Load a simulator state saved with --save-state before running"))
+        .arg(Arg::with_name("trace-csv")
+                 .long("trace-csv")
+                 .takes_value(true)
+                 .help("Write each step of the run (step, pc, mnemonic, args, and any register changed with its old and new value) as CSV to the given path"))
+        .arg(Arg::with_name("heatmap")
+                 .long("heatmap")
+                 .takes_value(true)
+                 .help("Write how many times the run read and wrote each memory address as CSV to the given path"))
+        .arg(Arg::with_name("gdb-stub")
+                 .long("gdb-stub")
+                 .takes_value(true)
+                 .help("Wait for a GDB client on this port (eg: target remote :1234) and let it read registers and memory, step, continue, and set breakpoints"))
+        .arg(Arg::with_name("diff")
+                 .long("diff")
+                 .takes_value(true)
+                 .help("Run this program and another .hmmm or .hb file side by side, reporting the first step where they differ"))
+        .arg(Arg::with_name("diff-inputs")
+                 .long("diff-inputs")
+                 .takes_value(true)
+                 .requires("diff")
+                 .help("Comma-separated numbers given to read in both runs of --diff (eg: \"16, 2\")"))
+        .arg(Arg::with_name("diff-other-inputs")
+                 .long("diff-other-inputs")
+                 .takes_value(true)
+                 .requires("diff")
+                 .help("Comma-separated numbers given to read in the second run of --diff instead of --diff-inputs"))
+        .arg(Arg::with_name("speed")
+                 .short("s")
+                 .long("speed")
+                 .takes_value(true)
+                 .help("Sets the multiplier (speed) of debug mode (eg: .5 is half speed, 2 is double)"))
+        .arg(Arg::with_name("asm")
+                 .long("asm")
+                 .takes_value(true)
+                 .help("Print the binary encoding of a single instruction (eg: \"add r1 r2 r3\"), then exit"))
+        .arg(Arg::with_name("explain")
+                 .long("explain")
+                 .takes_value(true)
+                 .help("Print how an instruction is written, what it does, and its binary encoding (eg: add), then exit"))
+        .arg(Arg::with_name("disasm")
+                 .long("disasm")
+                 .takes_value(true)
+                 .help("Print the instruction a 16-bit binary word decodes to (eg: \"0110 0001 0010 0011\"), then exit"))
+        .arg(Arg::with_name("autograder")
+                 .short("a")
+                 .long("autograder")
+                 .takes_value(true)
+                 .help("Toggles the AutoGrader functionality, expecting a test string to be given. If enabled, expects a directory path instead of a file path for --input and --output. --debug, --no-run, and --speed are ignored in this mode."))
+        .subcommand(SubCommand::with_name("lsp")
+                 .about("Run a language server over stdio, for editors to show errors and describe instructions"))
+        .subcommand(SubCommand::with_name("dap")
+                 .about("Run a debug adapter over stdio, for editors to set breakpoints, step, and show registers and memory"))
+        .subcommand(SubCommand::with_name("serve")
+                 .about("Run an HTTP server with POST /compile and POST /run endpoints taking and returning JSON")
+                 .arg(Arg::with_name("port")
+                          .long("port")
+                          .takes_value(true)
+                          .default_value("8080")
+                          .help("Port to listen on")))
+}
+
+/// Function to generate a completion script for bash, zsh,
+/// or fish, completing every flag of the command line tool
+pub fn completion_script(shell: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let shell: Shell = shell.parse()?;
+    let mut script: Vec<u8> = Vec::new();
+
+    build_cli().gen_completions_to("hmmm", shell, &mut script);
+
+    Ok(String::from_utf8(script)?)
+}
+
+/// Entry point for the command line tool, parsing its
+/// arguments from the process. Returns the exit code
+pub fn main() -> Result<i32, Box<dyn std::error::Error>> {
+    run(std::env::args_os())
+}
+
+/// Runs the command line tool with the given arguments,
+/// the first of which is the name of the program, returning
+/// the exit code (see --exit-register)
+pub fn run<I, T>(args: I) -> Result<i32, Box<dyn std::error::Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    // Create the terminal object just to have an easy way
+    // to clear it
+    let terminal = terminal::stdout();
+
+    // Setup command line matches
+    let matches = build_cli().get_matches_from(args);
+
+    set_color_choice(match matches.value_of("color") {
+        Some("always") => ColorChoice::Always,
+        Some("never") => ColorChoice::Never,
+        _ => ColorChoice::Auto,
+    });
+
+    if let Some(shell) = matches.value_of("completions") {
+        print!("{}", completion_script(shell)?);
+        return Ok(0);
+    }
+
+    if let Some(name) = matches.value_of("explain") {
+        return match explain_instruction(name) {
+            Ok(explanation) => {
+                print!("{}", explanation);
+                Ok(0)
+            }
+            Err(message) => Err(message.into()),
+        };
+    }
+
+    let lookup = if let Some(text) = matches.value_of("asm") {
+        Some(assemble_instruction(text))
+    } else {
+        matches.value_of("disasm").map(disassemble_instruction)
+    };
+
+    if let Some(lookup) = lookup {
+        return match lookup {
+            Ok(result) => {
+                println!("{}", result);
+                Ok(0)
+            }
+            Err(error) => {
+                println!("{} {}", " ERROR: ".on_red().white().bold(), error);
+                Err(error.into())
+            }
+        };
+    }
+
+    if matches.subcommand_matches("lsp").is_some() {
+        run_lsp(io::stdin().lock(), &mut io::stdout())?;
+        return Ok(0);
+    }
+
+    if matches.subcommand_matches("dap").is_some() {
+        run_dap(io::stdin().lock(), &mut io::stdout())?;
+        return Ok(0);
+    }
+
+    if let Some(serve_matches) = matches.subcommand_matches("serve") {
+        let port = serve_matches.value_of("port").unwrap().parse::<u16>()?;
+        let listener = net::TcpListener::bind(("127.0.0.1", port))?;
+        println!("Listening on http://127.0.0.1:{}", port);
+        run_server(&listener)?;
+        return Ok(0);
+    }
+
+    if matches.is_present("repl") {
+        println!("Type instructions without line numbers, or run [inputs], list, clear, quit");
+        run_repl(io::stdin().lock(), &mut io::stdout())?;
+        return Ok(0);
+    }
+
+    if matches.value_of("input").is_none() {
+        Err("Please specify a file to compile/run!".into())
+    } else {
+        let to_stdout = matches.value_of("output") == Some(STDIO_PATH);
+
+        let check =
+            matches.is_present("check") || matches.value_of("message-format") == Some("json");
+        let quiet = matches.is_present("quiet");
+
+        // Keep stdout clean if the output is being written to it,
+        // or only the problems found are wanted
+        if !to_stdout && !check && !quiet {
+            terminal.act(Action::ClearTerminal(Clear::All))?;
+            print_startup_banner();
+        }
+
+        let input_paths: Vec<&str> = matches
+            .values_of("input")
+            .unwrap()
+            .map(|path| path.trim_start_matches(".\\"))
+            .collect();
+        let file_path: &str = input_paths[0];
+
+        if matches.value_of("autograder").is_some() {
+            println!("{}\n", "AutoGrader Mode Enabled".bold().on_green());
+            let path = file_path.trim_matches(&['\\', '/'] as &[_]);
+            let mut autograder = AutoGrader::new_from_cmd(path, matches.value_of("autograder").unwrap());
+            autograder.grade_all();
+            autograder.print_results();
+            let export_result = autograder.export_results(path);
+
+            if export_result.is_err() {
+                println!("\n{}\n", "AutoGrader Export Failed".bold().on_red());
+            } else {
+                println!("\n{} {}\n", "AutoGrader Export Successful:".bold().on_green(), export_result.unwrap().bold());
+            }
+            return Ok(0);
+        }
+
+        if !matches.is_present("watch") {
+            return compile_and_run(&matches, &input_paths);
+        }
+
+        if input_paths.contains(&STDIO_PATH) {
+            return Err("Cannot watch stdin for changes!".into());
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::watcher(sender, time::Duration::from_millis(WATCH_DEBOUNCE_MS))?;
+
+        loop {
+            // Editors often replace the file on save, so watch
+            // the paths again each time around
+            for path in input_paths.iter() {
+                let _ = watcher.unwatch(path);
+                watcher.watch(path, RecursiveMode::NonRecursive)?;
+            }
+
+            // Keep watching on errors, HMMM ones have
+            // already been pretty-printed
+            if let Err(error) = compile_and_run(&matches, &input_paths) {
+                if hmmm_exit_code(error.as_ref()).is_none() {
+                    println!("{} {}", " ERROR: ".on_red().white().bold(), error);
+                }
+            }
+
+            println!(
+                "\n{}",
+                "Watching for changes, press Ctrl+C to exit...".black().on_yellow()
+            );
+
+            wait_for_change(&receiver)?;
+
+            if !to_stdout && !check && !quiet {
+                terminal.act(Action::ClearTerminal(Clear::All))?;
+                print_startup_banner();
+            }
+        }
+    }
+}
+
+/// Blocks until one of the watched files has changed. The watcher
+/// debounces events, so a burst of saves only triggers one recompile
+fn wait_for_change(
+    receiver: &mpsc::Receiver<DebouncedEvent>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        match receiver.recv()? {
+            DebouncedEvent::Write(_) | DebouncedEvent::Create(_) | DebouncedEvent::Rename(_, _) => {
+                return Ok(());
+            }
+            DebouncedEvent::Error(error, _) => return Err(Box::new(error)),
+            _ => {}
+        }
+    }
+}
+
+/// Compiles the input files, writes any requested outputs, and
+/// then runs the program unless --no-run was given
+fn compile_and_run(
+    matches: &ArgMatches,
+    input_paths: &[&str],
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let terminal = terminal::stdout();
+    let to_stdout = matches.value_of("output") == Some(STDIO_PATH);
+    let exit_register = match matches.value_of("exit-register") {
+        Some(name) => Some(
+            name.trim_start_matches(|c| c == 'r' || c == 'R')
+                .parse::<usize>()
+                .ok()
+                .filter(|register| *register <= 15)
+                .ok_or("--exit-register must be one of r0..r15")?,
+        ),
+        None => None,
+    };
+
+    // JSON diagnostics are for editors, so nothing else is printed
+    if matches.is_present("check") || matches.value_of("message-format") == Some("json") {
+        let message_format = match matches.value_of("message-format") {
+            Some("json") => MessageFormat::Json,
+            _ => MessageFormat::Human,
+        };
+
+        return check_program(input_paths, message_format);
+    }
+
+    let quiet = matches.is_present("quiet");
+
+    // Only a listing needs the source, so otherwise it is not kept
+    let program = if matches.is_present("emit-listing") {
+        load_program(input_paths, quiet)
+    } else {
+        load_program_streaming(input_paths, quiet).map(|compiled_text| (compiled_text, Vec::new()))
+    };
+
+    // Quiet compiles are headless, so report HMMM errors here
+    let (compiled_text, source_text) = match program {
+        Ok(program) => program,
+        Err(error) => {
+            if quiet && hmmm_exit_code(error.as_ref()).is_some() {
+                eprintln!("error: {}", error);
+            }
+            return Err(error);
+        }
+    };
+
+    // Code placed by .org can reach past a smaller --memory-size,
+    // which is a compile error rather than a failure to start
+    let memory_size = match matches.value_of("memory-size") {
+        Some(size) => parse_memory_size(size)?,
+        None => MEMORY_WORDS,
+    };
+    if compiled_text.len() > memory_size {
+        let error = CompileErr::ProgramTooLarge;
+        eprintln!(
+            "error: {}, it needs {} words but --memory-size is {}",
+            error,
+            compiled_text.len(),
+            memory_size
+        );
+        return Err(Box::new(error));
+    }
+
+    // Jumps past the end of the program run into data, so --strict
+    // stops them. Loads and stores there are usually deliberate
+    for (address, target) in out_of_range_addresses(&compiled_text) {
+        let instruction = &compiled_text[address];
+        let message = format!(
+            "line {}: {}",
+            address,
+            out_of_range_message(instruction, target, compiled_text.len())
+        );
+
+        if matches.is_present("strict") && jump_target(instruction).is_some() {
+            eprintln!("error: {}", message);
+            return Err(Box::new(CompileErr::AddressOutOfRange));
+        } else if !quiet && !to_stdout {
+            println!("{} {}", " WARNING: ".on_yellow().black(), message);
+        }
+    }
+
+    if !has_halt(&compiled_text) && !quiet && !to_stdout {
+        println!("{} {}", " WARNING: ".on_yellow().black(), MISSING_HALT_MESSAGE);
+    }
+    for start in infinite_loops(&compiled_text) {
+        if !quiet && !to_stdout {
+            println!("{} {}", " WARNING: ".on_yellow().black(), infinite_loop_message(start));
+        }
+    }
+
+    // If compiles without error, print out a success
+    // message and the program, truncated unless verbose
+    if !to_stdout {
+        print!(
+            "{}",
+            format_compile_report(&compiled_text, matches.is_present("verbose"), quiet)
+        );
+    }
+
+    if matches.is_present("checksum") {
+        let checksum = format!("CRC-32: {:08X}", program_hash(&compiled_text));
+
+        // Keep stdout clean for the program itself when writing to it
+        if to_stdout {
+            eprintln!("{}", checksum);
+        } else {
+            println!("{}", checksum);
+        }
+    }
+
+    // Output listing if given path
+    if matches.value_of("emit-listing").is_some() {
+        let listing_file = matches.value_of("emit-listing").unwrap();
+
+        if write_listing(listing_file, &compiled_text, &source_text).is_err() {
+            println!("Error writing listing file! Continuing...");
+        }
+    }
+
+    // Output control-flow graph if given path
+    if let Some(cfg_file) = matches.value_of("emit-cfg") {
+        if write_cfg_dot(cfg_file, &compiled_text).is_err() {
+            println!("Error writing control-flow graph! Continuing...");
+        }
+    }
+
+    // Output call graph if given path
+    if let Some(callgraph_file) = matches.value_of("emit-callgraph") {
+        if write_callgraph_dot(callgraph_file, &compiled_text).is_err() {
+            println!("Error writing call graph! Continuing...");
+        }
+    }
+
+    // Output file if given path
+    if matches.value_of("output").is_some() {
+        let output_file = matches.value_of("output").unwrap();
+        let output_format = matches.value_of("format").unwrap_or("");
+        // Compressed outputs are written as whatever they contain
+        let output_type = without_gzip(output_file);
+        let module_name = matches
+            .value_of("module-name")
+            .unwrap_or(DEFAULT_VERILOG_MODULE);
+        let endian = match matches.value_of("endian") {
+            Some("little") => Endian::Little,
+            _ => Endian::Big,
+        };
+        let registry = FormatRegistry::builtin()
+            .with_format(VerilogFormat {
+                module_name: module_name.to_string(),
+            })
+            .with_format(CArrayFormat { endian })
+            .with_format(IntelHexFormat { endian })
+            .with_format(SRecordFormat { endian });
+        // An explicit --format wins over the extension
+        let format = registry
+            .by_name(output_format)
+            .or_else(|| registry.by_extension(output_type))
+            .or_else(|| registry.by_name("hb").filter(|_| output_file == STDIO_PATH));
+        let result;
+
+        if output_format == "raw" {
+            result = write_raw_bytes(output_file, &compiled_text, endian);
+        } else if let Some(format) = format {
+            if format.name() == "hb" && matches.is_present("hb-header") {
+                let source_name = input_paths.join(", ");
+                result = write_compiled_hmmm_with_header(output_file, &compiled_text, &source_name);
+            } else {
+                result = write_file(output_file, format.emit(&compiled_text).as_str());
+            }
+        } else {
+            if !quiet {
+                println!("No output type specified, writing as binary...");
+            }
+            // If no ending, just tack on a .hb extension and write out as binary
+            result = write_compiled_hmmm(
+                format!("{}.hb", output_file).as_str(),
+                compiled_text.clone(),
+            );
+        }
+
+        if result.is_err() {
+            println!("Error writing output file! Continuing...");
+        }
+    }
+
+    // Compare against another program instead of running normally
+    if let Some(other_path) = matches.value_of("diff") {
+        let other_text = load_program_streaming(&[other_path], false)?;
+        let parse_inputs = |inputs: Option<&str>| match inputs {
+            Some(inputs) => inputs
+                .split(',')
+                .map(|input| input.trim().parse::<i16>())
+                .collect::<Result<Vec<i16>, _>>(),
+            None => Ok(Vec::new()),
+        };
+        let inputs = parse_inputs(matches.value_of("diff-inputs"))?;
+        let other_inputs = match matches.value_of("diff-other-inputs") {
+            Some(other_inputs) => parse_inputs(Some(other_inputs))?,
+            None => inputs.clone(),
+        };
+
+        let run_traced = |program: Vec<Instruction>,
+                          inputs: Vec<i16>|
+         -> Result<Vec<TraceEntry>, BuildErr> {
+            let mut simulator = Simulator::builder(program)
+                .inputs(inputs)
+                .output_sink(OutputSink::Outputs)
+                .build()?;
+
+            Ok(simulator.run_traced(DIFF_STEP_LIMIT).0)
+        };
+        let left = run_traced(compiled_text, inputs)?;
+        let right = run_traced(other_text, other_inputs)?;
+
+        return match first_divergence(&left, &right) {
+            Some(divergence) => {
+                print!("{}", format_divergence(&divergence));
+                Ok(1)
+            }
+            None => {
+                println!("Runs are identical");
+                Ok(0)
+            }
+        };
+    }
+
+    // Run simulation if --no-run flag is not present
+    if !matches.is_present("no-run") {
+        // Create it as new struct from compiled HMMM
+        let write_mode = if matches.is_present("unsigned-output") {
+            WriteMode::Unsigned
+        } else if matches.is_present("ascii-output") {
+            WriteMode::Ascii
+        } else {
+            WriteMode::Signed
+        };
+        let read_mode = if matches.is_present("hex-input") {
+            ReadMode::Hex
+        } else {
+            ReadMode::Decimal
+        };
+        let mut builder = match matches.value_of("max-steps") {
+            Some("0") => Simulator::builder(compiled_text).no_step_limit(),
+            Some(max_steps) => Simulator::builder(compiled_text).step_limit(max_steps.parse()?),
+            None => Simulator::builder(compiled_text),
+        };
+        builder = builder
+            .memory_size(memory_size)
+            .read_mode(read_mode)
+            .write_mode(write_mode)
+            .break_on_self_modify(matches.is_present("break-on-self-modify"))
+            .strict(matches.is_present("strict"));
+
+        for preset in matches.values_of("set-reg").into_iter().flatten() {
+            let (register, value) = parse_register_preset(preset)?;
+            builder = builder.preset_register(register, value);
+        }
+        for preset in matches.values_of("set-mem").into_iter().flatten() {
+            let (address, value) = parse_memory_preset(preset)?;
+            builder = builder.preset_memory(address, value);
+        }
+        if let Some(name) = matches.value_of("stack-pointer") {
+            let register = parse_writable_register(name)
+                .ok_or("--stack-pointer must be one of r1..r15")?;
+            builder = builder.stack_pointer(register);
+        }
+        if let Some(data_file) = matches.value_of("data-file") {
+            for (address, value) in parse_data_file(&fs::read_to_string(data_file)?)? {
+                builder = builder.preset_memory(address, value);
+            }
+        }
+        if let Some(cost_file) = matches.value_of("cycle-costs") {
+            for (name, cycles) in parse_cycle_costs(&fs::read_to_string(cost_file)?)? {
+                builder = builder.cycle_cost(name, cycles);
+            }
+        }
+
+        let mut simulator = builder.build()?;
+
+        // Headless simulators keep their warnings to themselves
+        if quiet {
+            simulator.headless = true;
+        }
+
+        if let Some(state_file) = matches.value_of("load-state") {
+            simulator.load_state(state_file)?;
+        }
+
+        // Let a GDB client drive the program instead of running it here
+        if let Some(port) = matches.value_of("gdb-stub") {
+            let listener = net::TcpListener::bind(("127.0.0.1", port.parse::<u16>()?))?;
+            println!("Waiting for GDB to connect on port {}...", port);
+
+            let (mut stream, _) = listener.accept()?;
+            run_gdb_stub(&mut stream, &mut simulator)?;
+
+            return Ok(0);
+        }
+        let debug_multiplier = matches
+            .value_of("speed")
+            .unwrap_or("1")
+            .parse::<f64>()
+            .unwrap_or(1.0);
+
+        // Run a fixed number of steps, then show where the program got to
+        if let Some(run_steps) = matches.value_of("run-steps") {
+            match simulator.run_steps(run_steps.parse::<u64>()?) {
+                Ok(()) | Err(RuntimeErr::Halt) => {
+                    print!("{}", format_state_dump(&simulator));
+
+                    if let Some(state_file) = matches.value_of("save-state") {
+                        simulator.save_state(state_file)?;
+                    }
+                    return Ok(halt_exit_code(&simulator, exit_register));
+                }
+                Err(error) => {
+                    if quiet {
+                        eprintln!("error: {}", error);
+                    } else {
+                        raise_runtime_error(&simulator, &error);
+                    }
+                    return Err(Box::new(error));
+                }
+            }
+        }
+
+        // Without a terminal to draw on, debug mode logs a table of
+        // steps instead, cut short on long runs unless verbose
+        let step_table = matches.is_present("debug") && !io::stdout().is_terminal();
+        let step_table_limit = if matches.is_present("verbose") {
+            u64::MAX
+        } else {
+            DEBUG_TABLE_ROW_LIMIT
+        };
+
+        // Each step run, with the instruction it ran, for --trace-csv
+        let trace_csv = matches.value_of("trace-csv");
+        let mut trace: Vec<(Instruction, StepResult)> = Vec::new();
+
+        if step_table {
+            print!("{}", format_step_table_header());
+        } else if matches.is_present("debug") {
+            println!("{}", "ENTERING DEBUGGING MODE...".on_red());
+            simulator.set_debug(true);
+            simulator.io_handler = IoHandle::new(DebugScreenHandler {
+                read_mode,
+                write_mode,
+            });
+            thread::sleep(time::Duration::from_millis(
+                (200) as u64,
+            ));
+            terminal.act(Action::ClearTerminal(Clear::All))?;
+            terminal.act(Action::DisableBlinking)?;
+            terminal.act(Action::HideCursor)?;
+        }
+
+        loop {
+            if simulator.is_debug() {
+                print_debug_screen(&mut simulator)?;
+                if debug_multiplier == 0. {
+                    let mut line = String::new();
+                    io::stdin().read_line(&mut line).unwrap();
+
+                    // Start the program over instead of stepping
+                    if line.trim() == "restart" {
+                        simulator.reset();
+                        trace.clear();
+                        continue;
+                    }
+
+                    // Run until the current subroutine returns. If it
+                    // stopped for any other reason, the step below
+                    // runs into the same halt or error to report it
+                    if line.trim() == "finish" {
+                        match simulator.run_to_return() {
+                            StopReason::ReachedPc | StopReason::NotInSubroutine => continue,
+                            _ => {}
+                        }
+                    }
+                } else {
+                    thread::sleep(time::Duration::from_millis(
+                        (500. / debug_multiplier) as u64,
+                    ));
+                }
+            }
+            // Attempt to run a step in the simulator
+            let address = simulator.get_program_counter();
+            let instruction = simulator.get_memory(address);
+            let mut result = simulator.step();
+
+            if let (Some(_), Some(instruction)) = (trace_csv, instruction) {
+                if let Ok(step_result) = &result {
+                    trace.push((instruction, step_result.clone()));
+                }
+            }
+
+            if step_table && simulator.steps_taken <= step_table_limit {
+                if result.is_ok() {
+                    print!("{}", format_step_row(&simulator, simulator.steps_taken, address));
+                }
+                if simulator.steps_taken == step_table_limit {
+                    println!("... later steps are not shown, use --verbose to see them all");
+                }
+            }
+            // Halting is the last step, so finish as if stepping past it
+            if matches!(&result, Ok(step_result) if step_result.halted) {
+                result = Err(RuntimeErr::Halt);
+            }
+            // If it's an error, raise it
+            if result.is_err() {
+                // Don't trap the user without a cursor,
+                // make sure to show it on exit
+                // Hopefully the program doesn't hard crash because if it does,
+                // the cursor might not be visible
+                terminal.act(Action::ShowCursor)?;
+                let result_err = result.as_ref().unwrap_err();
+
+                if let Some(trace_file) = trace_csv {
+                    if write_trace_csv(trace_file, &trace).is_err() {
+                        println!("Error writing trace file! Continuing...");
+                    }
+                }
+                if let Some(heatmap_file) = matches.value_of("heatmap") {
+                    if write_heatmap_csv(heatmap_file, &simulator).is_err() {
+                        println!("Error writing heatmap file! Continuing...");
+                    }
+                }
+                // If the error is Halt, exit quietly, as that is the
+                // program successfully finishing
+                if result_err == &RuntimeErr::Halt {
+                    if simulator.is_debug() {
+                        terminal.act(Action::MoveCursorTo(0, 31))?;
+                    }
+
+                    if !quiet {
+                        println!(
+                            "{}",
+                            "Program has reached end, exiting...".black().on_green()
+                        );
+                        println!(
+                            "Ran {} instructions in {} cycles",
+                            simulator.steps_taken, simulator.cycles_taken
+                        );
+                    }
+
+                    if let Some(state_file) = matches.value_of("save-state") {
+                        simulator.save_state(state_file)?;
+                    }
+
+                    return Ok(halt_exit_code(&simulator, exit_register));
+                } else if quiet {
+                    eprintln!("error: {}", result_err);
+                    return Err(Box::new(result_err.clone()));
+                } else {
+                    // If not, raise that error!
+                    terminal.act(Action::ClearTerminal(Clear::All))?;
+                    // Prints out the debug screen as well as the the error
+                    raise_runtime_error(&simulator, result_err);
+
+                    // Move the terminal prompt to the bottom of the screen
+                    for _ in 0..16 {
+                        println!("\n");
+                    }
+                    return Err(Box::new(result_err.clone()));
+                }
+            }
+        }
+    }
+    Ok(0)
+}
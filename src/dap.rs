@@ -0,0 +1,366 @@
+use super::diagnostics::*;
+use super::load_file;
+use super::lsp::{read_message, write_message};
+use super::simulator::*;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+
+// HMMM programs only have one thread
+const THREAD_ID: i64 = 1;
+// Variable references for the two scopes of every stack frame
+const REGISTERS_REFERENCE: i64 = 1;
+const MEMORY_REFERENCE: i64 = 2;
+
+/// Why a run started by the editor stopped
+enum Stop {
+    /// The step, next, or step out finished
+    Step,
+    Breakpoint,
+    Halted,
+    Error(RuntimeErr),
+}
+
+/// A program being debugged, along with where each of its
+/// instructions came from in the source file
+struct Session {
+    simulator: Simulator,
+    path: String,
+    source_map: Vec<usize>,
+    breakpoints: Vec<usize>,
+    /// Whether to wait for the editor before running the first instruction
+    stop_on_entry: bool,
+    /// Number of outputs already sent to the editor
+    outputs_sent: usize,
+}
+
+/// Function to run a Debug Adapter Protocol server over the given
+/// streams (stdin and stdout for an editor such as VS Code). The
+/// launch request takes the "program" to debug, and the numbers to
+/// give its read instructions as "inputs". It stops on entry
+/// unless "stopOnEntry" is false
+pub fn run_dap<R: BufRead, W: Write>(mut input: R, output: &mut W) -> io::Result<()> {
+    let mut seq = 0;
+    let mut session: Option<Session> = None;
+    // Breakpoint lines set before launch, kept until there is a program
+    let mut pending_lines: Vec<usize> = Vec::new();
+
+    while let Some(request) = read_message(&mut input)? {
+        let command = request["command"].as_str().unwrap_or("").to_string();
+        let arguments = &request["arguments"];
+        let mut events: Vec<(&str, Value)> = Vec::new();
+
+        let body: Result<Value, String> = match command.as_str() {
+            "initialize" => {
+                events.push(("initialized", Value::Null));
+                Ok(json!({ "supportsConfigurationDoneRequest": true }))
+            }
+            "launch" => match launch(arguments, &pending_lines) {
+                Ok(launched) => {
+                    session = Some(launched);
+                    Ok(Value::Null)
+                }
+                Err(message) => Err(message),
+            },
+            "configurationDone" => {
+                if let Some(session) = session.as_mut() {
+                    if session.stop_on_entry {
+                        events.push((
+                            "stopped",
+                            json!({ "reason": "entry", "threadId": THREAD_ID }),
+                        ));
+                    } else {
+                        let stop = run_until(session, |_| false);
+                        stop_events(session, stop, &mut events);
+                    }
+                }
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => {
+                let lines: Vec<usize> = arguments["breakpoints"]
+                    .as_array()
+                    .map(|breakpoints| {
+                        breakpoints
+                            .iter()
+                            .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                            .map(|line| line as usize)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                pending_lines = lines.clone();
+                let breakpoints = match session.as_mut() {
+                    Some(session) => set_breakpoints(session, &lines),
+                    None => lines.iter().map(|line| breakpoint_json(*line, false)).collect(),
+                };
+
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => with_session(&session, |session| {
+                json!({ "stackFrames": stack_frames(session) })
+            }),
+            "scopes" => Ok(json!({
+                "scopes": [
+                    { "name": "Registers", "variablesReference": REGISTERS_REFERENCE },
+                    { "name": "Memory", "variablesReference": MEMORY_REFERENCE },
+                ],
+            })),
+            "variables" => with_session(&session, |session| {
+                let reference = arguments["variablesReference"].as_i64().unwrap_or(0);
+                json!({ "variables": variables(&session.simulator, reference) })
+            }),
+            "next" | "stepIn" | "stepOut" | "continue" => match session.as_mut() {
+                Some(session) => {
+                    let stop = match command.as_str() {
+                        "next" => step_over(session),
+                        "stepIn" => run_until(session, |_| true),
+                        "stepOut" => step_out(session),
+                        _ => run_until(session, |_| false),
+                    };
+                    stop_events(session, stop, &mut events);
+
+                    Ok(json!({ "allThreadsContinued": true }))
+                }
+                None => Err("no program has been launched".to_string()),
+            },
+            "disconnect" => Ok(Value::Null),
+            _ => Err(format!("unknown request {}", command)),
+        };
+
+        seq += 1;
+        let mut response = json!({
+            "seq": seq,
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        write_message(output, &response)?;
+
+        for (event, body) in events {
+            seq += 1;
+            write_message(
+                output,
+                &json!({ "seq": seq, "type": "event", "event": event, "body": body }),
+            )?;
+        }
+
+        if command == "disconnect" {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+/// Function to compile the program named in a launch request and
+/// set up a simulator for it, with any breakpoints already set
+fn launch(arguments: &Value, breakpoint_lines: &[usize]) -> Result<Session, String> {
+    let path = arguments["program"]
+        .as_str()
+        .ok_or("launch needs a \"program\" to debug")?
+        .to_string();
+    let source = load_file(&path).map_err(|error| error.to_string())?;
+    let program = compile_program(&source.join("\n")).map_err(|diagnostics| {
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        messages.join("\n")
+    })?;
+
+    let inputs: Vec<i16> = arguments["inputs"]
+        .as_array()
+        .map(|inputs| {
+            inputs
+                .iter()
+                .filter_map(|input| input.as_i64())
+                .map(|input| input as i16)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // stdin and stdout carry the protocol, so the program cannot use them
+    let mut simulator = Simulator::builder(program.instructions)
+        .inputs(inputs)
+        .output_sink(OutputSink::Outputs)
+        .build()
+        .map_err(|error| error.to_string())?;
+    simulator.headless = true;
+
+    let mut session = Session {
+        simulator,
+        path,
+        source_map: program.source_map,
+        breakpoints: Vec::new(),
+        stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(true),
+        outputs_sent: 0,
+    };
+    set_breakpoints(&mut session, breakpoint_lines);
+
+    Ok(session)
+}
+
+/// Runs the given closure on the session, or fails if no
+/// program has been launched yet
+fn with_session<F: FnOnce(&Session) -> Value>(
+    session: &Option<Session>,
+    body: F,
+) -> Result<Value, String> {
+    session
+        .as_ref()
+        .map(body)
+        .ok_or_else(|| "no program has been launched".to_string())
+}
+
+/// Function to replace the session's breakpoints with the given
+/// source lines, returning the breakpoint for each. Lines without
+/// an instruction cannot be stopped at, so are not verified
+fn set_breakpoints(session: &mut Session, lines: &[usize]) -> Vec<Value> {
+    session.breakpoints.clear();
+
+    lines
+        .iter()
+        .map(|line| {
+            let address = session.source_map.iter().position(|mapped| mapped == line);
+
+            if let Some(address) = address {
+                session.breakpoints.push(address);
+            }
+
+            breakpoint_json(*line, address.is_some())
+        })
+        .collect()
+}
+
+fn breakpoint_json(line: usize, verified: bool) -> Value {
+    json!({ "verified": verified, "line": line })
+}
+
+/// Function to step until done returns true after a step, stopping
+/// early at a breakpoint, a halt, or an error
+fn run_until<F: Fn(&Simulator) -> bool>(session: &mut Session, done: F) -> Stop {
+    loop {
+        match session.simulator.step() {
+            Ok(step_result) if step_result.halted => return Stop::Halted,
+            Ok(_) => {
+                if done(&session.simulator) {
+                    return Stop::Step;
+                }
+                if session
+                    .breakpoints
+                    .contains(&session.simulator.get_program_counter())
+                {
+                    return Stop::Breakpoint;
+                }
+            }
+            Err(RuntimeErr::Halt) => return Stop::Halted,
+            Err(error) => return Stop::Error(error),
+        }
+    }
+}
+
+/// Function to run one instruction, running a whole subroutine
+/// if that instruction calls one
+fn step_over(session: &mut Session) -> Stop {
+    let depth = session.simulator.call_stack.len();
+
+    run_until(session, |simulator| simulator.call_stack.len() <= depth)
+}
+
+/// Function to run until the current subroutine returns, or
+/// just one instruction when not in a subroutine
+fn step_out(session: &mut Session) -> Stop {
+    let depth = session.simulator.call_stack.len();
+
+    run_until(session, |simulator| {
+        depth == 0 || simulator.call_stack.len() < depth
+    })
+}
+
+/// Function to queue the events describing how a run stopped,
+/// after any numbers the program wrote
+fn stop_events(session: &mut Session, stop: Stop, events: &mut Vec<(&str, Value)>) {
+    let outputs = &session.simulator.outputs[session.outputs_sent..];
+
+    for value in outputs {
+        events.push(("output", json!({ "category": "stdout", "output": format!("{}\n", value) })));
+    }
+    session.outputs_sent = session.simulator.outputs.len();
+
+    let stopped = |reason: &str, text: Option<String>| {
+        json!({ "reason": reason, "threadId": THREAD_ID, "text": text })
+    };
+
+    match stop {
+        Stop::Step => events.push(("stopped", stopped("step", None))),
+        Stop::Breakpoint => events.push(("stopped", stopped("breakpoint", None))),
+        Stop::Error(error) => {
+            events.push(("stopped", stopped("exception", Some(error.to_string()))))
+        }
+        Stop::Halted => {
+            events.push(("exited", json!({ "exitCode": 0 })));
+            events.push(("terminated", Value::Null));
+        }
+    }
+}
+
+/// Function to list the stack frames, innermost first: where the
+/// program is now, then each call that has not returned yet
+fn stack_frames(session: &Session) -> Vec<Value> {
+    let simulator = &session.simulator;
+    let mut addresses = vec![simulator.get_program_counter()];
+
+    // Each call is the instruction just before where it returns to
+    addresses.extend(
+        simulator
+            .call_stack
+            .iter()
+            .rev()
+            .map(|frame| frame.return_address.saturating_sub(1)),
+    );
+
+    addresses
+        .iter()
+        .enumerate()
+        .map(|(id, address)| {
+            let name = match simulator.get_memory(*address) {
+                Some(instruction) => format!("{} {}", address, instruction),
+                None => address.to_string(),
+            };
+
+            json!({
+                "id": id,
+                "name": name,
+                "source": { "path": session.path },
+                "line": session.source_map.get(*address).copied().unwrap_or(0),
+                "column": 1,
+            })
+        })
+        .collect()
+}
+
+/// Function to list the registers, or the memory words that are
+/// not zero, for the given scope
+fn variables(simulator: &Simulator, reference: i64) -> Vec<Value> {
+    let variable = |name: String, value: i16| {
+        json!({ "name": name, "value": value.to_string(), "variablesReference": 0 })
+    };
+
+    match reference {
+        REGISTERS_REFERENCE => simulator
+            .registers
+            .iter()
+            .enumerate()
+            .map(|(index, value)| variable(format!("r{}", index), *value))
+            .collect(),
+        MEMORY_REFERENCE => simulator
+            .memory_words()
+            .enumerate()
+            .filter(|(_, value)| *value != 0)
+            .map(|(address, value)| variable(address.to_string(), value))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,254 @@
+use super::analysis::*;
+use super::simulator::*;
+use std::collections::HashMap;
+use std::fmt;
+
+/// How serious a diagnostic is
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    /// Stops the program from compiling
+    Error,
+    /// Compiles, but is probably a mistake
+    Warning,
+}
+
+impl Severity {
+    /// Returns the lowercase name of the severity
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// How diagnostics are printed on the command line
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MessageFormat {
+    /// As "line:column: severity: message"
+    Human,
+    /// As one JSON object per line, for editors to parse
+    Json,
+}
+
+/// A problem found while compiling, pointing at where it is
+/// in the source so an editor can underline it
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    /// Line in the source, counting from 1
+    pub line: usize,
+    /// Column in the line, counting from 1
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Function to format the diagnostic as a single line JSON
+    /// object, pointing into the given file
+    pub fn to_json(&self, file: &str) -> String {
+        serde_json::json!({
+            "file": file,
+            "line": self.line,
+            "column": self.column,
+            "severity": self.severity.as_str(),
+            "message": self.message,
+        })
+        .to_string()
+    }
+
+    fn error(line_index: usize, column: usize, error: CompileErr) -> Diagnostic {
+        Diagnostic {
+            line: line_index + 1,
+            column,
+            severity: Severity::Error,
+            message: error.to_string(),
+        }
+    }
+
+    fn warning(line_index: usize, column: usize, message: String) -> Diagnostic {
+        Diagnostic {
+            line: line_index + 1,
+            column,
+            severity: Severity::Warning,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.line,
+            self.column,
+            self.severity.as_str(),
+            self.message
+        )
+    }
+}
+
+/// A compiled program, along with where each instruction came from
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    /// Source line (counting from 1) of each instruction
+    pub source_map: Vec<usize>,
+    /// Warnings found while compiling, which did not stop it
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Column (counting from 1) of the first non-whitespace
+/// character at or after a byte offset in a line
+fn column_after(line: &str, offset: usize) -> usize {
+    let rest = &line[offset..];
+
+    offset + (rest.len() - rest.trim_start().len()) + 1
+}
+
+/// Column (counting from 1) of the line number, just after any
+/// inline label
+fn number_column(line: &str) -> usize {
+    let (_, instruction) = split_inline_label(line);
+
+    column_after(line, line.len() - instruction.len())
+}
+
+/// Column (counting from 1) of the instruction name in a line,
+/// just after its line number
+fn instruction_column(line: &str) -> usize {
+    let start = number_column(line) - 1;
+    let after_number = line[start..]
+        .find(|c: char| c.is_whitespace() || c == ',')
+        .map_or(line.len(), |end| start + end);
+
+    column_after(line, after_number)
+}
+
+/// Function to compile HMMM source, carrying on past errors so
+/// that every problem in the file is reported at once
+pub fn compile_program(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    compile_program_in(source, &InstructionSet::builtin())
+}
+
+/// Function to compile HMMM source like compile_program, using
+/// the given instruction set
+pub fn compile_program_in(
+    source: &str,
+    instruction_set: &InstructionSet,
+) -> Result<Program, Vec<Diagnostic>> {
+    let raw_lines: Vec<&str> = source.lines().collect();
+    let lines: Vec<String> = raw_lines
+        .iter()
+        .map(|line| line.trim().to_string())
+        .collect();
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    // Find labels first, so they can be used before they are defined
+    let scan = scan_labels(&lines);
+    for (index, err) in scan.errors {
+        let column = column_after(raw_lines[index], 0);
+        diagnostics.push(Diagnostic::error(index, column, err));
+    }
+
+    let placement = place_program(&lines, &scan.labels, instruction_set);
+    for LineError {
+        index,
+        line_parts,
+        err,
+    } in placement.errors
+    {
+        let raw_line = raw_lines[index];
+        let column = match err {
+            _ if is_org_line(raw_line) => column_after(raw_line, 0),
+            CompileErr::LineNumberNotPresent
+            | CompileErr::InvalidLineNumber
+            | CompileErr::ProgramTooLarge => number_column(raw_line),
+            _ => instruction_column(raw_line),
+        };
+        // Name the unknown instruction, and what was probably meant
+        let unknown_name = match err {
+            CompileErr::InstructionDoesNotExist => line_parts.get(1),
+            _ => None,
+        };
+        let mut diagnostic = Diagnostic::error(index, column, err);
+        if let Some(name) = unknown_name {
+            diagnostic.message = instruction_set.unknown_name_message(name);
+        }
+        diagnostics.push(diagnostic);
+    }
+
+    let instructions = placement.instructions;
+    let source_map: Vec<usize> = placement
+        .line_indexes
+        .iter()
+        .map(|index| index + 1)
+        .collect();
+    let used_labels: Vec<String> = lines
+        .iter()
+        .filter(|line| is_instruction_line(line))
+        .flat_map(|line| split_source_line(line, &HashMap::new()).into_iter().skip(2))
+        .map(|part| part.to_lowercase())
+        .filter(|part| scan.labels.contains_key(part))
+        .collect();
+
+    for (name, index) in scan.lines {
+        if !used_labels.contains(&name) {
+            let column = column_after(raw_lines[index], 0);
+            diagnostics.push(Diagnostic::warning(
+                index,
+                column,
+                format!("label \"{}\" is never used", name),
+            ));
+        }
+    }
+
+    for (address, target) in out_of_range_addresses(&instructions) {
+        let index = source_map[address] - 1;
+        let column = instruction_column(raw_lines[index]);
+        let instruction = &instructions[address];
+        diagnostics.push(Diagnostic::warning(
+            index,
+            column,
+            out_of_range_message(instruction, target, instructions.len()),
+        ));
+    }
+
+    let has_errors = diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error);
+
+    // Point at the last instruction, where the program falls off the end.
+    // A program with errors may only be missing its halt because of them
+    let missing_halt = !has_errors && !has_halt(&instructions);
+    if let Some(last_line) = source_map.last().filter(|_| missing_halt) {
+        let column = instruction_column(raw_lines[last_line - 1]);
+        diagnostics.push(Diagnostic::warning(
+            last_line - 1,
+            column,
+            MISSING_HALT_MESSAGE.to_string(),
+        ));
+    }
+
+    for start in infinite_loops(&instructions).into_iter().filter(|_| !has_errors) {
+        let index = source_map[start] - 1;
+        let column = instruction_column(raw_lines[index]);
+        diagnostics.push(Diagnostic::warning(index, column, infinite_loop_message(start)));
+    }
+
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+
+    if diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        Err(diagnostics)
+    } else {
+        Ok(Program {
+            instructions,
+            source_map,
+            warnings: diagnostics,
+        })
+    }
+}
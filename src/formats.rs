@@ -0,0 +1,258 @@
+use super::simulator::*;
+use super::*;
+
+/// A way of writing out a compiled program, chosen by its name
+/// (with --format) or by the extension of the output path
+pub trait OutputFormat {
+    /// Name given to --format to pick this format (eg: "csv")
+    fn name(&self) -> &str;
+    /// Extension of files in this format, including the dot (eg: ".csv")
+    fn extension(&self) -> &str;
+    /// Returns the program written in this format
+    fn emit(&self, program: &[Instruction]) -> String;
+}
+
+/// The output formats that can be picked from, searched in order
+pub struct FormatRegistry {
+    formats: Vec<Box<dyn OutputFormat>>,
+}
+
+impl FormatRegistry {
+    /// Returns a registry with every built-in text format. Raw bytes
+    /// are not text, so are written separately
+    pub fn builtin() -> Self {
+        FormatRegistry { formats: Vec::new() }
+            .with_format(HmmmFormat)
+            .with_format(BinaryFormat)
+            .with_format(Base64Format)
+            .with_format(CsvFormat)
+            .with_format(MarkdownFormat)
+            .with_format(MifFormat)
+            .with_format(CoeFormat)
+            .with_format(CArrayFormat {
+                endian: Endian::Big,
+            })
+            .with_format(VerilogFormat {
+                module_name: DEFAULT_VERILOG_MODULE.to_string(),
+            })
+            .with_format(IntelHexFormat {
+                endian: Endian::Big,
+            })
+            .with_format(SRecordFormat {
+                endian: Endian::Big,
+            })
+    }
+
+    /// Adds a format, replacing any built-in one with the same name
+    pub fn with_format<F: OutputFormat + 'static>(mut self, format: F) -> Self {
+        self.formats.retain(|existing| existing.name() != format.name());
+        self.formats.push(Box::new(format));
+        self
+    }
+
+    /// Finds the format with the given name
+    pub fn by_name(&self, name: &str) -> Option<&dyn OutputFormat> {
+        self.formats
+            .iter()
+            .find(|format| format.name() == name)
+            .map(|format| format.as_ref())
+    }
+
+    /// Finds the format for a path from its extension
+    pub fn by_extension(&self, path: &str) -> Option<&dyn OutputFormat> {
+        self.formats
+            .iter()
+            .find(|format| !format.extension().is_empty() && path.ends_with(format.extension()))
+            .map(|format| format.as_ref())
+    }
+}
+
+/// Uncompiled .hmmm source, one numbered instruction per line
+pub struct HmmmFormat;
+
+impl OutputFormat for HmmmFormat {
+    fn name(&self) -> &str {
+        "hmmm"
+    }
+
+    fn extension(&self) -> &str {
+        UNCOMPILED
+    }
+
+    fn emit(&self, program: &[Instruction]) -> String {
+        as_uncompiled_hmmm(program)
+    }
+}
+
+/// Compiled .hb binary, one word of 0s and 1s per line
+pub struct BinaryFormat;
+
+impl OutputFormat for BinaryFormat {
+    fn name(&self) -> &str {
+        "hb"
+    }
+
+    fn extension(&self) -> &str {
+        COMPILED
+    }
+
+    fn emit(&self, program: &[Instruction]) -> String {
+        as_compiled_hmmm(program)
+    }
+}
+
+pub struct Base64Format;
+
+impl OutputFormat for Base64Format {
+    fn name(&self) -> &str {
+        "base64"
+    }
+
+    fn extension(&self) -> &str {
+        BASE64_COMPILED
+    }
+
+    fn emit(&self, program: &[Instruction]) -> String {
+        as_base64(program)
+    }
+}
+
+pub struct CsvFormat;
+
+impl OutputFormat for CsvFormat {
+    fn name(&self) -> &str {
+        "csv"
+    }
+
+    fn extension(&self) -> &str {
+        CSV
+    }
+
+    fn emit(&self, program: &[Instruction]) -> String {
+        as_csv(program)
+    }
+}
+
+pub struct MarkdownFormat;
+
+impl OutputFormat for MarkdownFormat {
+    fn name(&self) -> &str {
+        "md"
+    }
+
+    fn extension(&self) -> &str {
+        MARKDOWN
+    }
+
+    fn emit(&self, program: &[Instruction]) -> String {
+        as_markdown_table(program)
+    }
+}
+
+pub struct MifFormat;
+
+impl OutputFormat for MifFormat {
+    fn name(&self) -> &str {
+        "mif"
+    }
+
+    fn extension(&self) -> &str {
+        MIF
+    }
+
+    fn emit(&self, program: &[Instruction]) -> String {
+        as_mif(program)
+    }
+}
+
+pub struct CoeFormat;
+
+impl OutputFormat for CoeFormat {
+    fn name(&self) -> &str {
+        "coe"
+    }
+
+    fn extension(&self) -> &str {
+        COE
+    }
+
+    fn emit(&self, program: &[Instruction]) -> String {
+        as_coe(program)
+    }
+}
+
+/// A C array of 16-bit words, with bytes swapped if little-endian
+pub struct CArrayFormat {
+    pub endian: Endian,
+}
+
+impl OutputFormat for CArrayFormat {
+    fn name(&self) -> &str {
+        "carray"
+    }
+
+    fn extension(&self) -> &str {
+        C_ARRAY
+    }
+
+    fn emit(&self, program: &[Instruction]) -> String {
+        as_c_array(program, self.endian)
+    }
+}
+
+/// A Verilog ROM module with the given name
+pub struct VerilogFormat {
+    pub module_name: String,
+}
+
+impl OutputFormat for VerilogFormat {
+    fn name(&self) -> &str {
+        "verilog"
+    }
+
+    fn extension(&self) -> &str {
+        VERILOG
+    }
+
+    fn emit(&self, program: &[Instruction]) -> String {
+        as_verilog_rom(program, &self.module_name)
+    }
+}
+
+/// Intel HEX, which has no extension of its own here
+pub struct IntelHexFormat {
+    pub endian: Endian,
+}
+
+impl OutputFormat for IntelHexFormat {
+    fn name(&self) -> &str {
+        "ihex"
+    }
+
+    fn extension(&self) -> &str {
+        ""
+    }
+
+    fn emit(&self, program: &[Instruction]) -> String {
+        as_intel_hex(program, self.endian)
+    }
+}
+
+/// Motorola S-records, which have no extension of their own here
+pub struct SRecordFormat {
+    pub endian: Endian,
+}
+
+impl OutputFormat for SRecordFormat {
+    fn name(&self) -> &str {
+        "srec"
+    }
+
+    fn extension(&self) -> &str {
+        ""
+    }
+
+    fn emit(&self, program: &[Instruction]) -> String {
+        as_s_records(program, self.endian)
+    }
+}
@@ -0,0 +1,247 @@
+use super::simulator::*;
+use std::io::{self, Read, Write};
+
+// Stop replies, using the signal numbers GDB expects
+static STOPPED: &str = "S05";
+static CRASHED: &str = "S0b";
+static EXITED: &str = "W00";
+// Reply for a packet that could not be carried out
+static ERROR_REPLY: &str = "E01";
+// Most bytes one "m" packet can read, which is all of memory
+const MAX_READ_LENGTH: usize = 2 * MEMORY_WORDS;
+
+/// A request from a GDB client. Addresses are in bytes, with each
+/// 16-bit word of HMMM memory taking two, low byte first, so word
+/// N is at byte address 2 * N
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum GdbCommand {
+    /// "?": why the program stopped
+    HaltReason,
+    /// "g": every register, followed by the program counter
+    ReadRegisters,
+    /// "m addr,length": bytes of memory
+    ReadMemory { address: usize, length: usize },
+    /// "s": run one instruction
+    Step,
+    /// "c": run until a breakpoint, halt, or error
+    Continue,
+    /// "Z0,addr,kind": stop before running the instruction at addr
+    InsertBreakpoint(usize),
+    /// "z0,addr,kind": remove a breakpoint
+    RemoveBreakpoint(usize),
+    /// "D" or "k": the client is leaving
+    Detach,
+    /// Anything else, which gets an empty (unsupported) reply
+    Unknown(String),
+}
+
+/// Function to run a GDB remote serial protocol stub over the given
+/// stream (a TCP connection for a real client), driving the simulator
+/// until the client detaches or closes the connection
+pub fn run_gdb_stub<S: Read + Write>(stream: &mut S, sim: &mut Simulator) -> io::Result<()> {
+    let mut breakpoints: Vec<usize> = Vec::new();
+
+    while let Some(frame) = read_frame(stream)? {
+        let payload = match decode_packet(&frame) {
+            Some(payload) => payload,
+            None => {
+                // Ask the client to send it again
+                stream.write_all(b"-")?;
+                continue;
+            }
+        };
+        stream.write_all(b"+")?;
+
+        let command = parse_command(&payload);
+        let reply = handle_command(sim, &mut breakpoints, &command);
+
+        stream.write_all(encode_packet(&reply).as_bytes())?;
+        stream.flush()?;
+
+        if command == GdbCommand::Detach {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+/// Function to read one "$payload#checksum" frame, skipping the
+/// acknowledgements between them. Returns None once the client
+/// closes the stream
+fn read_frame<R: Read>(input: &mut R) -> io::Result<Option<String>> {
+    let mut byte = [0_u8; 1];
+
+    // Skip to the start of the next packet
+    loop {
+        if input.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        if byte[0] == b'$' {
+            break;
+        }
+    }
+
+    let mut frame = String::from("$");
+
+    loop {
+        if input.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        frame.push(byte[0] as char);
+
+        if byte[0] == b'#' {
+            break;
+        }
+    }
+
+    // Then the two hex digits of the checksum
+    for _ in 0..2 {
+        if input.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        frame.push(byte[0] as char);
+    }
+
+    Ok(Some(frame))
+}
+
+/// Returns the checksum of a payload: the sum of its bytes, modulo 256
+fn checksum(payload: &str) -> u8 {
+    payload
+        .bytes()
+        .fold(0_u8, |sum, byte| sum.wrapping_add(byte))
+}
+
+/// Function to check a "$payload#checksum" frame, returning
+/// the payload if the checksum matches
+pub fn decode_packet(frame: &str) -> Option<String> {
+    let body = frame.strip_prefix('$')?;
+    let (payload, sent_checksum) = body.split_at(body.rfind('#')?);
+    let sent_checksum = u8::from_str_radix(&sent_checksum[1..], 16).ok()?;
+
+    if checksum(payload) == sent_checksum {
+        Some(payload.to_string())
+    } else {
+        None
+    }
+}
+
+/// Function to wrap a payload in a "$payload#checksum" frame
+pub fn encode_packet(payload: &str) -> String {
+    format!("${}#{:02x}", payload, checksum(payload))
+}
+
+/// Function to turn a packet's payload into the command it asks for
+pub fn parse_command(payload: &str) -> GdbCommand {
+    let unknown = || GdbCommand::Unknown(payload.to_string());
+    let hex = |text: &str| usize::from_str_radix(text, 16).ok();
+
+    match payload.chars().next() {
+        Some('?') => GdbCommand::HaltReason,
+        Some('g') => GdbCommand::ReadRegisters,
+        Some('s') => GdbCommand::Step,
+        Some('c') => GdbCommand::Continue,
+        Some('D') | Some('k') => GdbCommand::Detach,
+        Some('m') => {
+            let mut parts = payload[1..].split(',');
+
+            match (parts.next().and_then(hex), parts.next().and_then(hex)) {
+                (Some(address), Some(length)) => GdbCommand::ReadMemory { address, length },
+                _ => unknown(),
+            }
+        }
+        Some('Z') | Some('z') => {
+            let mut parts = payload[1..].split(',');
+
+            // Only software breakpoints (type 0) are supported
+            match (parts.next(), parts.next().and_then(hex)) {
+                (Some("0"), Some(address)) if payload.starts_with('Z') => {
+                    GdbCommand::InsertBreakpoint(address)
+                }
+                (Some("0"), Some(address)) => GdbCommand::RemoveBreakpoint(address),
+                _ => unknown(),
+            }
+        }
+        _ => unknown(),
+    }
+}
+
+/// Function to carry out a command on the simulator, returning
+/// the payload to reply with
+pub fn handle_command(
+    sim: &mut Simulator,
+    breakpoints: &mut Vec<usize>,
+    command: &GdbCommand,
+) -> String {
+    match command {
+        GdbCommand::HaltReason => STOPPED.to_string(),
+        GdbCommand::ReadRegisters => {
+            let mut words: Vec<u16> = sim
+                .registers
+                .iter()
+                .map(|register| *register as u16)
+                .collect();
+            words.push((sim.get_program_counter() * 2) as u16);
+
+            words.iter().map(|word| word_bytes(*word)).collect()
+        }
+        GdbCommand::ReadMemory { address, length } => {
+            // Refuse lengths that would overflow or are far too long
+            // to answer, rather than trying to build the reply
+            let end = match address.checked_add(*length) {
+                Some(end) if *length <= MAX_READ_LENGTH => end,
+                _ => return ERROR_REPLY.to_string(),
+            };
+            let mut reply = String::new();
+
+            for byte_address in *address..end {
+                let word = match sim.memory[..sim.memory_size].get(byte_address / 2) {
+                    Some(word) => word,
+                    None => return ERROR_REPLY.to_string(),
+                };
+                let byte = if byte_address % 2 == 0 { word & 0xFF } else { word >> 8 };
+
+                reply.push_str(&format!("{:02x}", byte));
+            }
+
+            reply
+        }
+        GdbCommand::Step => stop_reply(sim.step()),
+        GdbCommand::Continue => loop {
+            match sim.step() {
+                Ok(step_result) if !step_result.halted => {}
+                result => break stop_reply(result),
+            }
+
+            if breakpoints.contains(&sim.get_program_counter()) {
+                break STOPPED.to_string();
+            }
+        },
+        GdbCommand::InsertBreakpoint(address) => {
+            breakpoints.push(address / 2);
+            "OK".to_string()
+        }
+        GdbCommand::RemoveBreakpoint(address) => {
+            breakpoints.retain(|breakpoint| *breakpoint != address / 2);
+            "OK".to_string()
+        }
+        GdbCommand::Detach => "OK".to_string(),
+        GdbCommand::Unknown(_) => String::new(),
+    }
+}
+
+/// Returns the hex of a word's two bytes, low byte first
+fn word_bytes(word: u16) -> String {
+    format!("{:02x}{:02x}", word & 0xFF, word >> 8)
+}
+
+/// Returns the stop reply for how a step ended
+fn stop_reply(result: Result<StepResult, RuntimeErr>) -> String {
+    match result {
+        Ok(step_result) if step_result.halted => EXITED.to_string(),
+        Ok(_) => STOPPED.to_string(),
+        Err(RuntimeErr::Halt) => EXITED.to_string(),
+        Err(_) => CRASHED.to_string(),
+    }
+}
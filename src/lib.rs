@@ -1,17 +1,53 @@
-use clap::{App, Arg};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Write, Stdin, Read};
-use std::process::*;
-use std::{thread, time};
+use std::io::{BufRead, BufReader, IsTerminal, Write, Stdin, Read};
 
 use colored::*;
 use std::*;
-use terminal::*;
 
 pub mod simulator;
 pub mod autograder;
+pub mod analysis;
+pub mod diagnostics;
+pub mod repl;
+pub mod lsp;
+#[cfg(feature = "cli")]
+pub mod gdb;
+#[cfg(feature = "cli")]
+pub mod dap;
+pub mod formats;
+#[cfg(feature = "cli")]
+pub mod serve;
+#[cfg(feature = "cli")]
+mod cli;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 use simulator::*;
-use autograder::*;
+use analysis::*;
+use diagnostics::*;
+
+pub use diagnostics::{Diagnostic, MessageFormat, Program, Severity};
+pub use repl::run_repl;
+pub use lsp::run_lsp;
+#[cfg(feature = "cli")]
+pub use gdb::run_gdb_stub;
+#[cfg(feature = "cli")]
+pub use dap::run_dap;
+pub use formats::{FormatRegistry, OutputFormat};
+#[cfg(feature = "cli")]
+pub use serve::run_server;
+#[cfg(feature = "cli")]
+pub use cli::*;
+pub use simulator::{
+    BuildErr, CallFrame, CompileErr, CustomInstruction, EventSender, InputSource, Instruction,
+    InstructionSet, InstructionType, IoHandle, IoHandler, MemoryChange, OutputSink, ReadMode,
+    RegisterChange, RuntimeErr, RuntimeWarning, Simulator, SimulatorBuilder, SimulatorEvent,
+    StdioHandler, StepResult, StopReason, TraceEntry, WriteMode,
+};
 
 // File extension for HMMM files
 // "Compiled" is really just a 1-to-1 mapping of the
@@ -19,18 +55,172 @@ use autograder::*;
 // does not support comments
 static UNCOMPILED: &str = ".hmmm";
 static COMPILED: &str = ".hb";
+// Extension for a compiled program as base64 of its big-endian bytes
+static BASE64_COMPILED: &str = ".b64";
+// Extension for exporting the program as a spreadsheet
+static CSV: &str = ".csv";
+// Extension for exporting the program as a Markdown table
+static MARKDOWN: &str = ".md";
+// Extension for exporting the program as an Altera memory initialization file
+static MIF: &str = ".mif";
+// Extension for exporting the program as a Xilinx coefficient file
+static COE: &str = ".coe";
+// Extension for exporting the program as a C array
+static C_ARRAY: &str = ".c";
+// Extension for exporting the program as a Verilog ROM
+static VERILOG: &str = ".v";
+// Module name used for the Verilog ROM if none is given
+static DEFAULT_VERILOG_MODULE: &str = "hmmm_rom";
+// Extension for gzip-compressed files, which can wrap any other extension
+static GZIP: &str = ".gz";
+// Start of each metadata line at the top of a .hb file
+static HB_HEADER_PREFIX: &str = "#";
+// Path used to read from stdin or write to stdout
+static STDIO_PATH: &str = "-";
+// Programs with at least this many lines show a progress bar while compiling
+const PROGRESS_BAR_THRESHOLD: usize = 5000;
+// Width of the progress bar, in characters
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+/// Function to get the path a file would have without its
+/// .gz extension, used to tell what format is inside it
+pub fn without_gzip(path: &str) -> &str {
+    path.strip_suffix(GZIP).unwrap_or(path)
+}
 
-/// Function to load any text file as a Vec of Strings
+/// Function to open any text file to be read line by line
+///
+/// A path of "-" reads from stdin instead, and paths ending in
+/// .gz are decompressed as they are read
+pub fn open_file(path: &str) -> std::io::Result<Box<dyn BufRead>> {
+    if path == STDIO_PATH {
+        Ok(Box::new(BufReader::new(io::stdin())))
+    } else if path.ends_with(GZIP) {
+        Ok(Box::new(BufReader::new(GzDecoder::new(File::open(path)?))))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+/// Function to load any text file as a Vec of Strings, opened
+/// like open_file
 pub fn load_file(path: &str) -> std::io::Result<Vec<String>> {
-    let reader = BufReader::new(File::open(path).expect("Cannot open file"));
-    let output_vec: Vec<String> = reader
-        .lines()
-        .map(|line| line.unwrap().trim().to_string())
-        .collect();
+    let mut output_vec: Vec<String> = Vec::new();
+
+    for line in open_file(path)?.lines() {
+        output_vec.push(line?.trim().to_string());
+    }
 
     Ok(output_vec)
 }
 
+/// Function to split source text into a Vec of trimmed lines,
+/// the same way load_file does for a file on disk
+pub fn source_to_lines(source: &str) -> Vec<String> {
+    source
+        .lines()
+        .map(|line| line.trim().to_string())
+        .collect()
+}
+
+/// When to color the terminal output
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ColorChoice {
+    /// Color only if stdout is a terminal and NO_COLOR is not set
+    Auto,
+    Always,
+    Never,
+}
+
+/// Function to turn coloring of all output on or off,
+/// based on the given choice
+pub fn set_color_choice(choice: ColorChoice) {
+    let use_color = match choice {
+        ColorChoice::Auto => env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal(),
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+
+    colored::control::set_override(use_color);
+}
+
+/// Function to format the boxed banner shown on success or
+/// error, with the message in green or red respectively
+pub fn format_banner(message: &str, success: bool) -> String {
+    let message = format!("{:^32}", message);
+    let message = if success {
+        message.green().bold()
+    } else {
+        message.red().bold()
+    };
+
+    format!(
+        "{}\n{}{}{}\n{}\n",
+        "▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀".yellow(),
+        "████".yellow(),
+        message,
+        "████".yellow(),
+        "▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄".yellow()
+    )
+}
+
+/// Function to link several HMMM sources into a single program,
+/// in the order given. Every instruction is renumbered to follow
+/// on from the previous source, so jumps between sources should
+/// use labels, as numeric addresses are not relocated
+pub fn link_sources(sources: Vec<Vec<String>>) -> Vec<String> {
+    let mut linked_text: Vec<String> = Vec::new();
+    let mut line_counter = 0;
+
+    for source in sources {
+        for line in source {
+            // A .org moves where the following lines are numbered from
+            if is_org_line(&line) {
+                if let Ok(target) = org_target(&line, line_counter) {
+                    line_counter = target;
+                }
+            }
+
+            if !is_instruction_line(&line) {
+                linked_text.push(line);
+                continue;
+            }
+
+            // Keep any inline label in front of the new number
+            let (label, instruction) = split_inline_label(&line);
+            let label = label.map_or(String::new(), |name| format!("{}: ", name));
+            let mut parts = instruction.trim_start().splitn(2, char::is_whitespace);
+            let line_number = parts.next().unwrap_or("");
+            let rest = parts.next();
+
+            // Leave lines without a number alone, so they still
+            // fail to compile with LineNumberNotPresent
+            match rest {
+                Some(rest) if line_number.parse::<i128>().is_ok() => {
+                    linked_text.push(format!("{}{} {}", label, line_counter, rest.trim_start()));
+                }
+                _ => linked_text.push(line),
+            }
+
+            line_counter += 1;
+        }
+    }
+
+    linked_text
+}
+
+/// Function to load several .hmmm files and link them into
+/// a single program, in the order given
+pub fn link_files(paths: &[&str]) -> std::io::Result<Vec<String>> {
+    let mut sources: Vec<Vec<String>> = Vec::new();
+
+    for path in paths {
+        sources.push(load_file(path)?);
+    }
+
+    Ok(link_sources(sources))
+}
+
 /// Function to pretty-print a compilation error and exit
 /// the program gracefully
 pub fn raise_compile_error(
@@ -41,14 +231,7 @@ pub fn raise_compile_error(
 ) {
     let args: String = line_parts[2..].join(" ");
 
-    println!("{}", "▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀".yellow());
-    println!(
-        "{}{}{}",
-        "████".yellow(),
-        "    COMPILATION UNSUCCESSFUL    ".red().bold(),
-        "████".yellow()
-    );
-    println!("{}\n", "▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄".yellow());
+    println!("{}", format_banner("COMPILATION UNSUCCESSFUL", false));
 
     println!(
         "{} {:?}",
@@ -62,543 +245,1411 @@ pub fn raise_compile_error(
     println!(
         "{} \"{}\"\n",
         " RAW TEXT:".on_red().white().bold(),
-        raw_line.white(),
+        raw_line.dimmed(),
     );
     println!("█▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀");
     println!("█           Interpreted As: ");
     println!("█ Line █ Command █ Arguments ");
     println!("█ {:4} █ {:7} █ {:15}", line_parts[0], line_parts[1], args);
     println!("█▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄");
+
+    if let Some(suggestion) = instruction_suggestion(&error, &line_parts) {
+        println!("{} {}", " HINT:".on_yellow().black().bold(), suggestion);
+    }
+
     println!("Exiting...");
 }
 
-/// Function to pretty-print a runtime error and exit
-/// the program gracefully
-pub fn raise_runtime_error(sim: &Simulator, error: &RuntimeErr) {
-    // Easy way to display information: show the debug screen!
-    let _debug_result = print_debug_screen(sim);
-    let current_line = sim.get_program_counter();
-
-    let w = terminal::stdout();
-    w.act(Action::MoveCursorTo(0, 29)).unwrap();
-    println!("{}", "▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀".yellow());
-    println!(
-        "{}{}{}",
-        "████".yellow(),
-        "    SIMULATION UNSUCCESSFUL     ".red().bold(),
-        "████".yellow()
-    );
-    println!("{}\n", "▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄".yellow());
+/// Function to suggest the closest known instruction, by edit
+/// distance, when a line uses one that does not exist
+pub fn instruction_suggestion(error: &CompileErr, line_parts: &[String]) -> Option<String> {
+    if *error != CompileErr::InstructionDoesNotExist {
+        return None;
+    }
 
-    println!(
-        "{} {:?}",
-        format!("{} {}:", " ERROR EXECUTING ADDRESS", current_line)
-            .on_red()
-            .white()
-            .bold(),
-        error
-    );
-    let current_line_contents = sim.get_memory(current_line).unwrap();
-    println!(
-        "{} {} {}\n",
-        " MEMORY ADDRESS CONTENTS:".on_red().white().bold(),
-        current_line_contents.instruction_type.names[0],
-        current_line_contents.text_contents
-    );
-}
+    let name = line_parts.get(1)?;
+    let instruction_set = InstructionSet::builtin();
+    instruction_set.closest_name(name)?;
 
-/// Function to print the current state of the simulator
-/// (registers, memory, etc.) to the screen without flickering
-/// (i.e. no flicker when the screen is updated)
-pub fn print_debug_screen(sim: &Simulator) -> terminal::error::Result<()> {
-    let mut debug_screen_lines: Vec<String> = Vec::new();
-
-    debug_screen_lines.push(format!(
-        "{}{}{}",
-        "█▀▀▀▀▀▀▀▀▀▀█",
-        "  REGISTER CONTENTS  ".bold().on_blue(),
-        "█▀▀▀▀▀▀▀▀▀▀█\n",
-    ));
-
-    for row in 0..4 {
-        debug_screen_lines.push(format!(
-            "█    R{: <2}   █    R{: <2}   █    R{: <2}   █    R{: <2}   █\n",
-            row * 4,
-            (row * 4) + 1,
-            (row * 4) + 2,
-            (row * 4) + 3
-        ));
-
-        debug_screen_lines.push(format!(
-            "█ {:8} █ {:8} █ {:8} █ {:8} █\n",
-            &sim.get_register(row * 4).unwrap_or(0),
-            &sim.get_register((row * 4) + 1).unwrap_or(0),
-            &sim.get_register((row * 4) + 2).unwrap_or(0),
-            &sim.get_register((row * 4) + 3).unwrap_or(0),
-        ));
-    }
-    debug_screen_lines.push("█▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄\n".to_string());
-    debug_screen_lines.push("█    █   0  █   1  █   2  █   3  █   4  █   5  █   6  █   7  █   8  █   9  █   A  █   B  █   C  █   D  █   E  █   F  █\n".to_string());
-    let address_chars = vec![
-        '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'A', 'B', 'C', 'D', 'E', 'F',
-    ];
-
-    let current_pc = &sim.get_program_counter();
-
-    for (i, address_rows) in address_chars.iter().enumerate() {
-        let mut to_print = format!("█  {} █", address_rows);
-
-        for (j, _address_columns) in address_chars.iter().enumerate() {
-            let memory_index = (i * 16) + j;
-
-            // We can safely unwrap here because we know that the memory
-            // is fully populated. Any errors will be caught at compile time,
-            // or worst case, with a RuntimeErr.
-            let current_instruction = sim.get_memory(memory_index).unwrap();
-
-            let instruction_text;
-            if current_pc == &memory_index {
-                instruction_text = current_instruction.as_hex().on_green();
-            } else if current_instruction.instruction_type.names[0] == "data" {
-                if current_instruction.binary_contents == vec!["0000", "0000", "0000", "0000"] {
-                    instruction_text = current_instruction.as_hex().on_black();
-                } else {
-                    instruction_text = current_instruction.as_hex().on_yellow().black();
-                }
-            } else {
-                instruction_text = current_instruction.as_hex().on_purple();
-            }
+    Some(instruction_set.unknown_name_message(name))
+}
 
-            to_print = format!("{} {} █", to_print, instruction_text);
-        }
+/// Function to format a plain dump of the simulator's program
+/// counter, registers, and memory (as hex words, 16 per row)
+pub fn format_state_dump(sim: &Simulator) -> String {
+    let mut contents = format!(
+        "PC: {}\nSTEPS: {}\nCYCLES: {}\n\nREGISTERS:\n",
+        sim.get_program_counter(),
+        sim.steps_taken,
+        sim.cycles_taken
+    );
 
-        debug_screen_lines.push(format!("{}\n", to_print));
+    for (index, register) in sim.registers.iter().enumerate() {
+        contents = format!("{}r{:<2} = {:<6}", contents, index, register);
+        contents.push(if index % 4 == 3 { '\n' } else { ' ' });
     }
 
-    debug_screen_lines.push("▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀\n".to_string());
+    contents.push_str("\nMEMORY:\n");
 
-    // Create terminal object to print out the debug screen
-    let mut w = terminal::stdout();
-    // Make sure the cursor is at the top of the screen
-    w.act(Action::MoveCursorTo(0, 0))?;
-    // Print line by line to avoid having to strobe the screen
-    for line in debug_screen_lines {
-        print!("{}", line);
+    for (row, words) in sim.memory[..sim.memory_size].chunks(16).enumerate() {
+        let words: Vec<String> = words.iter().map(|word| format!("{:04X}", word)).collect();
+        contents = format!("{}{:3}: {}\n", contents, row * 16, words.join(" "));
     }
 
-    // Line by line printing done, now to print out program counter,
-    // IR, human-readable output, and HMMM output.
+    contents
+}
 
-    // Print program counter
-    w.act(Action::MoveCursorTo(50, 1)).unwrap();
-    let to_print = format!("{}", " PROGRAM COUNTER: ".on_red().white().bold());
-    print!("{}", to_print);
-    w.act(Action::MoveCursorTo(50, 2)).unwrap();
-    let to_print = format!("{:<10}", sim.get_program_counter());
-    print!("{}", to_print);
+/// Function to format the header of the table printed by debug
+/// mode when stdout is not a terminal (eg: piped into a log)
+pub fn format_step_table_header() -> String {
+    let mut contents = format!("{:>6}  {:>3}  {:<16}", "STEP", "PC", "INSTRUCTION");
 
-    // Print IR
-    w.act(Action::MoveCursorTo(50, 4)).unwrap();
-    let to_print = format!("{}", " INSTRUCTION REGISTER: ".on_red().white().bold());
-    print!("{}", to_print);
-    let memory_ir = sim.get_memory(sim.get_program_counter());
+    for index in 0..REGISTER_COUNT {
+        contents = format!("{} {:>6}", contents, format!("r{}", index));
+    }
 
-    if memory_ir.is_some() {
-        let memory_ir = memory_ir.unwrap();
-        w.act(Action::MoveCursorTo(50, 5)).unwrap();
-        let to_print = format!(
-            "{:<15}",
-            format!(
-                "{} {}",
-                memory_ir.instruction_type.names[0], memory_ir.text_contents
-            )
-        );
-        print!("{}", to_print);
+    contents.push('\n');
+    contents
+}
 
-        // Print human-readable output
-        w.act(Action::MoveCursorTo(75, 1)).unwrap();
-        let to_print = format!("{}", " HUMAN-READABLE CODE: ".on_green().white().bold());
-        print!("{}", to_print);
+/// Function to format one row of the debug step table: the step
+/// just run, the address it ran from, its instruction, and every
+/// register afterwards
+pub fn format_step_row(sim: &Simulator, step: u64, address: usize) -> String {
+    let instruction = sim
+        .get_memory(address)
+        .map_or(String::new(), |instruction| instruction.to_string());
+    let mut contents = format!("{:>6}  {:>3}  {:<16}", step, address, instruction);
+
+    for register in sim.registers.iter() {
+        contents = format!("{} {:>6}", contents, register);
+    }
 
-        w.act(Action::MoveCursorTo(75, 2)).unwrap();
+    contents.push('\n');
+    contents
+}
 
-        let mut to_print = String::from(memory_ir.instruction_type.human_explanation);
+/// The first point at which two traced runs differ. A missing
+/// entry means that run had already stopped
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Divergence {
+    pub step: u64,
+    pub left: Option<TraceEntry>,
+    pub right: Option<TraceEntry>,
+}
 
-        for (i, c) in memory_ir.instruction_type.arguments.chars().enumerate() {
-            let result: String = match c {
-                'r' => {
-                    format!(
-                        "{}",
-                        u8::from_str_radix(memory_ir.binary_contents[i + 1].as_str(), 2).unwrap()
-                    )
-                }
-                's' => {
-                    let converted = signed_binary_conversion(
-                        memory_ir.binary_contents[i + 1..i + 3].join("").as_str(),
-                    )
-                    .unwrap();
+/// Function to find the first step at which two traces differ
+/// in their program counter or registers, if any
+pub fn first_divergence(left: &[TraceEntry], right: &[TraceEntry]) -> Option<Divergence> {
+    for index in 0..left.len().max(right.len()) {
+        let left_entry = left.get(index);
+        let right_entry = right.get(index);
+
+        if left_entry != right_entry {
+            return Some(Divergence {
+                step: index as u64,
+                left: left_entry.cloned(),
+                right: right_entry.cloned(),
+            });
+        }
+    }
 
-                    format!("{}", converted)
-                }
-                'u' => {
-                    let converted = u8::from_str_radix(
-                        memory_ir.binary_contents[i + 1..i + 3].join("").as_str(),
-                        2,
-                    )
-                    .unwrap();
-                    format!("{}", converted)
-                }
-                'n' => {
-                    let converted =
-                        i32::from_str_radix(memory_ir.binary_contents.join("").as_str(), 2)
-                            .unwrap();
-                    format!("{}", converted)
-                }
-                _ => String::from(""),
-            };
-            if !result.is_empty() {
-                to_print = to_print.replacen("_", result.as_str(), 1);
+    None
+}
+
+/// Function to format a divergence, showing the program counter
+/// of each run and only the registers that differ
+pub fn format_divergence(divergence: &Divergence) -> String {
+    let describe = |entry: &Option<TraceEntry>| match entry {
+        Some(entry) => format!("pc = {}", entry.program_counter),
+        None => String::from("already stopped"),
+    };
+
+    let mut contents = format!(
+        "Runs diverge at step {}\n  left:  {}\n  right: {}\n",
+        divergence.step,
+        describe(&divergence.left),
+        describe(&divergence.right)
+    );
+
+    if let (Some(left), Some(right)) = (&divergence.left, &divergence.right) {
+        for (index, (left_value, right_value)) in
+            left.registers.iter().zip(right.registers.iter()).enumerate()
+        {
+            if left_value != right_value {
+                contents = format!(
+                    "{}  r{}: {} vs {}\n",
+                    contents, index, left_value, right_value
+                );
             }
         }
-        if to_print.len() > 45 {
-            print!(
-                "{:<45}",
-                to_print.drain(..40).collect::<String>().trim().bold()
-            );
-            w.act(Action::MoveCursorTo(75, 3)).unwrap();
-            print!("{:<45}", to_print.trim().bold());
-        } else {
-            print!("{:<45}", to_print.bold());
-            w.act(Action::MoveCursorTo(75, 3)).unwrap();
-            print!("{:<45}", "");
-        }
     }
 
-    // Print HMMM output
-    w.act(Action::MoveCursorTo(50, 7)).unwrap();
-    let to_print = format!("{}", " HMMM OUT: ".on_green().white().bold());
-    print!("{}", to_print);
+    contents
+}
 
-    w.flush()?;
+/// Function to normalize a single line of compiled HMMM into
+/// the nibble-split form ("0000 0000 0000 0000") that
+/// Instruction::new_from_binary expects. Lines may be written
+/// with or without spaces between the nibbles, but must contain
+/// exactly 16 binary digits
+pub fn normalize_binary_line(line: &str) -> Result<String, CompileErr> {
+    let contiguous: String = line.split_whitespace().collect();
 
-    Ok(())
+    if contiguous.len() != 16 || !contiguous.chars().all(|c| c == '0' || c == '1') {
+        return Err(CompileErr::CorruptedBinary);
+    }
+
+    Ok(split_binary_to_chunks(contiguous))
+}
+
+/// Function to check if a line of compiled HMMM is written
+/// as a single 4-digit hex word (eg: "8123")
+pub fn is_hex_line(line: &str) -> bool {
+    let contiguous: String = line.split_whitespace().collect();
+
+    contiguous.len() == 4 && contiguous.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Function to expand a 4-digit hex word into the nibble-split
+/// binary form that Instruction::new_from_binary expects
+pub fn normalize_hex_line(line: &str) -> Result<String, CompileErr> {
+    if !is_hex_line(line) {
+        return Err(CompileErr::CorruptedBinary);
+    }
+
+    let contiguous: String = line.split_whitespace().collect();
+    let word = u16::from_str_radix(contiguous.as_str(), 16);
+
+    if word.is_err() {
+        return Err(CompileErr::CorruptedBinary);
+    }
+
+    Ok(split_binary_to_chunks(format!("{:016b}", word.unwrap())))
 }
 
 /// Function to read a vec of binary HMMM text into
 /// a Vec of Instruction structs
-pub fn read_compiled_hmmm(raw_binary: Vec<String>) -> Vec<Instruction> {
+///
+/// The whole file is treated as hex if its first line is a
+/// hex word, otherwise as binary. Mixing the two is rejected
+///
+/// Lines starting with # are a metadata header and are skipped
+pub fn read_compiled_hmmm(raw_binary: Vec<String>) -> Result<Vec<Instruction>, CompileErr> {
     let mut compiled_text: Vec<Instruction> = Vec::new();
+    let binary_lines: Vec<&String> = raw_binary
+        .iter()
+        .filter(|line| !line.trim_start().starts_with(HB_HEADER_PREFIX))
+        .collect();
+    let is_hex = binary_lines.first().map_or(false, |line| is_hex_line(line));
 
-    for line in raw_binary {
-        let next_instruction = Instruction::new_from_binary(line.as_str());
+    for line in binary_lines {
+        let normalized_line = if is_hex {
+            normalize_hex_line(line)?
+        } else {
+            normalize_binary_line(line)?
+        };
 
-        if next_instruction.is_err() {
-            panic!("{:?}", next_instruction.err())
-        }
+        compiled_text.push(Instruction::new_from_binary(normalized_line.as_str())?);
+    }
+
+    Ok(compiled_text)
+}
 
-        compiled_text.push(next_instruction.unwrap())
+/// Function to read a program written as base64 of its words'
+/// big-endian bytes, ignoring any whitespace or line breaks
+pub fn read_base64_hmmm(encoded: &str) -> Result<Vec<Instruction>, CompileErr> {
+    let contiguous: String = encoded.split_whitespace().collect();
+    let bytes = BASE64
+        .decode(contiguous)
+        .map_err(|_| CompileErr::CorruptedBinary)?;
+
+    if bytes.len() % 2 != 0 {
+        return Err(CompileErr::CorruptedBinary);
     }
 
-    compiled_text
+    bytes
+        .chunks(2)
+        .map(|pair| Instruction::from_u16(u16::from_be_bytes([pair[0], pair[1]])))
+        .collect()
+}
+
+/// Compiles HMMM source text into a Program, without printing
+/// anything on failure
+///
+/// Every error and warning in the source is returned at once,
+/// rather than stopping at the first one
+///
+/// ```
+/// use hmmm_rs::{compile, Simulator};
+///
+/// let program = compile("0 setn r1 6\n1 setn r2 7\n2 mul r3 r1 r2\n3 halt").unwrap();
+/// let mut sim = Simulator::new_headless(program.instructions);
+///
+/// // Step until the program halts
+/// while sim.step().is_ok() {}
+///
+/// assert_eq!(sim.get_register(3), Some(42));
+/// ```
+pub fn compile(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    compile_program(source)
+}
+
+/// Compiles HMMM source text into a Program like compile, using
+/// the given instruction set
+pub fn compile_in(
+    source: &str,
+    instruction_set: &InstructionSet,
+) -> Result<Program, Vec<Diagnostic>> {
+    compile_program_in(source, instruction_set)
+}
+
+/// Turns a program back into uncompiled HMMM code, with
+/// one numbered instruction per line
+pub fn as_uncompiled_hmmm(compiled_text: &[Instruction]) -> String {
+    let mut contents = String::from("");
+
+    for (index, instruction) in compiled_text.iter().enumerate() {
+        contents = format!("{}{} {}\n", contents, index, instruction);
+    }
+
+    String::from(contents.trim_end())
+}
+
+/// Turns a program back into uncompiled HMMM code like
+/// as_uncompiled_hmmm, ending each line that has a comment with
+/// it. Instructions are padded to the widest one, so the comments
+/// all start in the same column
+///
+/// ```
+/// let program = hmmm_rs::compile("0 setn r1 5\n1 write r1\n2 halt").unwrap();
+/// let comments = vec![Some("start".to_string()), None, Some("done".to_string())];
+/// let source = hmmm_rs::as_commented_hmmm(&program.instructions, &comments);
+///
+/// assert_eq!(source, "0 setn r1, 5  # start\n1 write r1\n2 halt        # done");
+/// ```
+pub fn as_commented_hmmm(compiled_text: &[Instruction], comments: &[Option<String>]) -> String {
+    let lines: Vec<String> = compiled_text
+        .iter()
+        .enumerate()
+        .map(|(index, instruction)| format!("{} {}", index, instruction))
+        .collect();
+
+    // First pass: find the widest instruction to line the comments up after
+    let width = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+
+    let lines: Vec<String> = lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| match comments.get(index).and_then(|c| c.as_deref()) {
+            Some(comment) => format!("{:<width$}  # {}", line, comment, width = width),
+            None => line.clone(),
+        })
+        .collect();
+
+    lines.join("\n")
+}
+
+/// Turns raw machine words (eg: a memory image) into uncompiled
+/// HMMM code, with any word that is not an instruction shown as
+/// data instead
+///
+/// ```
+/// let source = hmmm_rs::decompile(&[0x1105, 0x0005, 0x0000]);
+///
+/// assert_eq!(source, "0 setn r1, 5\n1 data 5\n2 halt");
+/// ```
+pub fn decompile(words: &[u16]) -> String {
+    let mut lines: Vec<String> = Vec::new();
+
+    // Words that match no instruction decode as data
+    for (index, word) in words.iter().enumerate() {
+        let instruction =
+            Instruction::from_u16(*word).unwrap_or_else(|_| Instruction::new_data_word(*word));
+        lines.push(format!("{} {}", index, instruction));
+    }
+
+    lines.join("\n")
+}
+
+/// Function to assemble a single instruction, written without
+/// a line number, into its binary and hex encoding
+///
+/// ```
+/// let encoding = hmmm_rs::assemble_instruction("add r1 r2 r3").unwrap();
+///
+/// assert_eq!(encoding, "0110 0001 0010 0011 (0x6123)");
+/// ```
+pub fn assemble_instruction(text: &str) -> Result<String, CompileErr> {
+    let line_parts = split_source_line(text.trim(), &collections::HashMap::new());
+    let instruction = Instruction::new_from_text(&line_parts.join(" ").to_lowercase())?;
+
+    Ok(format!("{} (0x{:04X})", instruction.as_binary(), instruction.to_u16()))
+}
+
+/// Function to explain what an instruction does: how it is
+/// written, a plain-English description, and its binary encoding.
+/// Unknown names suggest the closest real one
+///
+/// ```
+/// let explanation = hmmm_rs::explain_instruction("addn").unwrap();
+///
+/// assert!(explanation.starts_with("addn rX N\n"));
+/// ```
+pub fn explain_instruction(name: &str) -> Result<String, String> {
+    let instruction_set = InstructionSet::builtin();
+    let instruction_type = match instruction_set.by_name(&name.to_lowercase()) {
+        Some(instruction_type) if instruction_type.names[0] != "data" => instruction_type,
+        _ => return Err(instruction_set.unknown_name_message(name)),
+    };
+
+    let mut explanation = format!(
+        "{}\n{}\n\nEncoding: {}\n",
+        instruction_type.operand_format(),
+        instruction_type.description,
+        instruction_type.encoding_template()
+    );
+
+    if instruction_type.names.len() > 1 {
+        let aliases = instruction_type.names[1..].join(", ");
+        explanation.push_str(&format!("Also written as: {}\n", aliases));
+    }
+
+    Ok(explanation)
+}
+
+/// Function to disassemble a single 16-bit binary word, with
+/// or without spaces between nibbles, into its mnemonic
+pub fn disassemble_instruction(binary: &str) -> Result<String, CompileErr> {
+    Ok(Instruction::new_from_binary(binary.trim())?.to_string())
 }
 
 /// Simple function to write a program as uncompiled HMMM code
 /// Useful for "decompiling" a compiled program
 pub fn write_uncompiled_hmmm(path: &str, compiled_text: Vec<Instruction>) -> std::io::Result<()> {
+    write_file(path, as_uncompiled_hmmm(&compiled_text).as_str())
+}
+
+/// Function to write a program as uncompiled HMMM code with a
+/// comment on each line that has one, lined up in one column
+pub fn write_commented_hmmm(
+    path: &str,
+    compiled_text: &[Instruction],
+    comments: &[Option<String>],
+) -> std::io::Result<()> {
+    write_file(path, as_commented_hmmm(compiled_text, comments).as_str())
+}
+
+/// Function to format a program as the lines of a compiled .hb binary
+pub fn as_compiled_hmmm(compiled_text: &[Instruction]) -> String {
     let mut contents = String::from("");
 
-    for (index, instruction) in compiled_text.iter().enumerate() {
-        contents = format!(
-            "{}{} {} {}\n",
-            contents, index, instruction.instruction_type.names[0], instruction.text_contents
-        );
+    for instruction in compiled_text {
+        contents = format!("{}{}\n", contents, instruction.as_binary());
     }
 
-    contents = String::from(contents.trim_end());
-
-    fs::write(path, contents)?;
-    Ok(())
+    String::from(contents.trim_end())
 }
 
 /// Function to write a program as a compiled .hb binary
 pub fn write_compiled_hmmm(path: &str, compiled_text: Vec<Instruction>) -> std::io::Result<()> {
-    let mut contents = String::from("");
+    write_file(path, as_compiled_hmmm(&compiled_text).as_str())
+}
+
+/// Function to format the metadata header of a .hb file, recording
+/// the compiler version, the source it came from, and when
+pub fn hb_header(source_name: &str) -> String {
+    format!(
+        "{prefix} hmmm_rs {}\n{prefix} source: {}\n{prefix} compiled: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        source_name,
+        chrono::offset::Local::now().to_rfc3339(),
+        prefix = HB_HEADER_PREFIX
+    )
+}
+
+/// Function to write a program as a compiled .hb binary, starting
+/// with a metadata header naming its source
+pub fn write_compiled_hmmm_with_header(
+    path: &str,
+    compiled_text: &[Instruction],
+    source_name: &str,
+) -> std::io::Result<()> {
+    let contents = format!("{}{}", hb_header(source_name), as_compiled_hmmm(compiled_text));
+
+    write_file(path, contents.as_str())
+}
+
+/// Function to format a program as a C array of 16-bit words,
+/// along with a constant holding its length. Little-endian swaps
+/// the bytes of each word, for targets that read the array back
+/// a byte at a time
+pub fn as_c_array(compiled_text: &[Instruction], endian: Endian) -> String {
+    let mut contents = String::from("#include <stdint.h>\n\nconst uint16_t program[] = {\n");
 
     for instruction in compiled_text {
-        let binary = instruction.binary_contents.join(" ");
-        contents = format!("{}{}\n", contents, binary);
+        let word = match endian {
+            Endian::Big => instruction.to_u16(),
+            Endian::Little => instruction.to_u16().swap_bytes(),
+        };
+        contents = format!("{}    0x{:04X},\n", contents, word);
     }
 
-    contents = String::from(contents.trim_end());
+    contents = format!(
+        "{}}};\nconst unsigned int program_len = {};\n",
+        contents,
+        compiled_text.len()
+    );
 
-    fs::write(path, contents)?;
-    Ok(())
+    contents
 }
 
-pub fn write_file(path: &str, contents: &str) -> std::io::Result<()> {
-    fs::write(path, contents)?;
-    Ok(())
+/// Function to write a program as a C array for embedding
+/// in host C code
+pub fn write_c_array(
+    path: &str,
+    compiled_text: Vec<Instruction>,
+    endian: Endian,
+) -> std::io::Result<()> {
+    write_file(path, as_c_array(&compiled_text, endian).as_str())
 }
 
-pub fn main() -> terminal::error::Result<()> {
-    // Create the terminal object just to have an easy way
-    // to clear it
-    let terminal = terminal::stdout();
-    terminal.act(Action::ClearTerminal(Clear::All))?;
-
-    // Setup command line matches
-    let matches = App::new("HMMM Compiler")
-        .version("1.0")
-        .author("Ethan Vazquez <edv121@outlook.com>")
-        .about("A compiler, decompiler, debugger, and simulator for Harvey Mudd Miniature Machine (HMMM)")
-        .arg(Arg::with_name("input")
-                 .short("i")
-                 .long("input")
-                 .takes_value(true)
-                 .help("Input .hmmm or .hb file"))
-        .arg(Arg::with_name("output")
-                 .short("o")
-                 .long("output")
-                 .takes_value(true)
-                 .help("Output location of either .hmmm or .hb file"))
-        .arg(Arg::with_name("debug")
-                 .short("d")
-                 .long("debug")
-                 .takes_value(false)
-                 .help("Use debug mode for stepping through simulator"))
-        .arg(Arg::with_name("no-run")
-                 .short("n")
-                 .long("no-run")
-                 .takes_value(false)
-                 .help("Do not simulate (run) the program on compilation"))
-        .arg(Arg::with_name("speed")
-                 .short("s")
-                 .long("speed")
-                 .takes_value(true)
-                 .help("Sets the multiplier (speed) of debug mode (eg: .5 is half speed, 2 is double)"))
-        .arg(Arg::with_name("autograder")
-                 .short("a")
-                 .long("autograder")
-                 .takes_value(true)
-                 .help("Toggles the AutoGrader functionality, expecting a test string to be given. If enabled, expects a directory path instead of a file path for --input and --output. --debug, --no-run, and --speed are ignored in this mode."))         
-        .get_matches();
-
-    if matches.value_of("input").is_none() {
-        println!("Error: Please specify a file to compile/run!");
-        exit(1);
-    } else {
-        // Print out startup message
-        println!(
-            "{} ████    ████  ████    ████",
-            "██    ██  ████    ████ ".yellow()
-        );
-        println!(
-            "{} ██ ██  ██ ██  ██ ██  ██ ██",
-            "██    ██  ██ ██  ██ ██ ".yellow()
-        );
-        println!(
-            "{} ██  ████  ██  ██  ████  ██",
-            "████████  ██  ████  ██ ".yellow()
-        );
-        println!(
-            "{} ██   ██   ██  ██   ██   ██",
-            "██    ██  ██   ██   ██ ".yellow()
+/// Function to format a program as a synthesizable Verilog ROM,
+/// with every address past the end of the program reading as zero
+pub fn as_verilog_rom(compiled_text: &[Instruction], module_name: &str) -> String {
+    let mut contents = format!(
+        "module {} (\n    input  wire [7:0]  addr,\n    output reg  [15:0] data\n);\n    always @(*) begin\n        case (addr)\n",
+        module_name
+    );
+
+    for (index, instruction) in compiled_text.iter().enumerate() {
+        contents = format!(
+            "{}            8'd{}: data = 16'h{};\n",
+            contents,
+            index,
+            instruction.clone().as_hex()
         );
-        println!(
-            "{} ██        ██  ██        ██",
-            "██    ██  ██        ██ ".yellow()
+    }
+
+    contents = format!(
+        "{}            default: data = 16'h0000;\n        endcase\n    end\nendmodule\n",
+        contents
+    );
+
+    contents
+}
+
+/// Function to write a program as a Verilog ROM module
+pub fn write_verilog_rom(
+    path: &str,
+    compiled_text: Vec<Instruction>,
+    module_name: &str,
+) -> std::io::Result<()> {
+    write_file(path, as_verilog_rom(&compiled_text, module_name).as_str())
+}
+
+/// Byte order used when splitting 16-bit words into bytes
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+/// Function to split each word of a program into a pair of bytes
+pub fn as_raw_bytes(compiled_text: &[Instruction], endian: Endian) -> Vec<u8> {
+    compiled_text
+        .iter()
+        .flat_map(|instruction| match endian {
+            Endian::Big => instruction.to_u16().to_be_bytes(),
+            Endian::Little => instruction.to_u16().to_le_bytes(),
+        })
+        .collect()
+}
+
+/// Function to write a program as a raw blob of exactly two
+/// bytes per word
+pub fn write_raw_bytes(
+    path: &str,
+    compiled_text: &[Instruction],
+    endian: Endian,
+) -> std::io::Result<()> {
+    write_bytes(path, &as_raw_bytes(compiled_text, endian))
+}
+
+/// Function to format a program as base64 of its words'
+/// big-endian bytes, on a single line
+pub fn as_base64(compiled_text: &[Instruction]) -> String {
+    format!("{}\n", BASE64.encode(program_bytes(compiled_text)))
+}
+
+/// Function to write a program as a single line of base64
+pub fn write_base64(path: &str, compiled_text: &[Instruction]) -> std::io::Result<()> {
+    write_file(path, as_base64(compiled_text).as_str())
+}
+
+/// Function to split each word of a program into a pair of
+/// big-endian bytes
+fn program_bytes(compiled_text: &[Instruction]) -> Vec<u8> {
+    as_raw_bytes(compiled_text, Endian::Big)
+}
+
+/// Function to hash a program with CRC-32 over its big-endian
+/// words, so identical programs always give the same hash
+pub fn program_hash(compiled_text: &[Instruction]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+
+    for byte in program_bytes(compiled_text) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+/// Function to format a single Intel HEX record, ending
+/// with the two's complement checksum of its bytes
+fn intel_hex_record(address: u16, record_type: u8, data: &[u8]) -> String {
+    let mut bytes: Vec<u8> = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+
+    let checksum = bytes
+        .iter()
+        .fold(0_u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg();
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+
+    format!(":{}{:02X}\n", hex, checksum)
+}
+
+/// Function to format a program as Intel HEX, with each word
+/// as a pair of bytes in the given order, 16 bytes per data record
+pub fn as_intel_hex(compiled_text: &[Instruction], endian: Endian) -> String {
+    let mut contents = String::new();
+
+    for (index, chunk) in as_raw_bytes(compiled_text, endian).chunks(16).enumerate() {
+        contents.push_str(intel_hex_record((index * 16) as u16, 0x00, chunk).as_str());
+    }
+
+    // End of file record
+    contents.push_str(intel_hex_record(0, 0x01, &[]).as_str());
+
+    contents
+}
+
+/// Function to write a program as an Intel HEX file
+pub fn write_intel_hex(
+    path: &str,
+    compiled_text: &[Instruction],
+    endian: Endian,
+) -> std::io::Result<()> {
+    write_file(path, as_intel_hex(compiled_text, endian).as_str())
+}
+
+/// Function to format a single S-record with a 16-bit address,
+/// ending with the one's complement checksum of its bytes
+fn s_record(record_type: char, address: u16, data: &[u8]) -> String {
+    // The count covers the address, data, and checksum
+    let mut bytes: Vec<u8> = vec![(data.len() + 3) as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.extend_from_slice(data);
+
+    let checksum = !bytes.iter().fold(0_u8, |sum, byte| sum.wrapping_add(*byte));
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+
+    format!("S{}{}{:02X}\n", record_type, hex, checksum)
+}
+
+/// Function to format a program as Motorola S-records, with an
+/// S0 header, S1 data records of 16 bytes (each word's bytes in
+/// the given order), and an S9 terminator
+pub fn as_s_records(compiled_text: &[Instruction], endian: Endian) -> String {
+    let mut contents = s_record('0', 0, b"HMMM");
+
+    for (index, chunk) in as_raw_bytes(compiled_text, endian).chunks(16).enumerate() {
+        contents.push_str(s_record('1', (index * 16) as u16, chunk).as_str());
+    }
+
+    // Execution starts at address 0
+    contents.push_str(s_record('9', 0, &[]).as_str());
+
+    contents
+}
+
+/// Function to write a program as a Motorola S-record file
+pub fn write_s_records(
+    path: &str,
+    compiled_text: &[Instruction],
+    endian: Endian,
+) -> std::io::Result<()> {
+    write_file(path, as_s_records(compiled_text, endian).as_str())
+}
+
+/// Function to format a program as CSV, with a header row and
+/// then one row per instruction
+pub fn as_csv(compiled_text: &[Instruction]) -> String {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    // Writing into a Vec cannot fail, so neither can these
+    writer
+        .write_record(&["address", "mnemonic", "args", "binary", "hex"])
+        .unwrap();
+
+    for (index, instruction) in compiled_text.iter().enumerate() {
+        writer
+            .write_record(&[
+                index.to_string(),
+                instruction.instruction_type.names[0].to_string(),
+                instruction.arguments().join(", "),
+                instruction.as_binary(),
+                instruction.clone().as_hex(),
+            ])
+            .unwrap();
+    }
+
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
+
+/// Function to write a program as a CSV file
+pub fn write_csv(path: &str, compiled_text: &[Instruction]) -> std::io::Result<()> {
+    write_file(path, as_csv(compiled_text).as_str())
+}
+
+/// Function to format a run as CSV, with one row per register
+/// each step changed (or one row if it changed none), from the
+/// instruction run at each step and what that step did
+pub fn as_trace_csv(trace: &[(Instruction, StepResult)]) -> String {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    // Writing into a Vec cannot fail, so neither can these
+    writer
+        .write_record(&["step", "pc", "mnemonic", "args", "changed_reg", "old", "new"])
+        .unwrap();
+
+    for (index, (instruction, step_result)) in trace.iter().enumerate() {
+        let step_columns = vec![
+            (index + 1).to_string(),
+            step_result.address.to_string(),
+            instruction.instruction_type.names[0].to_string(),
+            instruction.arguments().join(", "),
+        ];
+
+        if step_result.register_changes.is_empty() {
+            let mut record = step_columns.clone();
+            record.extend(vec![String::new(); 3]);
+            writer.write_record(&record).unwrap();
+        }
+
+        for change in step_result.register_changes.iter() {
+            let mut record = step_columns.clone();
+            record.extend(vec![
+                format!("r{}", change.register),
+                change.old.to_string(),
+                change.new.to_string(),
+            ]);
+            writer.write_record(&record).unwrap();
+        }
+    }
+
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
+
+/// Function to write a run's trace as a CSV file
+pub fn write_trace_csv(path: &str, trace: &[(Instruction, StepResult)]) -> std::io::Result<()> {
+    write_file(path, as_trace_csv(trace).as_str())
+}
+
+/// Function to format how often a run read and wrote each
+/// memory address as CSV, leaving out addresses never touched
+pub fn as_heatmap_csv(sim: &Simulator) -> String {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    // Writing into a Vec cannot fail, so neither can these
+    writer.write_record(&["address", "reads", "writes"]).unwrap();
+
+    let counts = sim.memory_reads.iter().zip(sim.memory_writes.iter());
+
+    for (address, (reads, writes)) in counts.enumerate() {
+        if *reads > 0 || *writes > 0 {
+            writer
+                .write_record(&[address.to_string(), reads.to_string(), writes.to_string()])
+                .unwrap();
+        }
+    }
+
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
+
+/// Function to write a run's memory heatmap as a CSV file
+pub fn write_heatmap_csv(path: &str, sim: &Simulator) -> std::io::Result<()> {
+    write_file(path, as_heatmap_csv(sim).as_str())
+}
+
+/// Function to format a program as a GitHub-flavored Markdown table
+pub fn as_markdown_table(compiled_text: &[Instruction]) -> String {
+    let mut contents = String::from("| Address | Mnemonic | Arguments | Binary |\n");
+    contents.push_str("|--------:|----------|-----------|--------|\n");
+
+    for (index, instruction) in compiled_text.iter().enumerate() {
+        contents.push_str(
+            format!(
+                "| {} | {} | {} | `{}` |\n",
+                index,
+                instruction.instruction_type.names[0],
+                instruction.arguments().join(", "),
+                instruction.as_binary()
+            )
+            .as_str(),
         );
-        println!(
-            "{}",
-            " HARVEY       MUDD       MINIATURE      MACHINE   "
-                .black()
-                .dimmed()
-                .italic()
-                .bold()
-                .on_white()
+    }
+
+    contents
+}
+
+/// Function to write a program as a Markdown table
+pub fn write_markdown_table(path: &str, compiled_text: &[Instruction]) -> std::io::Result<()> {
+    write_file(path, as_markdown_table(compiled_text).as_str())
+}
+
+/// Function to format a program as an Altera MIF, filling the
+/// rest of the 256 word memory with zeros
+pub fn as_mif(compiled_text: &[Instruction]) -> String {
+    let depth = 256;
+
+    let mut contents = format!(
+        "WIDTH=16;\nDEPTH={};\n\nADDRESS_RADIX=UNS;\nDATA_RADIX=HEX;\n\nCONTENT BEGIN\n",
+        depth
+    );
+
+    for (index, instruction) in compiled_text.iter().enumerate() {
+        contents.push_str(format!("\t{} : {:04X};\n", index, instruction.word).as_str());
+    }
+
+    if compiled_text.len() < depth {
+        contents.push_str(format!("\t[{}..{}] : 0000;\n", compiled_text.len(), depth - 1).as_str());
+    }
+
+    contents.push_str("END;\n");
+
+    contents
+}
+
+/// Function to write a program as an Altera MIF file
+pub fn write_mif(path: &str, compiled_text: &[Instruction]) -> std::io::Result<()> {
+    write_file(path, as_mif(compiled_text).as_str())
+}
+
+/// Function to format a program as a Xilinx COE file for Block RAM
+pub fn as_coe(compiled_text: &[Instruction]) -> String {
+    let words: Vec<String> = compiled_text
+        .iter()
+        .map(|instruction| format!("{:04X}", instruction.word))
+        .collect();
+
+    format!(
+        "memory_initialization_radix=16;\nmemory_initialization_vector=\n{};\n",
+        words.join(",\n")
+    )
+}
+
+/// Function to write a program as a Xilinx COE file
+pub fn write_coe(path: &str, compiled_text: &[Instruction]) -> std::io::Result<()> {
+    write_file(path, as_coe(compiled_text).as_str())
+}
+
+/// Function to format a listing of the program, with the address,
+/// binary, and original source line of each instruction side by side,
+/// any assembler warnings under the line they point at, and then the
+/// registers each subroutine can change
+pub fn as_listing(compiled_text: &[Instruction], source_text: &[String]) -> String {
+    let mut source_lines: Vec<Option<&String>> = Vec::new();
+
+    for line in source_text {
+        if is_org_line(line) {
+            // Words skipped over by a .org have no source line
+            let target = org_address(line).unwrap_or(0).max(source_lines.len());
+            source_lines.resize(target, None);
+        } else if is_instruction_line(line) {
+            source_lines.push(Some(line));
+        }
+    }
+
+    // Only trust the warnings if the source is what was compiled
+    let (source_map, warnings) = match compile_program(&source_text.join("\n")) {
+        Ok(program) if program.instructions.len() == compiled_text.len() => {
+            (program.source_map, program.warnings)
+        }
+        _ => (Vec::new(), Vec::new()),
+    };
+    let mut warnings = warnings.into_iter().peekable();
+
+    let mut contents = format!("{:>4}  {:19}  {}\n", "ADDR", "BINARY", "SOURCE");
+
+    for (index, instruction) in compiled_text.iter().enumerate() {
+        let source = match source_lines.get(index) {
+            Some(Some(line)) => line.to_string(),
+            _ => format!("{} {}", index, instruction),
+        };
+
+        contents = format!(
+            "{}{:>4}  {:19}  {}\n",
+            contents,
+            index,
+            instruction.as_binary(),
+            source.trim_end()
         );
 
-        println!();
+        // A label on its own line names the next instruction, so any
+        // warning on a line before this one goes here too
+        let line = source_map.get(index).copied().unwrap_or(0);
+        while let Some(warning) = warnings.next_if(|warning| warning.line <= line) {
+            contents = format!("{}{}", contents, listing_warning(&warning));
+        }
+    }
 
-        let file_path: &str = matches.value_of("input").unwrap().trim_start_matches(".\\");
+    for warning in warnings {
+        contents = format!("{}{}", contents, listing_warning(&warning));
+    }
 
-        if matches.value_of("autograder").is_some() {
-            println!("{}\n", "AutoGrader Mode Enabled".bold().on_green());
-            let path = file_path.trim_matches(&['\\', '/'] as &[_]);
-            let mut autograder = AutoGrader::new_from_cmd(path, matches.value_of("autograder").unwrap());
-            autograder.grade_all();
-            autograder.print_results();
-            let export_result = autograder.export_results(path);
+    // The main program (at 0) is not called, so has no callers to warn
+    let subroutines: Vec<usize> = call_graph(compiled_text)
+        .entries
+        .into_iter()
+        .filter(|entry| *entry != 0)
+        .collect();
 
-            if export_result.is_err() {
-                println!("\n{}\n", "AutoGrader Export Failed".bold().on_red());
-            } else {
-                println!("\n{} {}\n", "AutoGrader Export Successful:".bold().on_green(), export_result.unwrap().bold());
-            }
-            exit(0);
+    if !subroutines.is_empty() {
+        contents = format!("{}\n{:>10}  {}\n", contents, "SUBROUTINE", "MODIFIES");
+    }
+
+    for entry in subroutines {
+        let registers: Vec<String> = clobbered_registers(compiled_text, entry)
+            .iter()
+            .map(|register| format!("r{}", register))
+            .collect();
+
+        contents = format!("{}{:>10}  {}\n", contents, entry, registers.join(", "));
+    }
+
+    contents
+}
+
+/// Function to format a warning as a line of a listing, lined
+/// up under the source column
+fn listing_warning(warning: &Diagnostic) -> String {
+    format!(
+        "{:>4}  {:19}  {}: {}\n",
+        "",
+        "",
+        warning.severity.as_str(),
+        warning.message
+    )
+}
+
+/// Function to write a listing (.lst) file of the program
+pub fn write_listing(
+    path: &str,
+    compiled_text: &[Instruction],
+    source_text: &[String],
+) -> std::io::Result<()> {
+    write_file(path, as_listing(compiled_text, source_text).as_str())
+}
+
+/// Function to format a program's control-flow graph as GraphViz
+/// DOT, with a node for each basic block and an edge for each way
+/// control can flow between them
+pub fn as_cfg_dot(compiled_text: &[Instruction]) -> String {
+    let mut contents = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+    let blocks = basic_blocks(compiled_text);
+
+    for block in blocks.iter() {
+        let mut label = String::new();
+        for address in block.start..=block.end {
+            label = format!("{}{}: {}\\l", label, address, compiled_text[address]);
         }
 
-        // Setup the vec for the compiled Instructions
-        let compiled_text: Vec<Instruction>;
+        contents = format!("{}    b{} [label=\"{}\"];\n", contents, block.start, label);
+    }
 
-        // Check to see what type of file is being loaded
-        if file_path.ends_with(UNCOMPILED) {
-            // If it's uncompiled, load it
-            let uncompiled_text = load_file(file_path).unwrap();
+    for block in blocks.iter() {
+        for successor in block.successors.iter() {
+            contents = format!("{}    b{} -> b{};\n", contents, block.start, successor);
+        }
+    }
 
-            // Then, compile it into Instruction structs
-            let compile_result = Simulator::compile_hmmm(uncompiled_text, false);
+    contents.push_str("}\n");
 
-            if compile_result.is_err() {
-                exit(compile_result.unwrap_err().as_code())
-            } else {
-                compiled_text = compile_result.unwrap();
-            }
-        } else if file_path.ends_with(COMPILED) {
-            // If it's already compiled, load it
-            let raw_binary = load_file(file_path).unwrap();
+    contents
+}
 
-            // Then, interpret it into Instruction structs
-            compiled_text = read_compiled_hmmm(raw_binary);
+/// Function to format a program's call graph as GraphViz DOT,
+/// with a node for each subroutine and an edge for each call
+pub fn as_callgraph_dot(compiled_text: &[Instruction]) -> String {
+    let mut contents = String::from("digraph callgraph {\n");
+
+    let graph = call_graph(compiled_text);
+
+    for entry in graph.entries.iter() {
+        let label = if *entry == 0 {
+            String::from("main")
         } else {
-            panic!("Unknown filetype!");
+            format!("sub {}", entry)
+        };
+
+        contents = format!("{}    n{} [label=\"{}\"];\n", contents, entry, label);
+    }
+
+    for (caller, callee) in graph.calls.iter() {
+        contents = format!("{}    n{} -> n{};\n", contents, caller, callee);
+    }
+
+    contents.push_str("}\n");
+
+    contents
+}
+
+/// Function to write a program's call graph as a DOT file
+pub fn write_callgraph_dot(path: &str, compiled_text: &[Instruction]) -> std::io::Result<()> {
+    write_file(path, as_callgraph_dot(compiled_text).as_str())
+}
+
+/// Function to write a program's control-flow graph as a DOT file
+pub fn write_cfg_dot(path: &str, compiled_text: &[Instruction]) -> std::io::Result<()> {
+    write_file(path, as_cfg_dot(compiled_text).as_str())
+}
+
+/// Function to write text to a file, or to stdout if the
+/// path is "-"
+pub fn write_file(path: &str, contents: &str) -> std::io::Result<()> {
+    write_bytes(path, contents.as_bytes())
+}
+
+/// Function to write bytes to a file, or to stdout if the
+/// path is "-"
+///
+/// Paths ending in .gz are compressed as they are written
+pub fn write_bytes(path: &str, contents: &[u8]) -> std::io::Result<()> {
+    if path == STDIO_PATH {
+        let mut stdout = io::stdout();
+        stdout.write_all(contents)?;
+        stdout.flush()?;
+    } else if path.ends_with(GZIP) {
+        let mut encoder = GzEncoder::new(File::create(path)?, Compression::default());
+        encoder.write_all(contents)?;
+        encoder.finish()?;
+    } else {
+        fs::write(path, contents)?;
+    }
+    Ok(())
+}
+
+/// Function to get the version shown by --version: the crate
+/// version, the git commit it was built from (if known), and
+/// how many instructions are supported
+pub fn version_string() -> String {
+    let git_hash = option_env!("HMMM_GIT_HASH").unwrap_or("unknown");
+
+    format!(
+        "{} (git {}, {} instructions)",
+        env!("CARGO_PKG_VERSION"),
+        git_hash,
+        InstructionSet::builtin().instruction_count()
+    )
+}
+
+/// Function to print the HMMM startup banner
+pub fn print_startup_banner() {
+    println!(
+        "{} ████    ████  ████    ████",
+        "██    ██  ████    ████ ".yellow()
+    );
+    println!(
+        "{} ██ ██  ██ ██  ██ ██  ██ ██",
+        "██    ██  ██ ██  ██ ██ ".yellow()
+    );
+    println!(
+        "{} ██  ████  ██  ██  ████  ██",
+        "████████  ██  ████  ██ ".yellow()
+    );
+    println!(
+        "{} ██   ██   ██  ██   ██   ██",
+        "██    ██  ██   ██   ██ ".yellow()
+    );
+    println!(
+        "{} ██        ██  ██        ██",
+        "██    ██  ██        ██ ".yellow()
+    );
+    println!(
+        "{}",
+        " HARVEY       MUDD       MINIATURE      MACHINE   "
+            .black()
+            .dimmed()
+            .italic()
+            .bold()
+            .on_white()
+    );
+
+    println!();
+}
+
+/// Function to format a single row of the compile summary table
+fn format_summary_row(index: usize, instruction: &Instruction) -> String {
+    format!(
+        "█ {:4} █ {:7} █ {:19} █  >>    {}\n",
+        index,
+        instruction.instruction_type.names[0],
+        instruction.arguments().join(", "),
+        instruction.as_binary()
+    )
+}
+
+/// Function to format the table of compiled instructions shown
+/// after a successful compile. Unless verbose, only the first 10
+/// lines are shown, with the last being shown also if there are more
+pub fn format_compile_summary(compiled_text: &[Instruction], verbose: bool) -> String {
+    let mut contents = String::from("▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀\n");
+    contents.push_str("█ Line █ Command █ Arguments           █\n");
+    contents.push_str("▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄\n");
+
+    for (index, line) in compiled_text.iter().enumerate() {
+        if index > 9 && !verbose {
+            // Print seperator to show the jump in line number
+            contents.push_str("........................................\n");
+            contents.push_str(
+                format_summary_row(compiled_text.len() - 1, compiled_text.last().unwrap()).as_str(),
+            );
+            break;
         }
-        // If compiles without error, print out a success
-        // message and the first 9 lines, with the last being
-        // printed also if there are > 9 lines
-        println!("{}", "▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀".yellow());
-        println!(
-            "{}{}{}",
-            "████".yellow(),
-            "     COMPILATION SUCCESSFUL     ".green().bold(),
-            "████".yellow()
+        contents.push_str(format_summary_row(index, line).as_str());
+    }
+
+    contents.push_str("█▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄█\n");
+
+    contents
+}
+
+/// Function to format the success message and compiled program
+/// shown after compiling, which is nothing at all when quiet
+pub fn format_compile_report(compiled_text: &[Instruction], verbose: bool, quiet: bool) -> String {
+    if quiet {
+        return String::new();
+    }
+
+    format!(
+        "{}\n\n{}\n\n",
+        format_banner("COMPILATION SUCCESSFUL", true),
+        format_compile_summary(compiled_text, verbose)
+    )
+}
+
+/// Function to print a success message and the compiled
+/// program, see format_compile_summary
+pub fn print_compile_summary(compiled_text: &[Instruction], verbose: bool) {
+    print!("{}", format_compile_report(compiled_text, verbose, false));
+}
+
+/// Returns the process exit code for an error returned from main,
+/// or None if it is not a CompileErr or RuntimeErr (which have
+/// already been pretty-printed by the time they are returned)
+pub fn hmmm_exit_code(error: &(dyn std::error::Error + 'static)) -> Option<i32> {
+    if let Some(compile_err) = error.downcast_ref::<CompileErr>() {
+        Some(compile_err.as_code())
+    } else if let Some(runtime_err) = error.downcast_ref::<RuntimeErr>() {
+        Some(runtime_err.as_code())
+    } else {
+        None
+    }
+}
+
+/// Function to get the process exit code for a halted program,
+/// which is the value of the given register clamped into 0..=255,
+/// or 0 if no register is given
+pub fn halt_exit_code(sim: &Simulator, exit_register: Option<usize>) -> i32 {
+    exit_register
+        .and_then(|register| sim.get_register(register))
+        .map_or(0, |value| value.clamp(0, 255) as i32)
+}
+
+/// Function to compile source, showing a progress bar on stderr
+/// while compiling large programs if it is a terminal
+pub fn compile_with_progress_bar(
+    uncompiled_text: Vec<String>,
+    is_headless: bool,
+) -> Result<Vec<Instruction>, CompileErr> {
+    if is_headless
+        || uncompiled_text.len() < PROGRESS_BAR_THRESHOLD
+        || !io::stderr().is_terminal()
+    {
+        return Simulator::compile_hmmm(uncompiled_text, is_headless);
+    }
+
+    Simulator::compile_hmmm_with_progress(uncompiled_text, is_headless, |done, total| {
+        let filled = done * PROGRESS_BAR_WIDTH / total;
+
+        eprint!(
+            "\rCompiling [{}{}] {}/{} lines",
+            "=".repeat(filled),
+            " ".repeat(PROGRESS_BAR_WIDTH - filled),
+            done,
+            total
         );
-        println!("{}", "▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄".yellow());
-        println!("\n");
-        println!("▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀");
-        println!("█ Line █ Command █ Arguments           █");
-        println!("▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄");
-
-        for (index, line) in compiled_text.iter().enumerate() {
-            if index > 9 {
-                // Print seperator to show the jump in line number
-                println!("........................................");
-                let last = compiled_text.last().unwrap();
-                println!(
-                    "█ {:4} █ {:7} █ {:19} █  >>    {}",
-                    compiled_text.len() - 1,
-                    last.instruction_type.names[0],
-                    last.text_contents,
-                    last.binary_contents.join(" ")
-                );
-                break;
-            }
-            println!(
-                "█ {:4} █ {:7} █ {:19} █  >>    {}",
-                index,
-                line.instruction_type.names[0],
-                line.text_contents,
-                line.binary_contents.join(" ")
-            );
+
+        // Finish the line, so errors print below the bar
+        if done == total {
+            eprintln!();
+        }
+    })
+}
+
+/// Function to load and compile the program at the given paths
+/// like load_program, without keeping the source text. A single
+/// .hmmm file is compiled line by line as it is read
+pub fn load_program_streaming(
+    input_paths: &[&str],
+    is_headless: bool,
+) -> Result<Vec<Instruction>, Box<dyn std::error::Error>> {
+    match input_paths {
+        [path] if *path == STDIO_PATH || without_gzip(path).ends_with(UNCOMPILED) => {
+            Simulator::compile_hmmm_streaming(open_file(path)?, is_headless)
+        }
+        _ => Ok(load_program(input_paths, is_headless)?.0),
+    }
+}
+
+/// Function to load and compile the program at the given paths,
+/// returning its instructions along with the source text, which
+/// is empty for .hb files. Several .hmmm paths are linked together
+pub fn load_program(
+    input_paths: &[&str],
+    is_headless: bool,
+) -> Result<(Vec<Instruction>, Vec<String>), Box<dyn std::error::Error>> {
+    let file_path = match input_paths.first() {
+        Some(path) => *path,
+        None => return Err("Please specify a file to compile/run!".into()),
+    };
+
+    // Compressed files are read as whatever they contain
+    let file_type = without_gzip(file_path);
+
+    if input_paths.len() > 1 {
+        // Multiple files can only be linked before compiling
+        if input_paths
+            .iter()
+            .any(|path| *path != STDIO_PATH && !without_gzip(path).ends_with(UNCOMPILED))
+        {
+            return Err("Only .hmmm files can be linked together!".into());
         }
 
-        println!("█▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄█▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄█\n\n");
+        let uncompiled_text = link_files(input_paths)?;
+        let compiled_text = compile_with_progress_bar(uncompiled_text.clone(), is_headless)?;
 
-        // Output file if given path
-        if matches.value_of("output").is_some() {
-            let output_file = matches.value_of("output").unwrap();
-            let result;
+        Ok((compiled_text, uncompiled_text))
+    } else if file_path == STDIO_PATH || file_type.ends_with(UNCOMPILED) {
+        // If it's uncompiled, load it
+        let uncompiled_text = load_file(file_path)?;
 
-            if output_file.ends_with(UNCOMPILED) {
-                result = write_uncompiled_hmmm(output_file, compiled_text.clone());
-            } else if output_file.ends_with(COMPILED) {
-                result = write_compiled_hmmm(output_file, compiled_text.clone());
-            } else {
-                println!("No output type specified, writing as binary...");
-                // If no ending, just tack on a .hb extension and write out as binary
-                result = write_compiled_hmmm(
-                    format!("{}.hb", output_file).as_str(),
-                    compiled_text.clone(),
-                );
+        // Then, compile it into Instruction structs
+        let compiled_text = compile_with_progress_bar(uncompiled_text.clone(), is_headless)?;
+
+        Ok((compiled_text, uncompiled_text))
+    } else if file_type.ends_with(BASE64_COMPILED) {
+        let encoded = load_file(file_path)?;
+
+        Ok((read_base64_hmmm(encoded.concat().as_str())?, Vec::new()))
+    } else if file_type.ends_with(COMPILED) {
+        // If it's already compiled, load it
+        let raw_binary = load_file(file_path)?;
+
+        // Then, interpret it into Instruction structs
+        Ok((read_compiled_hmmm(raw_binary)?, Vec::new()))
+    } else {
+        Err("Unknown filetype!".into())
+    }
+}
+
+/// Function to print a diagnostic in the given file, either
+/// for a person to read or as a line of JSON for an editor
+pub fn print_diagnostic(file: &str, diagnostic: &Diagnostic, message_format: MessageFormat) {
+    match message_format {
+        MessageFormat::Human => println!("{}:{}", file, diagnostic),
+        MessageFormat::Json => println!("{}", diagnostic.to_json(file)),
+    }
+}
+
+/// Function to compile the program at the given paths without
+/// running it or writing any output, printing every error and
+/// warning found. Returns the exit code, which is 1 on any error
+pub fn check_program(
+    input_paths: &[&str],
+    message_format: MessageFormat,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let source_name = input_paths.join(", ");
+
+    // Binaries have no source to point into, so are just decoded
+    if input_paths
+        .iter()
+        .any(|path| *path != STDIO_PATH && !without_gzip(path).ends_with(UNCOMPILED))
+    {
+        return match load_program(input_paths, true) {
+            Ok(_) => Ok(0),
+            Err(error) => {
+                let diagnostic = Diagnostic {
+                    line: 1,
+                    column: 1,
+                    severity: Severity::Error,
+                    message: error.to_string(),
+                };
+                print_diagnostic(&source_name, &diagnostic, message_format);
+                Ok(1)
             }
+        };
+    }
 
-            if result.is_err() {
-                println!("Error writing output file! Continuing...");
+    let source = link_files(input_paths)?.join("\n");
+
+    match compile_program(&source) {
+        Ok(program) => {
+            for warning in program.warnings.iter() {
+                print_diagnostic(&source_name, warning, message_format);
             }
+            Ok(0)
         }
-
-        // Run simulation if --no-run flag is not present
-        if !matches.is_present("no-run") {
-            // Create it as new struct from compiled HMMM
-            let mut simulator = Simulator::new(compiled_text);
-            let debug_multiplier = matches
-                .value_of("speed")
-                .unwrap_or("1")
-                .parse::<f64>()
-                .unwrap_or(1.0);
-
-            if matches.is_present("debug") {
-                println!("{}", "ENTERING DEBUGGING MODE...".on_red());
-                simulator.set_debug(true);
-                thread::sleep(time::Duration::from_millis(
-                    (200) as u64,
-                ));
-                terminal.act(Action::ClearTerminal(Clear::All))?;
-                terminal.act(Action::DisableBlinking)?;
-                terminal.act(Action::HideCursor)?;
+        Err(diagnostics) => {
+            for diagnostic in diagnostics.iter() {
+                print_diagnostic(&source_name, diagnostic, message_format);
             }
+            Ok(1)
+        }
+    }
+}
 
-            loop {
-                if simulator.is_debug() {
-                    print_debug_screen(&mut simulator)?;
-                    if debug_multiplier == 0. {
-                        let mut line = String::new();
-                        io::stdin().read_line(&mut line).unwrap();
-                    } else {
-                        thread::sleep(time::Duration::from_millis(
-                            (500. / debug_multiplier) as u64,
-                        ));
-                    }
-                }
-                // Attempt to run a step in the simulator
-                let result = &simulator.step();
-                // If it's an error, raise it
-                if result.is_err() {
-                    // Don't trap the user without a cursor,
-                    // make sure to show it on exit
-                    // Hopefully the program doesn't hard crash because if it does,
-                    // the cursor might not be visible
-                    terminal.act(Action::ShowCursor)?;
-                    let result_err = result.as_ref().unwrap_err();
-                    // If the error is Halt, exit quietly, as that is the
-                    // program successfully finishing
-                    if result_err == &RuntimeErr::Halt {
-                        if simulator.is_debug() {
-                            terminal.act(Action::MoveCursorTo(0, 31))?;
-                        }
-
-                        println!(
-                            "{}",
-                            "Program has reached end, exiting...".black().on_green()
-                        );
-
-                        exit(0);
-                    } else {
-                        // If not, raise that error!
-                        terminal.act(Action::ClearTerminal(Clear::All))?;
-                        // Prints out the debug screen as well as the the error
-                        raise_runtime_error(&simulator, result_err);
-                        let exit_code = &result_err.as_code();
-
-                        // Move the terminal prompt to the bottom of the screen
-                        for _ in 0..16 {
-                            println!("\n");
-                        }
-                        exit(*exit_code);
-                    }
-                }
-            }
+/// Function to parse a --set-reg value (eg: "r3=42") into
+/// a register from r1 to r15 and a 16-bit signed value
+pub fn parse_register_preset(text: &str) -> Result<(u8, i16), String> {
+    let (register, value) = split_preset(text)?;
+    let register = parse_writable_register(register)
+        .ok_or_else(|| format!("register in \"{}\" must be one of r1..r15", text))?;
+
+    Ok((register, parse_preset_value(text, value)?))
+}
+
+/// Returns the number of a register from r1 to r15 (eg: "r15"),
+/// as r0 cannot be written
+fn parse_writable_register(name: &str) -> Option<u8> {
+    name.trim_start_matches(|c| c == 'r' || c == 'R')
+        .parse::<u8>()
+        .ok()
+        .filter(|register| (1..=15).contains(register))
+}
+
+/// Function to parse a --set-mem value (eg: "10=7") into an
+/// address from 0 to 255 and a 16-bit signed value
+pub fn parse_memory_preset(text: &str) -> Result<(u8, i16), String> {
+    let (address, value) = split_preset(text)?;
+    let address = address
+        .parse::<u8>()
+        .map_err(|_| format!("address in \"{}\" must be between 0 and 255", text))?;
+
+    Ok((address, parse_preset_value(text, value)?))
+}
+
+/// Function to parse a --memory-size value, which must leave room
+/// for at least one word and fit in 8-bit addresses
+pub fn parse_memory_size(text: &str) -> Result<usize, String> {
+    text.trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|size| (1..=MEMORY_WORDS).contains(size))
+        .ok_or_else(|| format!("memory size must be between 1 and {}", MEMORY_WORDS))
+}
+
+fn split_preset(text: &str) -> Result<(&str, &str), String> {
+    let mut parts = text.splitn(2, '=').map(str::trim);
+
+    match (parts.next(), parts.next()) {
+        (Some(target), Some(value)) => Ok((target, value)),
+        _ => Err(format!("\"{}\" should be written as target=value", text)),
+    }
+}
+
+fn parse_preset_value(text: &str, value: &str) -> Result<i16, String> {
+    value
+        .parse::<i16>()
+        .map_err(|_| format!("value in \"{}\" must be between -32768 and 32767", text))
+}
+
+/// Function to parse a --data-file into the memory words it sets.
+/// Each line is an address followed by the values for it and the
+/// addresses after it (eg: "100 3 4 5"), and # starts a comment
+pub fn parse_data_file(text: &str) -> Result<Vec<(u8, i16)>, String> {
+    let mut presets = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut parts = line.split_whitespace();
+        let address = match parts.next() {
+            Some(address) => address,
+            None => continue,
+        };
+
+        let address = address.parse::<u8>().map_err(|_| {
+            format!("line {}: address must be between 0 and 255", index + 1)
+        })?;
+        let values = parts
+            .map(|value| value.parse::<i16>())
+            .collect::<Result<Vec<i16>, _>>()
+            .map_err(|_| {
+                format!("line {}: values must be between -32768 and 32767", index + 1)
+            })?;
+
+        if values.is_empty() {
+            return Err(format!("line {}: address {} has no values", index + 1, address));
+        }
+        if address as usize + values.len() > MEMORY_WORDS {
+            return Err(format!("line {}: values run past the end of memory", index + 1));
         }
-        Ok(())
+
+        for (offset, value) in values.into_iter().enumerate() {
+            presets.push((address + offset as u8, value));
+        }
+    }
+
+    Ok(presets)
+}
+
+/// Function to parse a --cycle-costs file into the cycles each
+/// instruction takes. Each line is an instruction name or alias
+/// followed by its cycles (eg: "mul 10"), and # starts a comment
+pub fn parse_cycle_costs(text: &str) -> Result<Vec<(&'static str, u64)>, String> {
+    let mut costs = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let (name, cycles) = match parts.as_slice() {
+            [] => continue,
+            [name, cycles] => (*name, *cycles),
+            _ => return Err(format!("line {}: expected a name and cycles", index + 1)),
+        };
+
+        let instruction_type = InstructionType::from_name(name)
+            .ok_or_else(|| format!("line {}: unknown instruction \"{}\"", index + 1, name))?;
+        let cycles = cycles
+            .parse::<u64>()
+            .map_err(|_| format!("line {}: cycles must be a whole number", index + 1))?;
+
+        costs.push((instruction_type.names[0], cycles));
     }
+
+    Ok(costs)
 }
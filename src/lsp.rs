@@ -0,0 +1,281 @@
+use super::diagnostics::*;
+use super::simulator::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+
+// JSON-RPC error code for a request the server does not handle
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Function to run a minimal language server over the given
+/// streams (stdin and stdout for an editor). It publishes the
+/// compiler's diagnostics whenever a document is opened, changed,
+/// or saved, describes instructions on hover, and finds where
+/// labels are defined
+pub fn run_lsp<R: BufRead, W: Write>(mut input: R, output: &mut W) -> io::Result<()> {
+    // Latest text of each open document, by URI
+    let mut documents: HashMap<String, String> = HashMap::new();
+
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or("")
+            .to_string();
+
+        match method {
+            "initialize" => {
+                let result = json!({
+                    "capabilities": {
+                        "textDocumentSync": {
+                            "openClose": true,
+                            "change": 1,
+                            "save": { "includeText": true },
+                        },
+                        "hoverProvider": true,
+                        "definitionProvider": true,
+                    },
+                    "serverInfo": { "name": "hmmm_rs", "version": env!("CARGO_PKG_VERSION") },
+                });
+                write_response(output, &message["id"], result)?;
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                documents.insert(uri.clone(), text.to_string());
+                publish_diagnostics(output, &uri, text)?;
+            }
+            "textDocument/didChange" => {
+                // Only full syncs are asked for, so the last change is the whole text
+                if let Some(change) = params["contentChanges"].as_array().and_then(|c| c.last()) {
+                    let text = change["text"].as_str().unwrap_or("");
+                    documents.insert(uri.clone(), text.to_string());
+                    publish_diagnostics(output, &uri, text)?;
+                }
+            }
+            "textDocument/didSave" => {
+                if let Some(text) = params["text"].as_str() {
+                    documents.insert(uri.clone(), text.to_string());
+                }
+                if let Some(text) = documents.get(&uri) {
+                    publish_diagnostics(output, &uri, text)?;
+                }
+            }
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+            }
+            "textDocument/hover" => {
+                let word = documents
+                    .get(&uri)
+                    .and_then(|text| word_at(text, &params["position"]));
+                let result = match word.as_deref().and_then(describe_instruction) {
+                    Some(description) => json!({
+                        "contents": { "kind": "markdown", "value": description },
+                    }),
+                    None => Value::Null,
+                };
+                write_response(output, &message["id"], result)?;
+            }
+            "textDocument/definition" => {
+                let result = documents
+                    .get(&uri)
+                    .and_then(|text| {
+                        let word = word_at(text, &params["position"])?;
+                        label_definition(text, &word)
+                    })
+                    .map_or(
+                        Value::Null,
+                        |line| json!({ "uri": uri, "range": line_range(line, 0, 0) }),
+                    );
+                write_response(output, &message["id"], result)?;
+            }
+            "shutdown" => write_response(output, &message["id"], Value::Null)?,
+            "exit" => return Ok(()),
+            _ => {
+                // Notifications have no id and need no reply
+                if !message["id"].is_null() {
+                    let error = json!({
+                        "jsonrpc": "2.0",
+                        "id": message["id"],
+                        "error": {
+                            "code": METHOD_NOT_FOUND,
+                            "message": format!("unknown method {}", method),
+                        },
+                    });
+                    write_message(output, &error)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Function to read one message, returning None once the
+/// editor closes the stream
+pub(crate) fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length: Option<usize> = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some(length) = header.strip_prefix("Content-Length:") {
+            content_length = length.trim().parse().ok();
+        }
+    }
+
+    let content_length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "message has no Content-Length")
+    })?;
+    let mut content = vec![0_u8; content_length];
+    input.read_exact(&mut content)?;
+
+    Ok(Some(serde_json::from_slice(&content)?))
+}
+
+pub(crate) fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()
+}
+
+fn write_response<W: Write>(output: &mut W, id: &Value, result: Value) -> io::Result<()> {
+    write_message(
+        output,
+        &json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+    )
+}
+
+/// Function to compile a document and send every error and
+/// warning found, or an empty list to clear old ones
+fn publish_diagnostics<W: Write>(output: &mut W, uri: &str, text: &str) -> io::Result<()> {
+    let diagnostics = match compile_program(text) {
+        Ok(program) => program.warnings,
+        Err(diagnostics) => diagnostics,
+    };
+    let lines: Vec<&str> = text.lines().collect();
+
+    let diagnostics: Vec<Value> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            // Underline from the column to the end of the line, with both
+            // converted from bytes to the UTF-16 units editors count in
+            let line = lines.get(diagnostic.line - 1).copied().unwrap_or("");
+            let start = utf16_offset(line, diagnostic.column - 1);
+            let end = utf16_offset(line, line.len());
+            let severity = match diagnostic.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+            };
+
+            json!({
+                "range": line_range(diagnostic.line - 1, start, end),
+                "severity": severity,
+                "source": "hmmm",
+                "message": diagnostic.message,
+            })
+        })
+        .collect();
+
+    write_message(
+        output,
+        &json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }),
+    )
+}
+
+/// Returns an LSP range on one line (counting from 0)
+fn line_range(line: usize, start: usize, end: usize) -> Value {
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": end.max(start) },
+    })
+}
+
+/// Returns the number of UTF-16 units (what LSP positions count)
+/// before a byte offset in a line
+fn utf16_offset(line: &str, byte_offset: usize) -> usize {
+    line.get(..byte_offset)
+        .unwrap_or(line)
+        .encode_utf16()
+        .count()
+}
+
+/// Returns the byte offset of an LSP character position (counted in
+/// UTF-16 units) in a line, clamped to the end of the line
+fn byte_offset(line: &str, utf16_offset: usize) -> usize {
+    let mut units = 0;
+
+    for (index, c) in line.char_indices() {
+        if units >= utf16_offset {
+            return index;
+        }
+        units += c.len_utf16();
+    }
+
+    line.len()
+}
+
+/// Returns the word (eg: an instruction or label name) under
+/// an LSP position, if there is one
+fn word_at(text: &str, position: &Value) -> Option<String> {
+    let line = text.lines().nth(position["line"].as_u64()? as usize)?;
+    let character = byte_offset(line, position["character"].as_u64()? as usize);
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let start = line[..character]
+        .rfind(|c: char| !is_word(c))
+        .map_or(0, |index| index + 1);
+    let end = line[character..]
+        .find(|c: char| !is_word(c))
+        .map_or(line.len(), |index| character + index);
+
+    if start < end {
+        Some(line[start..end].to_lowercase())
+    } else {
+        None
+    }
+}
+
+/// Returns a markdown description of the instruction with
+/// the given name or alias, if there is one
+fn describe_instruction(name: &str) -> Option<String> {
+    let instruction_set = InstructionSet::builtin();
+    let instruction_type = instruction_set.by_name(name)?;
+
+    if instruction_type.names[0] == "data" {
+        return None;
+    }
+
+    Some(format!(
+        "**{}**\n\n{}",
+        instruction_type.names.join(", "),
+        instruction_type.human_explanation
+    ))
+}
+
+/// Returns the line (counting from 0) defining a label
+fn label_definition(text: &str, name: &str) -> Option<usize> {
+    text.lines()
+        .position(|line| {
+            (is_label_line(line) && label_name(line) == name)
+                || split_inline_label(line).0.as_deref() == Some(name)
+        })
+}
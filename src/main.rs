@@ -1,6 +1,19 @@
-
 use std::process::*;
 fn main() {
-    hmmm_rs::main().unwrap();
-    exit(0);
-}
\ No newline at end of file
+    match hmmm_rs::main() {
+        Ok(exit_code) => exit(exit_code),
+        Err(err) => {
+            // Compile and runtime errors have already been printed,
+            // so only report anything else
+            let exit_code = match hmmm_rs::hmmm_exit_code(err.as_ref()) {
+                Some(code) => code,
+                None => {
+                    println!("Error: {}", err);
+                    1
+                }
+            };
+
+            exit(exit_code);
+        }
+    }
+}
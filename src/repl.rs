@@ -0,0 +1,110 @@
+use super::simulator::*;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+static PROMPT: &str = "hmmm> ";
+
+// Stops "run" from hanging on a program that never halts
+const REPL_STEP_LIMIT: u64 = 100_000;
+
+/// Function to run the interactive assembler. Each line is either
+/// an instruction without its line number, which is numbered and
+/// has its binary printed straight away, or one of these commands:
+///
+/// run [inputs...]  runs the program so far, reading the inputs
+/// list             prints every instruction so far
+/// clear            starts a new program
+/// quit             exits
+pub fn run_repl<R: BufRead, W: Write>(input: R, output: &mut W) -> io::Result<()> {
+    let mut program: Vec<Instruction> = Vec::new();
+
+    write!(output, "{}", PROMPT)?;
+    output.flush()?;
+
+    for line in input.lines() {
+        let line = line?;
+        let line_parts = split_source_line(line.trim(), &HashMap::new());
+
+        match line_parts
+            .first()
+            .map(|part| part.to_lowercase())
+            .as_deref()
+        {
+            None => {}
+            Some("quit") | Some("exit") => return Ok(()),
+            Some("list") => {
+                for (address, instruction) in program.iter().enumerate() {
+                    writeln!(
+                        output,
+                        "{:3}  {}  {}",
+                        address,
+                        instruction.as_binary(),
+                        instruction
+                    )?;
+                }
+            }
+            Some("clear") => program.clear(),
+            Some("run") => run_program(&program, &line_parts[1..], output)?,
+            Some(_) => {
+                let cleaned_line = line_parts.join(" ").to_lowercase();
+
+                // Report mistakes and carry on, nothing is added
+                match Instruction::new_from_text(&cleaned_line) {
+                    Ok(instruction) => {
+                        writeln!(output, "{:3}  {}", program.len(), instruction.as_binary())?;
+                        program.push(instruction);
+                    }
+                    Err(error) => writeln!(output, "error: {}", error)?,
+                }
+            }
+        }
+
+        write!(output, "{}", PROMPT)?;
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Function to run the program typed so far with the given
+/// inputs, printing what it writes and how it stopped
+fn run_program<W: Write>(
+    program: &[Instruction],
+    inputs: &[String],
+    output: &mut W,
+) -> io::Result<()> {
+    if program.is_empty() {
+        return writeln!(output, "error: nothing to run yet");
+    }
+
+    let inputs: Result<Vec<i16>, _> = inputs.iter().map(|input| input.parse::<i16>()).collect();
+    let inputs = match inputs {
+        Ok(inputs) => inputs,
+        Err(_) => return writeln!(output, "error: inputs must be numbers"),
+    };
+
+    let simulator = Simulator::builder(program.to_vec())
+        .inputs(inputs)
+        .output_sink(OutputSink::Outputs)
+        .step_limit(REPL_STEP_LIMIT)
+        .build();
+    let mut simulator = match simulator {
+        Ok(simulator) => simulator,
+        Err(error) => return writeln!(output, "error: {}", error),
+    };
+
+    let result = loop {
+        if let Err(error) = simulator.step() {
+            break error;
+        }
+    };
+
+    for value in simulator.get_outputs() {
+        writeln!(output, "{}", value)?;
+    }
+
+    match result {
+        RuntimeErr::Halt => writeln!(output, "halted after {} steps", simulator.steps_taken),
+        error => writeln!(output, "error: {}", error),
+    }
+}
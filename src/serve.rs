@@ -0,0 +1,239 @@
+use super::as_compiled_hmmm;
+use super::diagnostics::*;
+use super::simulator::*;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+// Programs sent to /run are stopped after this many steps, so an
+// infinite loop cannot tie up the server
+const SERVE_STEP_LIMIT: u64 = 100_000;
+// Largest request body read, as anything bigger is not a program
+// and would otherwise be allocated whatever its size
+pub const MAX_BODY_BYTES: usize = 1 << 20;
+// Requests are answered one at a time, so a client that stops
+// sending is dropped after this long rather than holding up the rest
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A request read from an HTTP client
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+/// Why a request could not be read
+enum RequestErr {
+    /// The client hung up before sending the whole request
+    Incomplete,
+    /// The body is longer than MAX_BODY_BYTES
+    TooLarge,
+}
+
+/// Function to answer HTTP requests on the given listener until it
+/// fails, one request per connection. POST /compile takes
+/// {"source": ...} and returns the diagnostics and compiled binary,
+/// and POST /run also takes "inputs" and returns what the program
+/// wrote and its final state
+pub fn run_server(listener: &TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        // A client that hangs up early should not stop the server
+        if let Err(error) = handle_connection(stream?) {
+            println!("Error answering request: {}", error);
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_connection(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let request = read_request(&mut BufReader::new(&mut stream))?;
+
+    let (status, body) = match request {
+        Ok(request) => match String::from_utf8(request.body) {
+            Ok(body) => handle_request(&request.method, &request.path, &body),
+            Err(_) => (400, json!({ "error": "body is not UTF-8" })),
+        },
+        Err(RequestErr::Incomplete) => (400, json!({ "error": "request is incomplete" })),
+        Err(RequestErr::TooLarge) => (
+            413,
+            json!({ "error": format!("body is over {} bytes", MAX_BODY_BYTES) }),
+        ),
+    };
+
+    write_response(&mut stream, status, &body)
+}
+
+/// Function to read a request line, its headers, and a body of the
+/// length given by Content-Length, which is only read if it is at
+/// most MAX_BODY_BYTES
+fn read_request<R: BufRead>(input: &mut R) -> io::Result<Result<Request, RequestErr>> {
+    let mut request_line = String::new();
+    if input.read_line(&mut request_line)? == 0 {
+        return Ok(Err(RequestErr::Incomplete));
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Ok(Err(RequestErr::Incomplete)),
+    };
+
+    let mut content_length = 0;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(Err(RequestErr::Incomplete));
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_BYTES {
+        return Ok(Err(RequestErr::TooLarge));
+    }
+
+    let mut body = vec![0_u8; content_length];
+    input.read_exact(&mut body)?;
+
+    Ok(Ok(Request { method, path, body }))
+}
+
+fn write_response<W: Write>(output: &mut W, status: u16, body: &Value) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        413 => "Payload Too Large",
+        _ => "Method Not Allowed",
+    };
+    let content = body.to_string();
+
+    write!(
+        output,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        reason,
+        content.len(),
+        content
+    )?;
+    output.flush()
+}
+
+/// Function to answer a request to the API, returning the HTTP
+/// status and the JSON body to send back
+pub fn handle_request(method: &str, path: &str, body: &str) -> (u16, Value) {
+    if path != "/compile" && path != "/run" {
+        return (404, json!({ "error": format!("no such endpoint {}", path) }));
+    }
+    if method != "POST" {
+        return (405, json!({ "error": "only POST is supported" }));
+    }
+
+    let request: Value = match serde_json::from_str(body) {
+        Ok(request) => request,
+        Err(error) => return (400, json!({ "error": error.to_string() })),
+    };
+    let source = match request["source"].as_str() {
+        Some(source) => source,
+        None => return (400, json!({ "error": "expected a \"source\" string" })),
+    };
+
+    let program = match compile_program(source) {
+        Ok(program) => program,
+        Err(diagnostics) => {
+            return (
+                200,
+                json!({ "success": false, "diagnostics": diagnostics_json(&diagnostics) }),
+            )
+        }
+    };
+    let diagnostics = diagnostics_json(&program.warnings);
+
+    if path == "/compile" {
+        return (
+            200,
+            json!({
+                "success": true,
+                "diagnostics": diagnostics,
+                "binary": as_compiled_hmmm(&program.instructions),
+            }),
+        );
+    }
+
+    let inputs: Vec<i16> = request["inputs"]
+        .as_array()
+        .map(|inputs| {
+            inputs
+                .iter()
+                .filter_map(|input| input.as_i64())
+                .map(|input| input as i16)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let simulator = Simulator::builder(program.instructions)
+        .inputs(inputs)
+        .output_sink(OutputSink::Outputs)
+        .step_limit(SERVE_STEP_LIMIT)
+        .build();
+    let mut simulator = match simulator {
+        Ok(simulator) => simulator,
+        Err(error) => return (400, json!({ "error": error.to_string() })),
+    };
+    simulator.headless = true;
+
+    let result = loop {
+        if let Err(error) = simulator.step() {
+            break error;
+        }
+    };
+    let error = match result {
+        RuntimeErr::Halt => Value::Null,
+        error => json!(error.to_string()),
+    };
+
+    (
+        200,
+        json!({
+            "success": true,
+            "diagnostics": diagnostics,
+            "outputs": simulator.outputs,
+            "error": error,
+            "state": {
+                "pc": simulator.get_program_counter(),
+                "steps": simulator.steps_taken,
+                "registers": simulator.registers.to_vec(),
+                "memory": simulator.memory_words().collect::<Vec<i16>>(),
+            },
+        }),
+    )
+}
+
+fn diagnostics_json(diagnostics: &[Diagnostic]) -> Vec<Value> {
+    diagnostics
+        .iter()
+        .map(|diagnostic| {
+            json!({
+                "line": diagnostic.line,
+                "column": diagnostic.column,
+                "severity": diagnostic.severity.as_str(),
+                "message": diagnostic.message,
+            })
+        })
+        .collect()
+}
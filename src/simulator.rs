@@ -1,8 +1,11 @@
 use super::*;
 use lazy_static::lazy_static;
 use std::io;
-use std::io::stdin;
+use std::collections::HashMap;
 use std::io::BufRead;
+use std::sync::{mpsc, Arc, Mutex};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
 lazy_static! {
     static ref INSTRUCTION_LOOKUP: Vec<InstructionType> = vec![
@@ -12,192 +15,326 @@ lazy_static! {
             "1111 1111 1111 1111",
             "",
             "Halts the program"
-        ),
+        )
+        .with_description("Stops the program."),
         InstructionType::new(
             vec!["read"],
             "0000 0000 0000 0001",
             "1111 0000 1111 1111",
             "r",
             "Place 16-bit integer in register _",
-        ),
+        )
+        .with_description("Waits for the user to type a number, then stores it in rX."),
         InstructionType::new(
             vec!["write"],
             "0000 0000 0000 0010",
             "1111 0000 1111 1111",
             "r",
             "Print contents of register _"
-        ),
+        )
+        .with_description("Prints the number in rX."),
         InstructionType::new(
             vec!["jumpr", "jump"],
             "0000 0000 0000 0011",
             "1111 0000 1111 1111",
             "r",
             "Set program counter to address in register _"
-        ),
+        )
+        .with_description("Jumps to the address held in rX, often used to return from a subroutine."),
         InstructionType::new(
             vec!["setn"],
             "0001 0000 0000 0000",
             "1111 0000 0000 0000",
             "rs",
             "Set register _ equal to integer _"
-        ),
+        )
+        .with_description("Stores the number N, between -128 and 127, in rX."),
         InstructionType::new(
             vec!["loadn"],
             "0010 0000 0000 0000",
             "1111 0000 0000 0000",
             "ru",
             "Load register _ with contents of memory address _"
-        ),
+        )
+        .with_description("Copies the word at memory address N, between 0 and 255, into rX."),
         InstructionType::new(
             vec!["storen"],
             "0011 0000 0000 0000",
             "1111 0000 0000 0000",
             "ru",
             "Place contents of register _ into memory address _"
-        ),
+        )
+        .with_description("Copies the number in rX into memory address N, between 0 and 255."),
         InstructionType::new(
             vec!["loadr", "loadi", "load"],
             "0100 0000 0000 0000",
-            "1111 0000 0000 0000",
+            "1111 0000 0000 1111",
             "rr",
             "Load register _ with memory data indexed by register _"
-        ),
+        )
+        .with_description("Copies the word at the memory address held in rY into rX."),
         InstructionType::new(
             vec!["storer", "storei", "store"],
             "0100 0000 0000 0001",
-            "1111 0000 0000 0000",
+            "1111 0000 0000 1111",
             "rr",
             "Store register _ in memory indexed by register _"
-        ),
+        )
+        .with_description("Copies the number in rX into the memory address held in rY."),
         InstructionType::new(
             vec!["popr"],
             "0100 0000 0000 0010",
             "1111 0000 0000 1111",
             "rr",
             "Subtract 1 from the indexing register, then loadr"
-        ),
+        )
+        .with_description("Subtracts 1 from rY, then copies the word at the address it holds into rX, taking a value off a stack."),
         InstructionType::new(
             vec!["pushr"],
             "0100 0000 0000 0011",
             "1111 0000 0000 1111",
             "rr",
             "storer, then add 1 to the indexing register"
-        ),
+        )
+        .with_description("Copies the number in rX into the address held in rY, then adds 1 to rY, putting a value on a stack."),
         InstructionType::new(
             vec!["addn"],
             "0101 0000 0000 0000",
             "1111 0000 0000 0000",
             "rs",
             "Take register _ and add _ to it"
-        ),
+        )
+        .with_description("Adds the number N, between -128 and 127, to rX."),
         InstructionType::new(
             vec!["nop"],
             "0110 0000 0000 0000",
             "1111 1111 1111 1111",
             "",
             "Do nothing"
-        ),
+        )
+        .with_description("Does nothing, moving on to the next instruction."),
         InstructionType::new(
             vec!["copy", "mov"],
             "0110 0000 0000 0000",
             "1111 0000 0000 1111",
             "rr",
             "Set register _ = register _"
-        ),
+        )
+        .with_description("Copies the number in rY into rX."),
         InstructionType::new(
             vec!["add"],
             "0110 0000 0000 0000",
             "1111 0000 0000 0000",
             "rrr",
             "Set register _ = register _ + register _"
-        ),
+        )
+        .with_description("Adds the numbers in rY and rZ, storing the sum in rX."),
         InstructionType::new(
             vec!["neg"],
             "0111 0000 0000 0000",
             "1111 0000 1111 0000",
             "rzr",
             "Set register _ = - register _"
-        ),
+        )
+        .with_description("Stores the negative of the number in rY in rX."),
         InstructionType::new(
             vec!["sub"],
             "0111 0000 0000 0000",
             "1111 0000 0000 0000",
             "rrr",
             "Set register _ = register _ - register _"
-        ),
+        )
+        .with_description("Subtracts the number in rZ from the number in rY, storing the difference in rX."),
         InstructionType::new(
             vec!["mul"],
             "1000 0000 0000 0000",
             "1111 0000 0000 0000",
             "rrr",
             "Set register _ = register _ * register _"
-        ),
+        )
+        .with_description("Multiplies the numbers in rY and rZ, storing the product in rX."),
         InstructionType::new(
             vec!["div"],
             "1001 0000 0000 0000",
             "1111 0000 0000 0000",
             "rrr",
             "Set register _ = register _ // register _ (int. division)"
-        ),
+        )
+        .with_description("Divides the number in rY by the number in rZ, storing the whole number quotient in rX."),
         InstructionType::new(
             vec!["mod"],
             "1010 0000 0000 0000",
             "1111 0000 0000 0000",
             "rrr",
             "Set register _ = register _ % register _ (remainder of div.)"
-        ),
+        )
+        .with_description("Divides the number in rY by the number in rZ, storing the remainder in rX."),
         InstructionType::new(
             vec!["jumpn"],
             "1011 0000 0000 0000",
             "1111 1111 0000 0000",
             "zu",
             "Set program counter to address _"
-        ),
+        )
+        .with_description("Jumps to address N, between 0 and 255."),
         InstructionType::new(
             vec!["calln", "call"],
             "1011 0000 0000 0000",
             "1111 0000 0000 0000",
             "ru",
             "Copy address of next instruction into register _, and jump to address _"
-        ),
+        )
+        .with_description("Copies the address of the next instruction into rX, then jumps to address N, calling a subroutine."),
         InstructionType::new(
             vec!["jeqzn", "jeqz"],
             "1100 0000 0000 0000",
             "1111 0000 0000 0000",
             "ru",
             "If register _ == 0, jump to line _"
-        ),
+        )
+        .with_description("Jumps to address N if the number in rX is zero."),
         InstructionType::new(
             vec!["jnezn", "jnez"],
             "1101 0000 0000 0000",
             "1111 0000 0000 0000",
             "ru",
             "If register _ != 0, jump to line _"
-        ),
+        )
+        .with_description("Jumps to address N if the number in rX is not zero."),
         InstructionType::new(
             vec!["jgtzn", "jgtz"],
             "1110 0000 0000 0000",
             "1111 0000 0000 0000",
             "ru",
             "If register _ > 0, jump to line _"
-        ),
+        )
+        .with_description("Jumps to address N if the number in rX is greater than zero."),
         InstructionType::new(
             vec!["jltzn", "jltz"],
             "1111 0000 0000 0000",
             "1111 0000 0000 0000",
             "ru",
             "If register _ < 0, jump to line _"
-        ),
+        )
+        .with_description("Jumps to address N if the number in rX is less than zero."),
         InstructionType::new(
             vec!["data"],
             "0000 0000 0000 0000",
             "0000 0000 0000 0000",
             "n",
             "ERROR: DATA _"
-        ),
+        )
+        .with_description("A plain number stored in memory, rather than an instruction."),
     ]
     .into_iter()
     .collect();
+
+    /// The built-in instruction set, used unless another is given
+    static ref BUILTIN_INSTRUCTIONS: InstructionSet =
+        InstructionSet::new(INSTRUCTION_LOOKUP.clone());
+}
+
+/// A table of instruction types that programs are compiled and
+/// decoded with, so experimental instructions can be added
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct InstructionSet {
+    /// Types in the order words are matched against them,
+    /// ending with the catch-all data type
+    types: Vec<InstructionType>,
+    /// Lookup of every name and alias to its index in types, so
+    /// text can be compiled without scanning the whole table
+    names: HashMap<&'static str, usize>,
+}
+
+impl InstructionSet {
+    pub fn new(types: Vec<InstructionType>) -> Self {
+        let mut names: HashMap<&'static str, usize> = HashMap::new();
+
+        for (index, instruction_type) in types.iter().enumerate() {
+            for name in instruction_type.names.iter() {
+                names.entry(*name).or_insert(index);
+            }
+        }
+
+        InstructionSet { types, names }
+    }
+
+    /// Returns a copy of the built-in HMMM instruction set
+    pub fn builtin() -> Self {
+        BUILTIN_INSTRUCTIONS.clone()
+    }
+
+    /// Adds an instruction type, matched before the catch-all
+    /// data type so words using it decode to it
+    pub fn with_instruction(self, instruction_type: InstructionType) -> Self {
+        let mut types = self.types;
+        let data_index = types
+            .iter()
+            .position(|existing| existing.names[0] == "data")
+            .unwrap_or(types.len());
+
+        types.insert(data_index, instruction_type);
+
+        InstructionSet::new(types)
+    }
+
+    /// Returns how many instruction types there are,
+    /// not counting the catch-all data type
+    pub fn instruction_count(&self) -> usize {
+        self.types
+            .iter()
+            .filter(|instruction_type| instruction_type.names[0] != "data")
+            .count()
+    }
+
+    /// Finds the instruction type with the given name or alias
+    pub fn by_name(&self, name: &str) -> Option<&InstructionType> {
+        self.names.get(name).map(|index| &self.types[*index])
+    }
+
+    /// Finds the name or alias closest to a misspelled one, if
+    /// any is within a couple of edits of it. Ties go to an
+    /// instruction's main name over its aliases
+    pub fn closest_name(&self, name: &str) -> Option<&'static str> {
+        let name = name.to_lowercase();
+
+        self.names
+            .iter()
+            .filter(|(candidate, _)| **candidate != "data")
+            .map(|(candidate, index)| {
+                let is_alias = self.types[*index].names[0] != *candidate;
+                (edit_distance(&name, candidate), is_alias, *candidate)
+            })
+            .filter(|(distance, _, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+            .min()
+            .map(|(_, _, candidate)| candidate)
+    }
+
+    /// Describes a name that is not an instruction, suggesting the
+    /// closest one if there is one near enough
+    /// (eg: "unknown instruction "mull", did you mean "mul"?")
+    pub fn unknown_name_message(&self, name: &str) -> String {
+        match self.closest_name(name) {
+            Some(suggestion) => format!(
+                "unknown instruction \"{}\", did you mean \"{}\"?",
+                name, suggestion
+            ),
+            None => format!("unknown instruction \"{}\"", name),
+        }
+    }
+
+    /// Finds the first instruction type matching a machine word
+    pub fn by_word(&self, word: u16) -> Option<&InstructionType> {
+        self.types
+            .iter()
+            .find(|instruction| word & instruction.mask_word() == instruction.match_word())
+    }
+}
+
+impl Default for InstructionSet {
+    fn default() -> Self {
+        InstructionSet::builtin()
+    }
 }
 
 /// Struct for all instructions types, to make it easier to
@@ -230,9 +367,42 @@ pub struct InstructionType {
     /// in a human-readable format, with "_" signifying
     /// where an argument can be inserted
     pub human_explanation: &'static str,
+    /// Plain-English description of what the instruction
+    /// does, naming arguments as in operand_format
+    pub description: &'static str,
+}
+
+/// Instruction types are written as just their default name,
+/// as their other fields can always be looked up from it
+#[cfg(feature = "serde")]
+impl serde::Serialize for InstructionType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.names[0])
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for InstructionType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+
+        InstructionType::from_name(name.as_str()).ok_or_else(|| {
+            serde::de::Error::custom(format!("unknown instruction \"{}\"", name))
+        })
+    }
 }
 
 impl InstructionType {
+    /// Finds the instruction type with the given name or alias,
+    /// including "data" for plain numbers in memory
+    pub fn from_name(name: &str) -> Option<InstructionType> {
+        if name == "data" {
+            return Some(Instruction::new_blank_data().instruction_type);
+        }
+
+        BUILTIN_INSTRUCTIONS.by_name(name).cloned()
+    }
+
     pub fn new(
         names: Vec<&'static str>,
         match_string: &'static str,
@@ -246,8 +416,130 @@ impl InstructionType {
             mask_string,
             arguments,
             human_explanation,
+            description: "",
         }
     }
+
+    /// Sets the plain-English description shown by --explain
+    pub fn with_description(mut self, description: &'static str) -> Self {
+        self.description = description;
+        self
+    }
+
+    /// Returns the letter naming each argument, in order: X, Y,
+    /// and Z for registers, or N for a number
+    fn argument_letters(&self) -> Vec<char> {
+        let mut registers = ['X', 'Y', 'Z'].iter();
+
+        self.arguments
+            .chars()
+            .filter(|arg_type| *arg_type != 'z')
+            .map(|arg_type| match arg_type {
+                'r' => *registers.next().unwrap_or(&'W'),
+                _ => 'N',
+            })
+            .collect()
+    }
+
+    /// Returns how the instruction is written, with its
+    /// arguments named (eg: "add rX rY rZ")
+    pub fn operand_format(&self) -> String {
+        let mut format = String::from(self.names[0]);
+
+        for letter in self.argument_letters() {
+            format.push(' ');
+            if letter != 'N' {
+                format.push('r');
+            }
+            format.push(letter);
+        }
+
+        format
+    }
+
+    /// Returns the binary encoding, with the nibbles holding
+    /// each argument filled with its letter (eg: "0110 XXXX YYYY ZZZZ")
+    pub fn encoding_template(&self) -> String {
+        let mut nibbles: Vec<String> = self.match_string.split(' ').map(String::from).collect();
+        let mut open_slots = self.open_slots().into_iter();
+        let mut letters = self.argument_letters().into_iter();
+
+        for arg_type in self.arguments.chars().filter(|arg_type| *arg_type != 'z') {
+            let letter = letters.next().unwrap_or('?').to_string().repeat(4);
+            let width = match arg_type {
+                'r' => 1,
+                's' | 'u' => 2,
+                _ => 4,
+            };
+
+            for slot in open_slots.by_ref().take(width) {
+                nibbles[slot] = letter.clone();
+            }
+        }
+
+        nibbles.join(" ")
+    }
+
+    /// Returns the match string as a 16-bit word
+    pub fn match_word(&self) -> u16 {
+        binary_string_to_word(self.match_string)
+    }
+
+    /// Returns the mask string as a 16-bit word
+    pub fn mask_word(&self) -> u16 {
+        binary_string_to_word(self.mask_string)
+    }
+
+    /// Returns the nibble slots (0 to 3) left open by the
+    /// mask, where arguments are placed
+    pub fn open_slots(&self) -> Vec<usize> {
+        self.mask_string
+            .split(' ')
+            .enumerate()
+            .filter(|(_, nibble_mask)| *nibble_mask == "0000")
+            .map(|(slot, _)| slot)
+            .collect()
+    }
+
+    /// Returns how many cycles the instruction takes by default.
+    /// Multiplying and dividing are the slowest, then anything
+    /// that goes out to memory
+    pub fn default_cycles(&self) -> u64 {
+        match self.names[0] {
+            "mul" | "div" | "mod" => 4,
+            "loadn" | "storen" | "loadr" | "storer" | "popr" | "pushr" => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Returns the number of single character insertions, deletions,
+/// or substitutions needed to turn one string into another
+fn edit_distance(from: &str, to: &str) -> usize {
+    let to: Vec<char> = to.chars().collect();
+    let mut previous: Vec<usize> = (0..=to.len()).collect();
+
+    for (i, from_char) in from.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, to_char) in to.iter().enumerate() {
+            let substitution = previous[j] + (from_char != *to_char) as usize;
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[to.len()]
+}
+
+/// Converts a string of binary digits, ignoring spaces,
+/// into a 16-bit word
+fn binary_string_to_word(binary: &str) -> u16 {
+    binary
+        .chars()
+        .filter(|c| *c != ' ')
+        .fold(0, |word, c| (word << 1) | (c == '1') as u16)
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -263,6 +555,11 @@ pub enum CompileErr {
     CorruptedBinary,
     LineNumberNotPresent,
     InvalidLineNumber,
+    DuplicateLabel,
+    AddressOutOfRange,
+    OrgMovesBackward,
+    ProgramTooLarge,
+    LabelIsRegister,
 }
 
 impl CompileErr {
@@ -279,102 +576,126 @@ impl CompileErr {
             CompileErr::CorruptedBinary => 18,
             CompileErr::LineNumberNotPresent => 19,
             CompileErr::InvalidLineNumber => 20,
+            CompileErr::DuplicateLabel => 21,
+            CompileErr::AddressOutOfRange => 22,
+            CompileErr::OrgMovesBackward => 23,
+            CompileErr::ProgramTooLarge => 24,
+            CompileErr::LabelIsRegister => 25,
         }
     }
 }
 
+impl std::fmt::Display for CompileErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            CompileErr::InstructionDoesNotExist => "instruction does not exist",
+            CompileErr::InvalidArgumentType => "argument is the wrong type for this instruction",
+            CompileErr::InvalidRegister => "register index must be r0..r15",
+            CompileErr::TooManyArguments => "too many arguments for this instruction",
+            CompileErr::TooFewArguments => "too few arguments for this instruction",
+            CompileErr::InvalidSignedNumber => "signed number must be between -128 and 127",
+            CompileErr::InvalidUnsignedNumber => "unsigned number must be between 0 and 255",
+            CompileErr::InvalidNumber => "number must be a 16-bit decimal or hex value",
+            CompileErr::CorruptedBinary => "binary line is not a valid 16-bit word",
+            CompileErr::LineNumberNotPresent => "line does not start with a line number",
+            CompileErr::InvalidLineNumber => "line number is out of sequence",
+            CompileErr::DuplicateLabel => "label is defined more than once",
+            CompileErr::AddressOutOfRange => "address is past the end of the program",
+            CompileErr::OrgMovesBackward => ".org address is before code already placed",
+            CompileErr::ProgramTooLarge => "program does not fit in memory",
+            CompileErr::LabelIsRegister => "label is named like a register (eg: r1)",
+        };
+
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for CompileErr {}
+
 #[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Instruction {
     pub instruction_type: InstructionType,
     pub text_contents: String,
-    pub binary_contents: Vec<String>,
+    /// The 16-bit machine word of the instruction
+    pub word: u16,
 }
 
 impl Instruction {
     pub fn new_from_text(line_contents: &str) -> Result<Instruction, CompileErr> {
+        Instruction::new_from_text_in(line_contents, &BUILTIN_INSTRUCTIONS)
+    }
+
+    /// Compiles a line of text using the given instruction set
+    pub fn new_from_text_in(
+        line_contents: &str,
+        instruction_set: &InstructionSet,
+    ) -> Result<Instruction, CompileErr> {
         // Split on both "," and " "
         let contents_list: Vec<&str> = line_contents.split(' ').collect();
 
-        let mut instruction_type: Option<InstructionType> = None;
-
-        for instruction in INSTRUCTION_LOOKUP.clone() {
-            if instruction.names.contains(&contents_list[0]) {
-                instruction_type = Some(instruction);
-                break;
-            }
-        }
-
-        // First, check to make sure the instruction type exists in the lookup table
-        if instruction_type.is_none() {
-            return Err(CompileErr::InstructionDoesNotExist);
-        }
-
-        let instruction_type = instruction_type.unwrap();
+        // First, check to make sure the instruction type exists in the lookup table.
+        // It is only borrowed until the instruction is built, so is cloned once
+        let instruction_type = match instruction_set.by_name(contents_list[0]) {
+            Some(instruction_type) => instruction_type,
+            None => return Err(CompileErr::InstructionDoesNotExist),
+        };
 
-        let instruction_args: Vec<&str> = contents_list[1..].iter().map(|a| a as &str).collect();
+        let instruction_args = &contents_list[1..];
+        let required_args = instruction_type
+            .arguments
+            .chars()
+            .filter(|arg_type| *arg_type != 'z')
+            .count();
 
         // Second, check to see if the number of arguments match
         if instruction_args.len() > instruction_type.arguments.len() {
             return Err(CompileErr::TooManyArguments);
-        } else if instruction_args.len() < instruction_type.arguments.replace("z", "").len() {
+        } else if instruction_args.len() < required_args {
             return Err(CompileErr::TooFewArguments);
         } else if instruction_type.arguments.is_empty() {
             // If it's a single command, just return it
             return Ok(Instruction {
+                word: instruction_type.match_word(),
                 instruction_type: instruction_type.clone(),
-                text_contents: String::from(""),
-                binary_contents: instruction_type
-                    
-                    .match_string
-                    .split(' ')
-                    .map(String::from)
-                    .collect(),
+                text_contents: String::new(),
             });
         }
 
-        let mut text_contents: String = String::from(instruction_args[0]);
+        // Two arguments are split by a space, any other number by commas
+        let separator = if instruction_args.len() == 2 { " " } else { ", " };
+        let mut text_contents = String::with_capacity(line_contents.len() + instruction_args.len());
 
-        if instruction_args.len() == 2 {
-            text_contents = format!("{} {}", text_contents, instruction_args[1]);
-        } else {
-            for i in 0..(instruction_args.len() - 1) {
-                text_contents = format!("{}, {}", text_contents, instruction_args[1 + i]);
+        for (i, arg) in instruction_args.iter().enumerate() {
+            if i > 0 {
+                text_contents.push_str(separator);
             }
+            text_contents.push_str(arg);
         }
 
-        let instruction_chars = instruction_type.arguments.chars();
-
-        let mut binary_contents: Vec<String> = instruction_type
-            .match_string
-            .split(' ')
-            .map(String::from)
-            .collect();
-
-        let mut filled_slots: Vec<bool> = instruction_type
-            .mask_string
-            .split(' ')
-            .map(|a| {
-                a != "0000"
-            })
-            .collect();
+        let mut word = instruction_type.match_word();
+        let mut open_slots = instruction_type.open_slots().into_iter();
 
         // Third, check if instructions match the source instruction types
         let mut arg_to_get = 0;
-        for current_instruction_type in instruction_chars {
+        for current_instruction_type in instruction_type.arguments.chars() {
+            // "z" nibbles are already 0 in the match word
+            if current_instruction_type == 'z' {
+                continue;
+            }
+
             let arg = instruction_args[arg_to_get];
-            let slot_to_fill = filled_slots.iter().position(|a| !(*a)).unwrap();
-            let mut binary_string = String::from("");
-            filled_slots[slot_to_fill] = true;
+            arg_to_get += 1;
 
             if current_instruction_type == 'r' {
-                if arg.to_lowercase().starts_with('r') {
-                    let register_number = arg[1..].parse::<u8>();
-
-                    if register_number.is_err() {
-                        return Err(CompileErr::InvalidRegister);
-                    }
-
-                    binary_string = format!("{:04b}", register_number.unwrap());
+                if arg.starts_with('r') || arg.starts_with('R') {
+                    let register_number = match arg[1..].parse::<u8>() {
+                        Ok(register_number) if register_number <= 15 => register_number,
+                        _ => return Err(CompileErr::InvalidRegister),
+                    };
+
+                    let slot = open_slots.next().unwrap();
+                    word |= (register_number as u16) << nibble_shift(slot);
                 } else {
                     return Err(CompileErr::InvalidArgumentType);
                 }
@@ -385,159 +706,113 @@ impl Instruction {
                     return Err(CompileErr::InvalidSignedNumber);
                 }
 
-                binary_string = format!("{:08b}", number.unwrap());
+                let slot = open_slots.next().unwrap();
+                open_slots.next();
+                word |= (number.unwrap() as u8 as u16) << byte_shift(slot);
             } else if current_instruction_type == 'u' {
                 let number = arg.parse::<u8>();
                 if number.is_err() {
                     return Err(CompileErr::InvalidUnsignedNumber);
                 }
 
-                binary_string = format!("{:08b}", number.unwrap());
+                let slot = open_slots.next().unwrap();
+                open_slots.next();
+                word |= (number.unwrap() as u16) << byte_shift(slot);
             } else if current_instruction_type == 'n' {
                 let number_dec = arg.parse::<i32>();
                 let number_hex = i32::from_str_radix(arg, 16);
 
-                if number_hex.is_ok() {
-                    binary_string = format!("{:016b}", number_hex.unwrap());
+                let number = if number_hex.is_ok() {
+                    number_hex.unwrap()
                 } else if number_dec.is_ok() {
-                    binary_string = format!("{:016b}", number_dec.unwrap());
+                    number_dec.unwrap()
                 } else {
                     return Err(CompileErr::InvalidNumber);
-                }
-            } else if current_instruction_type == 'z' {
-                binary_string = "0000".to_string();
-                arg_to_get -= 1;
-                filled_slots[slot_to_fill] = false;
-            }
+                };
 
-            arg_to_get += 1;
+                if number < i16::MIN as i32 || number > u16::MAX as i32 {
+                    return Err(CompileErr::InvalidNumber);
+                }
 
-            if binary_string.len() == 4 {
-                binary_contents[slot_to_fill] = binary_string;
-            } else if binary_string.len() == 8 {
-                binary_contents[slot_to_fill] = String::from(binary_string.get(0..4).unwrap());
-                binary_contents[slot_to_fill + 1] = String::from(binary_string.get(4..8).unwrap());
-            } else {
-                binary_contents[slot_to_fill] = String::from(binary_string.get(0..4).unwrap());
-                binary_contents[slot_to_fill + 1] = String::from(binary_string.get(4..8).unwrap());
-                binary_contents[slot_to_fill + 2] = String::from(binary_string.get(8..12).unwrap());
+                word = number as u16;
             }
         }
 
         Ok(Instruction {
-            instruction_type,
+            instruction_type: instruction_type.clone(),
             text_contents,
-            binary_contents,
+            word,
         })
     }
 
     pub fn new_from_binary(line_contents: &str) -> Result<Instruction, CompileErr> {
-        let binary_contents: Vec<String> = line_contents
-            .clone()
-            .split(' ')
-            .map(String::from)
-            .collect();
-
-        let mut instruction_type: Option<InstructionType> = None;
-
-        let line_split: Vec<String> = line_contents.split(' ').map(String::from).collect();
-
-        for instruction in INSTRUCTION_LOOKUP.clone().into_iter() {
-            let mut matches_instruction: bool = true;
+        Instruction::new_from_binary_in(line_contents, &BUILTIN_INSTRUCTIONS)
+    }
 
-            let matcher: Vec<String> = instruction
-                .match_string
-                .split(' ')
-                .map(String::from)
-                .collect();
+    /// Decodes a line of binary using the given instruction set
+    pub fn new_from_binary_in(
+        line_contents: &str,
+        instruction_set: &InstructionSet,
+    ) -> Result<Instruction, CompileErr> {
+        let contiguous: String = line_contents.split(' ').collect();
+
+        // from_str_radix would also accept a leading sign,
+        // so make sure there are only binary digits
+        if contiguous.len() != 16 || !contiguous.chars().all(|c| c == '0' || c == '1') {
+            return Err(CompileErr::CorruptedBinary);
+        }
 
-            let mask: Vec<bool> = instruction
-                .mask_string
-                .split(' ')
-                .map(|a| {
-                    a != "0000"
-                })
-                .collect();
+        match u16::from_str_radix(contiguous.as_str(), 2) {
+            Ok(word) => Instruction::from_u16_in(word, instruction_set),
+            Err(_) => Err(CompileErr::CorruptedBinary),
+        }
+    }
 
-            for i in 0..4 {
-                if mask[i] && matcher[i] != line_split[i] {
-                    matches_instruction = false;
-                }
-            }
+    /// Decodes a 16-bit machine word into an Instruction
+    pub fn from_u16(word: u16) -> Result<Instruction, CompileErr> {
+        Instruction::from_u16_in(word, &BUILTIN_INSTRUCTIONS)
+    }
 
-            if matches_instruction {
-                instruction_type = Some(instruction);
-                break;
-            }
-        }
+    /// Decodes a 16-bit machine word using the given instruction set
+    pub fn from_u16_in(
+        word: u16,
+        instruction_set: &InstructionSet,
+    ) -> Result<Instruction, CompileErr> {
+        let instruction_type = instruction_set.by_word(word);
 
         if instruction_type.is_none() {
             return Err(CompileErr::InstructionDoesNotExist);
         }
 
-        let instruction_type = instruction_type.unwrap();
-        let mut text_contents = String::from("");
-
-        let mut instruction_args: Vec<String> = Vec::new();
+        // Only clone the one type that matched
+        let mut instruction = Instruction {
+            instruction_type: instruction_type.unwrap().clone(),
+            text_contents: String::from(""),
+            word,
+        };
 
-        let mut slots_filled = 1;
+        instruction.text_contents = instruction.arguments().join(", ");
 
-        for arg_type in instruction_type.arguments.chars() {
-            if arg_type == 'r' {
-                instruction_args.push(format!(
-                    "r{}",
-                    u8::from_str_radix(binary_contents[slots_filled].as_str(), 2).unwrap()
-                ));
-                slots_filled += 1;
-            } else if arg_type == 's' {
-                let combined_binary = format!(
-                    "{}{}",
-                    binary_contents[slots_filled],
-                    binary_contents[slots_filled + 1]
-                );
-                instruction_args.push(format!(
-                    "{}",
-                    i8::from_str_radix(combined_binary.as_str(), 2).unwrap()
-                ));
-                slots_filled += 2;
-            } else if arg_type == 'u' {
-                let combined_binary = format!(
-                    "{}{}",
-                    binary_contents[slots_filled],
-                    binary_contents[slots_filled + 1]
-                );
-                instruction_args.push(format!(
-                    "{}",
-                    u8::from_str_radix(combined_binary.as_str(), 2).unwrap()
-                ));
-                slots_filled += 2;
-            } else if arg_type == 'n' {
-                let combined_binary = binary_contents.join("");
+        Ok(instruction)
+    }
 
-                instruction_args.push(format!(
-                    "{}",
-                    i32::from_str_radix(combined_binary.as_str(), 2).unwrap()
-                ));
-                slots_filled += 4;
-            }
-        }
-        if !instruction_args.is_empty() {
-            text_contents = instruction_args[0].clone();
-        }
-        if instruction_args.len() > 1 {
-            for i in 1..(instruction_args.len()) {
-                text_contents = format!("{}, {}", text_contents, instruction_args[i]);
-            }
-        }
+    /// Returns the 16-bit machine word of the instruction
+    pub fn to_u16(&self) -> u16 {
+        self.word
+    }
 
-        Ok(Instruction {
-            instruction_type,
-            text_contents,
-            binary_contents,
-        })
+    /// Returns whether the two instructions do the same thing, even
+    /// if they were spelled differently (eg: "setn R1 5" and the
+    /// "r1, 5" decoded from binary), which == does not ignore
+    pub fn semantic_eq(&self, other: &Instruction) -> bool {
+        self.word == other.word && self.instruction_type.names[0] == other.instruction_type.names[0]
     }
 
     pub fn new_data(data: &str) -> Self {
+        Instruction::new_data_word(u16::from_str_radix(data, 2).unwrap())
+    }
+
+    pub fn new_data_word(word: u16) -> Self {
         Instruction {
             instruction_type: InstructionType::new(
                 vec!["data"],
@@ -546,35 +821,137 @@ impl Instruction {
                 "n",
                 "Data",
             ),
-            binary_contents: vec![
-                data[0..4].to_string(),
-                data[4..8].to_string(),
-                data[8..12].to_string(),
-                data[12..16].to_string(),
-            ],
+            word,
             text_contents: "".to_string(),
         }
     }
 
     pub fn new_blank_data() -> Self {
-        Instruction::new_data("0000000000000000")
+        Instruction::new_data_word(0)
+    }
+
+    /// Returns the 4-bit nibble at the given slot (0 to 3,
+    /// from most to least significant)
+    pub fn nibble(&self, slot: usize) -> u8 {
+        ((self.word >> nibble_shift(slot)) & 0xF) as u8
+    }
+
+    /// Returns the 8-bit byte starting at the given slot
+    pub fn byte(&self, slot: usize) -> u8 {
+        ((self.word >> byte_shift(slot)) & 0xFF) as u8
+    }
+
+    /// Decodes the arguments of the instruction from its binary,
+    /// skipping over any nibbles that are fixed by its mask
+    pub fn arguments(&self) -> Vec<String> {
+        let mut open_slots = self.instruction_type.open_slots().into_iter();
+
+        let mut instruction_args: Vec<String> = Vec::new();
+
+        for arg_type in self.instruction_type.arguments.chars() {
+            if arg_type == 'r' {
+                let slot = open_slots.next().unwrap();
+                instruction_args.push(format!("r{}", self.nibble(slot)));
+            } else if arg_type == 's' || arg_type == 'u' {
+                let slot = open_slots.next().unwrap();
+                open_slots.next();
+
+                if arg_type == 's' {
+                    instruction_args.push(format!("{}", self.byte(slot) as i8));
+                } else {
+                    instruction_args.push(format!("{}", self.byte(slot)));
+                }
+            } else if arg_type == 'n' {
+                instruction_args.push(format!("{}", self.word));
+            }
+        }
+
+        instruction_args
+    }
+
+    /// Returns the binary spelling of each nibble of the instruction
+    pub fn binary_contents(&self) -> Vec<String> {
+        (0..4).map(|slot| format!("{:04b}", self.nibble(slot))).collect()
+    }
+
+    /// Returns the binary spelling of the instruction,
+    /// with a space between each nibble
+    pub fn as_binary(&self) -> String {
+        split_binary_to_chunks(format!("{:016b}", self.word))
     }
 
     pub fn as_hex(self) -> String {
-        let mut hex_string = "".to_string();
+        format!("{:04X}", self.word)
+    }
+}
+
+/// Number of bits to shift a nibble at the given slot (0 to 3,
+/// from most to least significant) into place in a word
+fn nibble_shift(slot: usize) -> usize {
+    12 - (slot * 4)
+}
 
-        for i in 0..4 {
-            hex_string = format!(
-                "{}{}",
-                hex_string,
-                format!(
-                    "{:X}",
-                    u8::from_str_radix(self.binary_contents[i].as_str(), 2).unwrap()
-                )
-            );
+/// Number of bits to shift a byte starting at the given slot
+/// into place in a word
+fn byte_shift(slot: usize) -> usize {
+    8 - (slot * 4)
+}
+
+impl std::fmt::Display for Instruction {
+    /// Formats the instruction in its canonical
+    /// "mnemonic arg1, arg2" form
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let arguments = self.arguments();
+
+        if arguments.is_empty() {
+            write!(f, "{}", self.instruction_type.names[0])
+        } else {
+            write!(
+                f,
+                "{} {}",
+                self.instruction_type.names[0],
+                arguments.join(", ")
+            )
         }
+    }
+}
+
+impl std::convert::TryFrom<u16> for Instruction {
+    type Error = CompileErr;
+
+    /// Decodes a 16-bit machine word, failing if no instruction
+    /// matches it, rather than falling back to data like from_u16
+    fn try_from(word: u16) -> Result<Self, Self::Error> {
+        let instruction = Instruction::from_u16(word)?;
 
-        hex_string
+        if instruction.instruction_type.names[0] == "data" {
+            Err(CompileErr::InstructionDoesNotExist)
+        } else {
+            Ok(instruction)
+        }
+    }
+}
+
+/// Hashes only the machine word, so instructions that are equal
+/// always hash the same, whatever their spelling
+impl std::hash::Hash for Instruction {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.word.hash(state);
+    }
+}
+
+impl std::convert::TryFrom<&str> for Instruction {
+    type Error = CompileErr;
+
+    /// Compiles a line of text without a line number (eg: "add r1 r2 r3")
+    fn try_from(line_contents: &str) -> Result<Self, Self::Error> {
+        Instruction::new_from_text(line_contents)
+    }
+}
+
+impl From<&Instruction> for u16 {
+    fn from(instruction: &Instruction) -> u16 {
+        instruction.to_u16()
     }
 }
 
@@ -594,6 +971,9 @@ pub enum RuntimeErr {
     RegisterOutOfBounds,
     MaximumIterationsReached,
     TooManyInputs,
+    SelfModifyingCode,
+    UninitializedMemoryRead,
+    JumpIntoData,
 }
 
 impl RuntimeErr {
@@ -613,13 +993,443 @@ impl RuntimeErr {
             RuntimeErr::RegisterOutOfBounds => 110,
             RuntimeErr::MaximumIterationsReached => 111,
             RuntimeErr::TooManyInputs => 112,
+            RuntimeErr::SelfModifyingCode => 113,
+            RuntimeErr::UninitializedMemoryRead => 114,
+            RuntimeErr::JumpIntoData => 115,
+        }
+    }
+}
+
+impl std::fmt::Display for RuntimeErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for RuntimeErr {}
+
+/// Problems noticed while running that do not stop the program
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RuntimeWarning {
+    /// The instruction at pc wrote over one of the program's own
+    /// instructions (rather than its data)
+    SelfModifyingCode { pc: usize, address: usize },
+    /// The instruction at pc read memory that was never written
+    UninitializedRead { pc: usize, address: usize },
+    /// The instruction at pc moved the program counter onto data
+    JumpIntoData { pc: usize, address: usize },
+}
+
+impl std::fmt::Display for RuntimeWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeWarning::SelfModifyingCode { pc, address } => write!(
+                f,
+                "instruction at address {} overwrote code at address {}",
+                pc, address
+            ),
+            RuntimeWarning::UninitializedRead { pc, address } => write!(
+                f,
+                "instruction at address {} read address {}, which was never written",
+                pc, address
+            ),
+            RuntimeWarning::JumpIntoData { pc, address } => write!(
+                f,
+                "instruction at address {} moved to address {}, which holds data",
+                pc, address
+            ),
+        }
+    }
+}
+
+/// Where the read instruction gets its numbers from
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InputSource {
+    /// Ask the io handler for each number, which prompts on
+    /// stdin unless another handler was given
+    Stdin,
+    /// Take the next number from the simulator's inputs
+    Inputs,
+    /// Take the next line of text from the simulator's script,
+    /// parsed the same way as a line typed on stdin
+    Script,
+}
+
+/// Handles the read and write instructions, so a program can
+/// be embedded somewhere other than a terminal
+pub trait IoHandler {
+    /// Returns the number given to a read instruction
+    fn read(&mut self) -> Result<i16, RuntimeErr>;
+    /// Takes the number printed by a write instruction
+    fn write(&mut self, value: i16);
+}
+
+/// IoHandler that prompts for numbers on stdin and prints to
+/// stdout, used unless the simulator is given another handler
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdioHandler {
+    pub read_mode: ReadMode,
+    pub write_mode: WriteMode,
+}
+
+impl IoHandler for StdioHandler {
+    fn read(&mut self) -> Result<i16, RuntimeErr> {
+        loop {
+            let mut line = String::new();
+            println!("{}", "Enter number:".on_yellow().black());
+
+            if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+                return Err(RuntimeErr::TooManyInputs);
+            }
+
+            match line.trim() {
+                "q" => return Err(RuntimeErr::Halt),
+                text => match self.read_mode.parse(text) {
+                    Some(number) => return Ok(number),
+                    None => println!("Invalid number! Please try again..."),
+                },
+            }
+        }
+    }
+
+    fn write(&mut self, value: i16) {
+        println!("{}\n{}", "HMMM OUT:".on_green().black(), self.write_mode.format(value));
+    }
+}
+
+/// Shared handle to the IoHandler of a simulator, so the
+/// simulator can still be cloned, and sent to another thread
+#[derive(Clone)]
+pub struct IoHandle(Arc<Mutex<dyn IoHandler + Send>>);
+
+impl IoHandle {
+    pub fn new(handler: impl IoHandler + Send + 'static) -> Self {
+        IoHandle(Arc::new(Mutex::new(handler)))
+    }
+
+    pub fn read(&self) -> Result<i16, RuntimeErr> {
+        self.lock().read()
+    }
+
+    pub fn write(&self, value: i16) {
+        self.lock().write(value)
+    }
+
+    /// Locks the handler, still using it if a thread panicked
+    /// while holding it
+    fn lock(&self) -> std::sync::MutexGuard<'_, dyn IoHandler + Send + 'static> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for IoHandle {
+    fn default() -> Self {
+        IoHandle::new(StdioHandler::default())
+    }
+}
+
+impl std::fmt::Debug for IoHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "IoHandle")
+    }
+}
+
+/// Handlers cannot be compared, so any two are treated as equal,
+/// like event senders
+impl PartialEq for IoHandle {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for IoHandle {}
+
+/// Something that happened while the simulator ran, sent to
+/// the event sender so a GUI can follow along
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SimulatorEvent {
+    /// The instruction at the address was run
+    StepExecuted { address: usize },
+    RegisterChanged(RegisterChange),
+    MemoryChanged(MemoryChange),
+    OutputProduced(i16),
+    Halted,
+}
+
+/// Channel the simulator sends its events over. Any two are
+/// treated as equal, so simulators can still be compared
+#[derive(Clone, Debug)]
+pub struct EventSender(mpsc::Sender<SimulatorEvent>);
+
+impl EventSender {
+    /// Sends the events for one step, ignoring a closed channel
+    /// as nobody is listening anymore
+    fn send_step(&self, result: &Result<StepResult, RuntimeErr>) {
+        let step_result = match result {
+            Ok(step_result) => step_result,
+            Err(_) => return,
+        };
+
+        let mut events = vec![SimulatorEvent::StepExecuted {
+            address: step_result.address,
+        }];
+        events.extend(
+            step_result
+                .register_changes
+                .iter()
+                .map(|change| SimulatorEvent::RegisterChanged(*change)),
+        );
+        events.extend(
+            step_result
+                .memory_changes
+                .iter()
+                .map(|change| SimulatorEvent::MemoryChanged(*change)),
+        );
+        events.extend(step_result.output.map(SimulatorEvent::OutputProduced));
+        if step_result.halted {
+            events.push(SimulatorEvent::Halted);
+        }
+
+        for event in events {
+            let _ = self.0.send(event);
+        }
+    }
+}
+
+impl PartialEq for EventSender {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for EventSender {}
+
+/// Runs an instruction added through an InstructionSet, with
+/// its registers already in current_regs
+pub type CustomInstruction = fn(&mut Simulator) -> Result<(), RuntimeErr>;
+
+// Header identifying files written by Simulator::save_state
+static STATE_FORMAT: &str = "hmmm-state";
+// Bumped whenever the saved state layout changes
+const STATE_VERSION: u64 = 1;
+
+// Furthest a misspelled instruction can be from a real
+// one (in edits) for it to be suggested
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// Number of source lines compiled between each progress report
+pub const PROGRESS_INTERVAL: usize = 100;
+
+/// Words of memory reachable with an 8-bit address
+pub const MEMORY_WORDS: usize = 256;
+
+/// Registers reachable with a 4-bit register number
+pub const REGISTER_COUNT: usize = 16;
+
+/// Steps a simulator runs before stopping with
+/// MaximumIterationsReached, so a program that never
+/// halts cannot hang
+pub const DEFAULT_STEP_LIMIT: u64 = 10_000_000;
+
+// Serde only derives for arrays of up to 32 items,
+// so memory is written out as a plain list of words
+#[cfg(feature = "serde")]
+mod memory_serde {
+    use super::MEMORY_WORDS;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::convert::TryInto;
+
+    pub fn serialize<S: Serializer>(
+        memory: &[u16; MEMORY_WORDS],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(memory.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u16; MEMORY_WORDS], D::Error> {
+        let words: Vec<u16> = Vec::deserialize(deserializer)?;
+        let length = words.len();
+
+        words
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(length, &"256 memory words"))
+    }
+}
+
+/// A subroutine call that has not returned yet
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CallFrame {
+    /// Address the subroutine should return to
+    pub return_address: usize,
+    /// Register holding the return address
+    pub return_register: u8,
+}
+
+/// Why Simulator::run_until_pc stopped
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum StopReason {
+    /// The program counter reached the target address
+    ReachedPc,
+    /// The program ran a halt
+    Halted,
+    /// The simulator's step limit was reached
+    StepLimit,
+    /// run_to_return was called outside of a subroutine
+    NotInSubroutine,
+    /// Any other runtime error
+    Error(RuntimeErr),
+}
+
+/// The state of the simulator just before a step was run
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TraceEntry {
+    pub step: u64,
+    pub program_counter: usize,
+    pub registers: Vec<i16>,
+}
+
+/// A register written by a step, with its value before and after
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RegisterChange {
+    pub register: u8,
+    pub old: i16,
+    pub new: i16,
+}
+
+/// A memory cell written by a step, with its value before and after
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryChange {
+    pub address: usize,
+    pub old: i16,
+    pub new: i16,
+}
+
+/// What a single step did, so callers do not need to compare the
+/// state before and after it. Stepping again after the halt
+/// returns Err(RuntimeErr::Halt)
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StepResult {
+    /// Address of the instruction that was run
+    pub address: usize,
+    pub register_changes: Vec<RegisterChange>,
+    pub memory_changes: Vec<MemoryChange>,
+    /// Number given to a read instruction
+    pub input: Option<i16>,
+    /// Number printed by a write instruction
+    pub output: Option<i16>,
+    /// Whether the instruction set the program counter itself,
+    /// instead of moving on to the next address
+    pub jumped: bool,
+    /// Whether the program halted, either at a halt instruction
+    /// or by quitting when asked for a number
+    pub halted: bool,
+}
+
+/// How the read instruction parses the text it is given
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReadMode {
+    /// As a signed decimal number
+    Decimal,
+    /// As a 16-bit hex word, with or without "0x"
+    Hex,
+}
+
+impl Default for ReadMode {
+    fn default() -> Self {
+        ReadMode::Decimal
+    }
+}
+
+impl ReadMode {
+    /// Parses a line of text as a number, returning None if it
+    /// is not valid in this mode
+    pub fn parse(&self, line: &str) -> Option<i16> {
+        let line = line.trim();
+
+        match self {
+            ReadMode::Decimal => line.parse::<i16>().ok(),
+            ReadMode::Hex => {
+                let digits = line
+                    .strip_prefix("0x")
+                    .or_else(|| line.strip_prefix("0X"))
+                    .unwrap_or(line);
+
+                if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return None;
+                }
+
+                u16::from_str_radix(digits, 16).ok().map(|word| word as i16)
+            }
+        }
+    }
+}
+
+/// Where the write instruction sends its numbers to
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutputSink {
+    /// Give each number to the io handler, which prints it to
+    /// stdout unless another handler was given
+    Stdout,
+    /// Collect each number in the simulator's outputs
+    Outputs,
+}
+
+/// How the write instruction prints its numbers
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WriteMode {
+    /// As a signed 16-bit number
+    Signed,
+    /// As the same bits read as an unsigned 16-bit number
+    Unsigned,
+    /// As the ASCII character with that code, or an escape
+    /// (eg: "\\x000A") if it is not printable
+    Ascii,
+}
+
+impl Default for WriteMode {
+    fn default() -> Self {
+        WriteMode::Signed
+    }
+}
+
+impl WriteMode {
+    /// Formats a number as printed in this mode
+    pub fn format(&self, output: i16) -> String {
+        match self {
+            WriteMode::Signed => output.to_string(),
+            WriteMode::Unsigned => (output as u16).to_string(),
+            WriteMode::Ascii => match output {
+                0x20..=0x7E => (output as u8 as char).to_string(),
+                _ => format!("\\x{:04X}", output as u16),
+            },
         }
     }
 }
 
 #[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Simulator {
-    pub memory: Vec<Instruction>,
+    /// The program as compiled, kept so it can be restarted
+    pub program: Vec<Instruction>,
+    /// Raw words of memory, only decoded into instructions when needed
+    #[cfg_attr(feature = "serde", serde(with = "memory_serde"))]
+    pub memory: [u16; MEMORY_WORDS],
+    /// Number of words of memory the program can reach
+    pub memory_size: usize,
+    /// r0 is never written, so always reads as zero. There are
+    /// REGISTER_COUNT unless the builder was given another count
     pub registers: Vec<i16>,
     pub program_counter: usize,
     pub counter_log: Vec<usize>,
@@ -628,41 +1438,541 @@ pub struct Simulator {
     pub current_regs: Vec<u8>,
     pub headless: bool,
     pub inputs: Vec<i16>,
+    pub script: Vec<String>,
     pub outputs: Vec<i16>,
+    /// Each number in outputs as printed under the write mode
+    /// (eg: "65535" for -1 when unsigned)
+    pub output_text: Vec<String>,
+    pub input_source: InputSource,
+    pub output_sink: OutputSink,
+    pub read_mode: ReadMode,
+    pub write_mode: WriteMode,
+    pub step_limit: Option<u64>,
+    pub steps_taken: u64,
+    /// Cycle costs overriding the defaults, by instruction name
+    pub cycle_costs: HashMap<String, u64>,
+    /// Total cycles taken by the steps run so far
+    pub cycles_taken: u64,
+    /// Whether the program has halted, so no more steps can be run
+    pub halted: bool,
+    /// Number of words at the start of memory holding the original program
+    pub code_length: usize,
+    /// Stop with SelfModifyingCode instead of warning
+    pub break_on_self_modify: bool,
+    /// Whether each memory cell holds part of the program or has been written
+    pub initialized: Vec<bool>,
+    /// Stop with UninitializedMemoryRead or JumpIntoData instead of warning
+    pub strict: bool,
+    pub warnings: Vec<RuntimeWarning>,
+    /// Subroutine calls that have not returned yet, innermost last
+    pub call_stack: Vec<CallFrame>,
+    /// What the step being run has done so far
+    pub step_result: StepResult,
+    /// Handles read and write when reading from Stdin and writing
+    /// to Stdout, which is a StdioHandler unless another was given
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub io_handler: IoHandle,
+    /// How to run each instruction that is not built in, by name
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub custom_instructions: HashMap<String, CustomInstruction>,
+    /// Where to send events as the program runs, if anywhere
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub event_sender: Option<EventSender>,
+    /// Registers set before the program starts, and on every reset
+    pub register_presets: Vec<(u8, i16)>,
+    /// Memory words set before the program starts, and on every reset
+    pub memory_presets: Vec<(u8, i16)>,
+    /// Register pointing at the top of the stack, set to the first
+    /// address after the program on start and on every reset
+    pub stack_pointer: Option<u8>,
+    /// How many times each memory cell has been read as data
+    pub memory_reads: Vec<u64>,
+    /// How many times each memory cell has been written
+    pub memory_writes: Vec<u64>,
 }
 
-impl Simulator {
+/// Why a SimulatorBuilder could not build a Simulator
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BuildErr {
+    /// The program has more words than the simulator has memory
+    ProgramTooLarge { words: usize, memory_size: usize },
+    /// The register count was zero, leaving no r0
+    NoRegisters,
+    /// A register preset, or the stack pointer, is r0 or past the
+    /// last register
+    RegisterPresetOutOfRange { register: u8, register_count: usize },
+    /// A memory preset is past the end of memory
+    MemoryPresetOutOfRange { address: u8, memory_size: usize },
+}
+
+impl std::fmt::Display for BuildErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildErr::ProgramTooLarge { words, memory_size } => write!(
+                f,
+                "program of {} words does not fit in {} words of memory",
+                words, memory_size
+            ),
+            BuildErr::NoRegisters => write!(f, "a simulator needs at least one register, r0"),
+            BuildErr::RegisterPresetOutOfRange {
+                register,
+                register_count,
+            } => write!(
+                f,
+                "cannot preset r{}, only r1 to r{} can be set",
+                register,
+                register_count - 1
+            ),
+            BuildErr::MemoryPresetOutOfRange {
+                address,
+                memory_size,
+            } => write!(
+                f,
+                "cannot set address {} in {} words of memory",
+                address, memory_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BuildErr {}
+
+/// Builder for a Simulator, to set up where it reads
+/// and writes numbers, how much memory it has, and how
+/// many steps it may take before being stopped
+#[derive(Clone, Debug)]
+pub struct SimulatorBuilder {
+    compiled_text: Vec<Instruction>,
+    input_source: InputSource,
+    output_sink: OutputSink,
+    read_mode: ReadMode,
+    write_mode: WriteMode,
+    inputs: Vec<i16>,
+    script: Vec<String>,
+    memory_size: usize,
+    register_count: usize,
+    step_limit: Option<u64>,
+    cycle_costs: HashMap<String, u64>,
+    break_on_self_modify: bool,
+    strict: bool,
+    io_handler: Option<IoHandle>,
+    custom_instructions: HashMap<String, CustomInstruction>,
+    register_presets: Vec<(u8, i16)>,
+    memory_presets: Vec<(u8, i16)>,
+    stack_pointer: Option<u8>,
+}
+
+impl SimulatorBuilder {
     pub fn new(compiled_text: Vec<Instruction>) -> Self {
-        let data_left = 256 - compiled_text.len();
-        let mut memory: Vec<Instruction> = compiled_text;
-        let data = Instruction::new_blank_data();
+        SimulatorBuilder {
+            compiled_text,
+            input_source: InputSource::Stdin,
+            output_sink: OutputSink::Stdout,
+            read_mode: ReadMode::Decimal,
+            write_mode: WriteMode::Signed,
+            inputs: Vec::new(),
+            script: Vec::new(),
+            memory_size: 256,
+            register_count: REGISTER_COUNT,
+            step_limit: Some(DEFAULT_STEP_LIMIT),
+            cycle_costs: HashMap::new(),
+            break_on_self_modify: false,
+            strict: false,
+            io_handler: None,
+            custom_instructions: HashMap::new(),
+            register_presets: Vec::new(),
+            memory_presets: Vec::new(),
+            stack_pointer: None,
+        }
+    }
+
+    /// Sets where the read instruction gets its numbers from
+    pub fn input_source(mut self, input_source: InputSource) -> Self {
+        self.input_source = input_source;
+        self
+    }
+
+    /// Reads numbers from the given list instead of stdin
+    pub fn inputs(mut self, inputs: Vec<i16>) -> Self {
+        self.input_source = InputSource::Inputs;
+        self.inputs = inputs;
+        self
+    }
 
-        for _ in 0..data_left {
-            memory.push(data.clone());
+    /// Reads lines of text from the given script instead of stdin
+    pub fn script(mut self, script: Vec<String>) -> Self {
+        self.input_source = InputSource::Script;
+        self.script = script;
+        self
+    }
+
+    /// Sets how the read instruction parses the text it is given
+    pub fn read_mode(mut self, read_mode: ReadMode) -> Self {
+        self.read_mode = read_mode;
+        self
+    }
+
+    /// Sets where the write instruction sends its numbers to
+    pub fn output_sink(mut self, output_sink: OutputSink) -> Self {
+        self.output_sink = output_sink;
+        self
+    }
+
+    /// Sets how the write instruction prints its numbers
+    pub fn write_mode(mut self, write_mode: WriteMode) -> Self {
+        self.write_mode = write_mode;
+        self
+    }
+
+    /// Sets the number of words of memory. As addresses are
+    /// 8 bits, anything above 256 is cut down to 256
+    pub fn memory_size(mut self, memory_size: usize) -> Self {
+        self.memory_size = memory_size;
+        self
+    }
+
+    /// Sets the number of registers, for instruction sets that
+    /// need more (or fewer) than 16. Built-in instructions only
+    /// have 4 bits for a register, so can reach r0 to r15
+    pub fn register_count(mut self, register_count: usize) -> Self {
+        self.register_count = register_count;
+        self
+    }
+
+    /// Stops the simulator with MaximumIterationsReached
+    /// after the given number of steps (DEFAULT_STEP_LIMIT
+    /// unless given)
+    pub fn step_limit(mut self, step_limit: u64) -> Self {
+        self.step_limit = Some(step_limit);
+        self
+    }
+
+    /// Lets the simulator run forever, for programs that are
+    /// meant to keep going until stopped
+    pub fn no_step_limit(mut self) -> Self {
+        self.step_limit = None;
+        self
+    }
+
+    /// Sets how many cycles an instruction takes, instead of
+    /// its default cost
+    pub fn cycle_cost(mut self, name: &str, cycles: u64) -> Self {
+        self.cycle_costs.insert(name.to_string(), cycles);
+        self
+    }
+
+    /// Stops the simulator with SelfModifyingCode when the program
+    /// writes into its own code, instead of only warning once
+    pub fn break_on_self_modify(mut self, break_on_self_modify: bool) -> Self {
+        self.break_on_self_modify = break_on_self_modify;
+        self
+    }
+
+    /// Stops the simulator with UninitializedMemoryRead when the
+    /// program reads memory that was never written, and with
+    /// JumpIntoData when it moves onto data, instead of warning
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sends every read and write through the given handler,
+    /// instead of the default StdioHandler
+    pub fn io_handler(mut self, io_handler: impl IoHandler + Send + 'static) -> Self {
+        self.input_source = InputSource::Stdin;
+        self.output_sink = OutputSink::Stdout;
+        self.io_handler = Some(IoHandle::new(io_handler));
+        self
+    }
+
+    /// Sets how to run an instruction that is not built in,
+    /// such as one added through an InstructionSet
+    pub fn custom_instruction(mut self, name: &str, run: CustomInstruction) -> Self {
+        self.custom_instructions.insert(name.to_string(), run);
+        self
+    }
+
+    /// Sets a register before the program starts (and again on
+    /// every reset). Can be given more than once
+    pub fn preset_register(mut self, register: u8, value: i16) -> Self {
+        self.register_presets.push((register, value));
+        self
+    }
+
+    /// Sets a word of memory, as data, before the program starts
+    /// (and again on every reset). Can be given more than once
+    pub fn preset_memory(mut self, address: u8, value: i16) -> Self {
+        self.memory_presets.push((address, value));
+        self
+    }
+
+    /// Uses a register as the stack pointer for pushr and popr,
+    /// starting it at the first address after the program. HMMM
+    /// stacks grow upwards: pushr stores at the address the stack
+    /// pointer holds and then adds 1, and popr subtracts 1 and then
+    /// loads, so the stack fills the free memory towards the end
+    pub fn stack_pointer(mut self, register: u8) -> Self {
+        self.stack_pointer = Some(register);
+        self
+    }
+
+    /// Builds the simulator, failing if the program does not fit
+    /// in its memory, it was given no registers, or a preset is
+    /// for a register or address it does not have
+    pub fn build(self) -> Result<Simulator, BuildErr> {
+        let memory_size = self.memory_size.min(MEMORY_WORDS);
+
+        if self.compiled_text.len() > memory_size {
+            return Err(BuildErr::ProgramTooLarge {
+                words: self.compiled_text.len(),
+                memory_size,
+            });
         }
 
-        let mut registers: Vec<i16> = Vec::new();
-        for _ in 0..16 {
-            registers.push(0_i16);
+        if self.register_count == 0 {
+            return Err(BuildErr::NoRegisters);
         }
-        Simulator {
-            memory,
-            registers,
+
+        let preset_registers = self.register_presets.iter().map(|(register, _)| register);
+
+        for register in preset_registers.chain(self.stack_pointer.iter()) {
+            if *register == 0 || *register as usize >= self.register_count {
+                return Err(BuildErr::RegisterPresetOutOfRange {
+                    register: *register,
+                    register_count: self.register_count,
+                });
+            }
+        }
+
+        for (address, _) in self.memory_presets.iter() {
+            if *address as usize >= memory_size {
+                return Err(BuildErr::MemoryPresetOutOfRange {
+                    address: *address,
+                    memory_size,
+                });
+            }
+        }
+
+        let code_length = self.compiled_text.len();
+        let headless = self.io_handler.is_some()
+            || (self.input_source != InputSource::Stdin && self.output_sink == OutputSink::Outputs);
+        let io_handler = match self.io_handler {
+            Some(io_handler) => io_handler,
+            None => IoHandle::new(StdioHandler {
+                read_mode: self.read_mode,
+                write_mode: self.write_mode,
+            }),
+        };
+
+        let mut simulator = Simulator {
+            memory: load_program_words(&self.compiled_text),
+            memory_size,
+            program: self.compiled_text,
+            registers: vec![0; self.register_count],
             program_counter: 0,
             counter_log: Vec::new(),
             just_updated_pc: false,
             debug: false,
             current_regs: vec![0, 0, 0],
-            headless: false,
-            inputs: Vec::new(),
+            headless,
+            inputs: self.inputs,
+            script: self.script,
             outputs: Vec::new(),
+            output_text: Vec::new(),
+            input_source: self.input_source,
+            output_sink: self.output_sink,
+            read_mode: self.read_mode,
+            write_mode: self.write_mode,
+            step_limit: self.step_limit,
+            steps_taken: 0,
+            cycle_costs: self.cycle_costs,
+            cycles_taken: 0,
+            halted: false,
+            code_length,
+            break_on_self_modify: self.break_on_self_modify,
+            initialized: initialized_cells(code_length, memory_size),
+            strict: self.strict,
+            warnings: Vec::new(),
+            call_stack: Vec::new(),
+            step_result: StepResult::default(),
+            io_handler,
+            custom_instructions: self.custom_instructions,
+            event_sender: None,
+            register_presets: self.register_presets,
+            memory_presets: self.memory_presets,
+            stack_pointer: self.stack_pointer,
+            memory_reads: vec![0; memory_size],
+            memory_writes: vec![0; memory_size],
+        };
+
+        simulator.apply_presets();
+        Ok(simulator)
+    }
+}
+
+/// Shows the whole state at once: the program counter, every
+/// register four to a row, then each memory word that is not zero
+impl std::fmt::Display for Simulator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "PC: {}", self.program_counter)?;
+
+        for (index, register) in self.registers.iter().enumerate() {
+            write!(f, "r{:<2} = {:<6}", index, register)?;
+            if index % 4 == 3 {
+                writeln!(f)?;
+            } else {
+                write!(f, " ")?;
+            }
+        }
+
+        let nonzero: Vec<String> = self
+            .memory_words()
+            .enumerate()
+            .filter(|(_, value)| *value != 0)
+            .map(|(address, value)| format!("{}: {}", address, value))
+            .collect();
+
+        if nonzero.is_empty() {
+            write!(f, "MEMORY: all zero")
+        } else {
+            write!(f, "MEMORY: {}", nonzero.join(", "))
         }
     }
+}
 
+impl Simulator {
+    /// Creates a simulator reading from stdin and printing to stdout.
+    /// Panics if the program does not fit in memory, which compiled
+    /// programs always do. Use builder to get an error instead
+    pub fn new(compiled_text: Vec<Instruction>) -> Self {
+        SimulatorBuilder::new(compiled_text)
+            .build()
+            .expect("program does not fit in memory")
+    }
+
+    /// Creates a simulator reading from its inputs and collecting its
+    /// outputs. Panics like new if the program does not fit in memory
     pub fn new_headless(compiled_text: Vec<Instruction>) -> Self {
-        let mut sim = Simulator::new(compiled_text);
-        sim.headless = true;
-        sim
+        SimulatorBuilder::new(compiled_text)
+            .input_source(InputSource::Inputs)
+            .output_sink(OutputSink::Outputs)
+            .build()
+            .expect("program does not fit in memory")
+    }
+
+    pub fn builder(compiled_text: Vec<Instruction>) -> SimulatorBuilder {
+        SimulatorBuilder::new(compiled_text)
+    }
+
+    /// Sends a SimulatorEvent over the channel for everything
+    /// that happens from now on
+    pub fn with_event_sender(mut self, sender: mpsc::Sender<SimulatorEvent>) -> Self {
+        self.event_sender = Some(EventSender(sender));
+        self
+    }
+
+    /// Function to restart the program from the beginning, clearing
+    /// the registers and reloading the original program into memory,
+    /// so any words it overwrote are restored
+    pub fn reset(&mut self) {
+        self.memory = load_program_words(&self.program);
+
+        self.registers = vec![0; self.registers.len()];
+
+        self.program_counter = 0;
+        self.counter_log.clear();
+        self.just_updated_pc = false;
+        self.current_regs = vec![0, 0, 0];
+        self.outputs.clear();
+        self.output_text.clear();
+        self.steps_taken = 0;
+        self.cycles_taken = 0;
+        self.halted = false;
+        self.initialized = initialized_cells(self.code_length, self.memory_size);
+        self.warnings.clear();
+        self.call_stack.clear();
+        self.step_result = StepResult::default();
+        self.memory_reads = vec![0; self.memory_size];
+        self.memory_writes = vec![0; self.memory_size];
+        self.apply_presets();
+    }
+
+    /// Sets the stack pointer, registers, and memory words given to
+    /// the builder, counting the memory as written so reading it does
+    /// not warn. Presets come last, so they can move the stack pointer
+    fn apply_presets(&mut self) {
+        if let Some(register) = self.stack_pointer {
+            self.registers[register as usize] = self.code_length as i16;
+        }
+
+        for (register, value) in self.register_presets.iter() {
+            self.registers[*register as usize] = *value;
+        }
+
+        for (address, value) in self.memory_presets.iter() {
+            self.memory[*address as usize] = *value as u16;
+            self.initialized[*address as usize] = true;
+        }
+    }
+
+    /// Function to save the memory, registers, program counter, and
+    /// step count to a JSON file, to be picked up later by load_state
+    pub fn save_state(&self, path: &str) -> io::Result<()> {
+        let state = serde_json::json!({
+            "format": STATE_FORMAT,
+            "version": STATE_VERSION,
+            "program_counter": self.program_counter,
+            "steps_taken": self.steps_taken,
+            "registers": self.registers,
+            "memory": &self.memory[..self.memory_size],
+        });
+
+        std::fs::write(path, serde_json::to_string_pretty(&state)?)
+    }
+
+    /// Function to load a state saved by save_state. The state must
+    /// have been saved from a simulator with the same memory size
+    pub fn load_state(&mut self, path: &str) -> io::Result<()> {
+        let invalid =
+            |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let state: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+
+        if state["format"] != STATE_FORMAT {
+            return Err(invalid("not a saved HMMM state"));
+        }
+        if state["version"] != STATE_VERSION {
+            return Err(invalid("unsupported saved state version"));
+        }
+
+        let program_counter = state["program_counter"]
+            .as_u64()
+            .ok_or_else(|| invalid("missing program_counter"))?;
+        let steps_taken = state["steps_taken"]
+            .as_u64()
+            .ok_or_else(|| invalid("missing steps_taken"))?;
+        let registers: Vec<i16> = serde_json::from_value(state["registers"].clone())?;
+        let memory: Vec<u16> = serde_json::from_value(state["memory"].clone())?;
+
+        if registers.len() != self.registers.len() || memory.len() != self.memory_size {
+            return Err(invalid("saved state does not match the simulator's size"));
+        }
+
+        self.memory[..self.memory_size].copy_from_slice(&memory);
+        // Which cells were written isn't saved, so assume any
+        // holding something other than zero were
+        self.initialized = memory
+            .iter()
+            .enumerate()
+            .map(|(address, word)| address < self.code_length || *word != 0)
+            .collect();
+        self.registers = registers;
+        // r0 is hardwired to zero, whatever the file says
+        self.registers[0] = 0;
+        self.program_counter = program_counter as usize;
+        self.steps_taken = steps_taken;
+        self.halted = false;
+
+        Ok(())
     }
 
     /// Function to compile a vec of HMMM instructions into
@@ -671,71 +1981,126 @@ impl Simulator {
         uncompiled_text: Vec<String>,
         is_headless: bool,
     ) -> Result<Vec<Instruction>, CompileErr> {
-        let mut line_counter = 0;
+        Simulator::compile_hmmm_in(uncompiled_text, is_headless, &BUILTIN_INSTRUCTIONS)
+    }
+
+    /// Function to compile a vec of HMMM instructions into a
+    /// Vec of Instruction structs, using the given instruction set
+    pub fn compile_hmmm_in(
+        uncompiled_text: Vec<String>,
+        is_headless: bool,
+        instruction_set: &InstructionSet,
+    ) -> Result<Vec<Instruction>, CompileErr> {
+        compile_lines(uncompiled_text, is_headless, instruction_set, &mut |_, _| {})
+    }
+
+    /// Function to compile like compile_hmmm, calling progress with
+    /// the number of source lines done and the total number after
+    /// every PROGRESS_INTERVAL lines, and after the last line
+    pub fn compile_hmmm_with_progress<F: FnMut(usize, usize)>(
+        uncompiled_text: Vec<String>,
+        is_headless: bool,
+        mut progress: F,
+    ) -> Result<Vec<Instruction>, CompileErr> {
+        compile_lines(uncompiled_text, is_headless, &BUILTIN_INSTRUCTIONS, &mut progress)
+    }
+
+    /// Function to compile a vec of HMMM instructions, splitting and
+    /// encoding the lines in parallel before checking their numbers
+    /// in order. Gives the same result as compile_hmmm
+    #[cfg(feature = "parallel")]
+    pub fn compile_hmmm_parallel(
+        uncompiled_text: Vec<String>,
+        is_headless: bool,
+    ) -> Result<Vec<Instruction>, CompileErr> {
+        let labels = find_labels(&uncompiled_text)?;
+        let instruction_set: &InstructionSet = &BUILTIN_INSTRUCTIONS;
+
+        let lexed_lines: Vec<LexedLine> = uncompiled_text
+            .par_iter()
+            .enumerate()
+            .filter(|(_, line)| is_instruction_line(line) || is_org_line(line))
+            .map(|(index, line)| lex_line(index, line, &labels, instruction_set))
+            .collect();
+
+        number_lines(&uncompiled_text, lexed_lines, is_headless)
+    }
+
+    /// Function to compile HMMM source as it is read, keeping only
+    /// the compiled instructions rather than every line of text.
+    /// Lines using a label defined further down are kept until the
+    /// end, so their errors are only reported once the whole
+    /// source has been read
+    pub fn compile_hmmm_streaming<R: BufRead>(
+        reader: R,
+        is_headless: bool,
+    ) -> Result<Vec<Instruction>, Box<dyn std::error::Error>> {
+        let instruction_set: &InstructionSet = &BUILTIN_INSTRUCTIONS;
+        let mut labels: HashMap<String, usize> = HashMap::new();
         let mut compiled_text: Vec<Instruction> = Vec::new();
+        // Lines waiting on a label, by their index in the source
+        // and the address they compile to
+        let mut deferred: Vec<(usize, usize, String)> = Vec::new();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?.trim().to_string();
+
+            if is_label_line(&line) {
+                define_label(&mut labels, label_name(&line), compiled_text.len())?;
+                continue;
+            } else if is_org_line(&line) {
+                let target = org_target(&line, compiled_text.len())?;
+                compiled_text.resize(target, Instruction::new_blank_data());
+                continue;
+            } else if !is_instruction_line(&line) {
+                continue;
+            }
 
-        for (index, line) in uncompiled_text.iter().enumerate() {
-            if !(line.trim().starts_with('#')) && line.len() > 2 {
-                let mut line_parts: Vec<String> = line
-                    .split(&[',', ' ', '\t'][..])
-                    .map(String::from)
-                    .collect();
-                let line_number = line_parts.get(0).unwrap().trim().parse::<i128>();
-                let comment_part = line_parts.iter().position(|a| a.starts_with('#'));
-
-                if comment_part.is_some() {
-                    line_parts.drain(comment_part.unwrap()..);
-                }
+            if let (Some(name), _) = split_inline_label(&line) {
+                define_label(&mut labels, name, compiled_text.len())?;
+            }
 
-                let line_parts: Vec<String> = String::from(line_parts.join(" ").trim())
-                    .split_whitespace()
-                    .map(String::from)
-                    .collect();
-
-                let cleaned_line = line_parts[1..].join(" ").to_lowercase();
-                if line_number.is_err() {
-                    if !is_headless {
-                        raise_compile_error(
-                            index,
-                            CompileErr::LineNumberNotPresent,
-                            line,
-                            line_parts,
-                        );
-                    }
-                    return Err(CompileErr::LineNumberNotPresent);
-                } else if line_number.unwrap() != line_counter {
-                    if !is_headless {
-                        raise_compile_error(
-                            index,
-                            CompileErr::InvalidLineNumber,
-                            line,
-                            line_parts,
-                        );
-                    }
-                    return Err(CompileErr::InvalidLineNumber);
-                } else {
-                    let next_instruction = Instruction::new_from_text(cleaned_line.as_str());
-                    if next_instruction.is_err() {
-                        let err = next_instruction.unwrap_err();
-                        if !is_headless {
-                            raise_compile_error(index, err.clone(), line, line_parts);
-                        }
-                        return Err(err);
-                    } else {
-                        compiled_text.push(next_instruction.unwrap());
-                        line_counter += 1;
-                    }
-                }
+            let lexed_line = lex_line(index, &line, &labels, instruction_set);
+            let waits_on_label = lexed_line.line_parts.iter().skip(2).any(|part| {
+                is_label_name(part)
+                    && !is_register_name(part)
+                    && !labels.contains_key(&part.to_lowercase())
+            });
+
+            let address = compiled_text.len();
+
+            if waits_on_label {
+                // Still check the line number now, with a stand-in
+                // instruction until every label is known
+                let stand_in = LexedLine {
+                    instruction: Ok(Instruction::new_blank_data()),
+                    ..lexed_line
+                };
+                compiled_text.push(check_line(stand_in, address, &line, is_headless)?);
+                deferred.push((index, address, line));
+            } else {
+                compiled_text.push(check_line(lexed_line, address, &line, is_headless)?);
             }
         }
 
+        for (index, address, line) in deferred {
+            let lexed_line = lex_line(index, &line, &labels, instruction_set);
+
+            compiled_text[address] = check_line(lexed_line, address, &line, is_headless)?;
+        }
+
         Ok(compiled_text)
     }
 
     pub fn write_reg(&mut self, register: u8, data: i16) -> Result<(), RuntimeErr> {
-        if register > 15 {
+        if register as usize >= self.registers.len() {
             return Err(RuntimeErr::InvalidRegisterLocation);
         } else if register > 0 {
+            self.step_result.register_changes.push(RegisterChange {
+                register,
+                old: self.registers[register as usize],
+                new: data,
+            });
             self.registers[register as usize] = data;
         }
 
@@ -745,49 +2110,132 @@ impl Simulator {
     pub fn read_reg(&mut self, register: u8) -> Result<i16, RuntimeErr> {
         if register == 0 {
             Ok(0_i16)
-        } else if register > 15 {
-            Err(RuntimeErr::InvalidRegisterLocation)
         } else {
-            Ok(self.registers[register as usize])
+            self.registers
+                .get(register as usize)
+                .copied()
+                .ok_or(RuntimeErr::InvalidRegisterLocation)
         }
     }
 
     pub fn write_mem(&mut self, memory: u8, data: i16) -> Result<(), RuntimeErr> {
-        let data_binary = format!("{:016b}", data);
+        if memory as usize >= self.memory_size {
+            return Err(RuntimeErr::InvalidMemoryLocation);
+        }
 
-        let data = Instruction::new_data(data_binary.as_str());
+        if self.is_code(memory as usize) {
+            if self.break_on_self_modify {
+                return Err(RuntimeErr::SelfModifyingCode);
+            }
+
+            // Only warn the first time, as loops would flood the output
+            let already_warned = self
+                .warnings
+                .iter()
+                .any(|warning| matches!(warning, RuntimeWarning::SelfModifyingCode { .. }));
+
+            if !already_warned {
+                self.warn(RuntimeWarning::SelfModifyingCode {
+                    pc: self.program_counter,
+                    address: memory as usize,
+                });
+            }
+        }
 
-        self.memory[memory as usize] = data;
+        self.step_result.memory_changes.push(MemoryChange {
+            address: memory as usize,
+            old: self.memory[memory as usize] as i16,
+            new: data,
+        });
+        self.memory[memory as usize] = data as u16;
+        self.initialized[memory as usize] = true;
+        self.memory_writes[memory as usize] += 1;
         Ok(())
     }
 
+    /// Records a warning, printing it too unless headless or
+    /// in debug mode (where it would garble the screen)
+    pub fn warn(&mut self, warning: RuntimeWarning) {
+        if !self.headless && !self.debug {
+            println!("{} {}", " WARNING: ".on_yellow().black(), warning);
+        }
+
+        self.warnings.push(warning);
+    }
+
     pub fn read_mem(&mut self, memory: u8) -> Result<i16, RuntimeErr> {
-        let data = self.memory[memory as usize].clone();
-        if data.instruction_type.names[0] != "data" {
-            Err(RuntimeErr::MemoryLocationNotData)
-        } else {
-            let binary = data.binary_contents.join("");
-            let num = i16::from_str_radix(binary.as_str(), 2);
+        if memory as usize >= self.memory_size {
+            return Err(RuntimeErr::InvalidMemoryLocation);
+        }
 
-            if num.is_err() {
-                Err(RuntimeErr::InvalidMemoryData)
-            } else {
-                Ok(num.unwrap())
+        if !self.initialized[memory as usize] {
+            if self.strict {
+                return Err(RuntimeErr::UninitializedMemoryRead);
+            }
+
+            // Warn once per address, as loops would flood the output
+            let warning = RuntimeWarning::UninitializedRead {
+                pc: self.program_counter,
+                address: memory as usize,
+            };
+            let already_warned = self.warnings.iter().any(|existing| match existing {
+                RuntimeWarning::UninitializedRead { address, .. } => *address == memory as usize,
+                _ => false,
+            });
+
+            if !already_warned {
+                self.warn(warning);
             }
         }
+
+        // Code is refused until the program overwrites it
+        let is_instruction = self.is_code(memory as usize)
+            && self.program_instruction(memory as usize).is_some();
+
+        if is_instruction {
+            Err(RuntimeErr::MemoryLocationNotData)
+        } else {
+            self.memory_reads[memory as usize] += 1;
+            Ok(self.memory[memory as usize] as i16)
+        }
     }
 
     /// Updates the program counter, which points to a "memory address"
-    /// between 0 and 255.
+    /// between 0 and the end of memory (255 by default).
     /// Logs each change for debugging purposes.
     pub fn update_pc(&mut self, new_pc: usize) -> Result<(), RuntimeErr> {
-        if new_pc > 255 {
-            Err(RuntimeErr::InvalidProgramCounter)
-        } else {
-            self.counter_log.push(self.program_counter);
-            self.program_counter = new_pc;
-            Ok(())
+        if new_pc >= self.memory_size {
+            return Err(RuntimeErr::InvalidProgramCounter);
+        }
+
+        // Landing on data usually means the program went off the rails,
+        // so say which instruction sent it there before it tries to run it
+        let lands_on_data = self
+            .get_memory(new_pc)
+            .map_or(false, |instruction| instruction.instruction_type.names[0] == "data");
+
+        if lands_on_data {
+            if self.strict {
+                return Err(RuntimeErr::JumpIntoData);
+            }
+
+            // Warn once per address, as loops would flood the output
+            let already_warned = self.warnings.iter().any(|existing| match existing {
+                RuntimeWarning::JumpIntoData { address, .. } => *address == new_pc,
+                _ => false,
+            });
+
+            if !already_warned {
+                self.warn(RuntimeWarning::JumpIntoData {
+                    pc: self.program_counter,
+                    address: new_pc,
+                });
+            }
         }
+
+        self.counter_log.push(self.program_counter);
+        self.program_counter = new_pc;
+        Ok(())
     }
 
     /// Sets the state of debug mode
@@ -828,12 +2276,63 @@ impl Simulator {
         self.outputs.clone()
     }
 
+    /// Parses a line of text the way the read instruction does,
+    /// returning None if it is not a valid number
+    pub fn parse_input(&self, line: &str) -> Option<i16> {
+        self.read_mode.parse(line)
+    }
+
+    /// Formats a number the way the write instruction prints it
+    pub fn format_output(&self, output: i16) -> String {
+        self.write_mode.format(output)
+    }
+
     /// Function to both execute instruction on program counter
     /// and increment program counter
-    pub fn step(&mut self) -> Result<(), RuntimeErr> {
+    pub fn step(&mut self) -> Result<StepResult, RuntimeErr> {
+        let result = self.run_step();
+
+        if let Some(sender) = &self.event_sender {
+            sender.send_step(&result);
+        }
+
+        result
+    }
+
+    fn run_step(&mut self) -> Result<StepResult, RuntimeErr> {
+        if self.halted {
+            return Err(RuntimeErr::Halt);
+        }
+
+        // Stop if the step limit has been reached
+        if self.step_limit.map_or(false, |limit| self.steps_taken >= limit) {
+            return Err(RuntimeErr::MaximumIterationsReached);
+        }
+        self.steps_taken += 1;
+        self.step_result = StepResult {
+            address: self.program_counter,
+            ..StepResult::default()
+        };
+
+        if self.program_counter < self.memory_size {
+            let cycles = match self.program_instruction(self.program_counter) {
+                Some(instruction) => self.cycle_cost(&instruction.instruction_type),
+                // Words written while running are run as data
+                None => self.cycle_costs.get("data").copied().unwrap_or(1),
+            };
+            self.cycles_taken += cycles;
+        }
+
         // Run memory at program counter
         let execution_result = self.execute_next();
 
+        // Halting leaves the program counter on the halt
+        if execution_result == Err(RuntimeErr::Halt) {
+            self.halted = true;
+            self.step_result.halted = true;
+            return Ok(std::mem::take(&mut self.step_result));
+        }
+
         // If the execution resulted in an error, return it
         if execution_result.is_err() {
             return Err(execution_result.unwrap_err());
@@ -850,9 +2349,128 @@ impl Simulator {
             }
         }
 
+        self.step_result.jumped = self.just_updated_pc;
+
+        Ok(std::mem::take(&mut self.step_result))
+    }
+
+    /// Returns how many cycles an instruction of the given
+    /// type takes in this simulator
+    pub fn cycle_cost(&self, instruction_type: &InstructionType) -> u64 {
+        match self.cycle_costs.get(instruction_type.names[0]) {
+            Some(cycles) => *cycles,
+            None => instruction_type.default_cycles(),
+        }
+    }
+
+    /// Returns an iterator running one step each time it is advanced.
+    /// The halting step (or any error) is the last item, so
+    /// `for step in sim.steps()` runs the whole program
+    pub fn steps(&mut self) -> impl Iterator<Item = Result<StepResult, RuntimeErr>> + '_ {
+        let mut finished = false;
+
+        std::iter::from_fn(move || {
+            if finished {
+                return None;
+            }
+
+            let result = self.step();
+            finished = result.as_ref().map_or(true, |step_result| step_result.halted);
+            Some(result)
+        })
+    }
+
+    /// Function to run at most the given number of steps,
+    /// stopping early if the program halts or hits an error.
+    /// Reaching the end of the steps is not an error
+    pub fn run_steps(&mut self, steps: u64) -> Result<(), RuntimeErr> {
+        for _ in 0..steps {
+            if self.step()?.halted {
+                return Err(RuntimeErr::Halt);
+            }
+        }
+
         Ok(())
     }
 
+    /// Function to step until the program counter reaches the given
+    /// address, the program halts, or the step limit is reached.
+    /// Always runs at least one step, so it can be called again
+    /// to run to the next time the address is reached
+    pub fn run_until_pc(&mut self, address: usize) -> StopReason {
+        loop {
+            match self.step() {
+                Ok(step_result) if step_result.halted => return StopReason::Halted,
+                Ok(_) => {
+                    if self.program_counter == address {
+                        return StopReason::ReachedPc;
+                    }
+                }
+                Err(RuntimeErr::Halt) => return StopReason::Halted,
+                Err(RuntimeErr::MaximumIterationsReached) => return StopReason::StepLimit,
+                Err(error) => return StopReason::Error(error),
+            }
+        }
+    }
+
+    /// Function to step until the current subroutine returns to its
+    /// caller, stopping at the instruction after the call. Recursive
+    /// calls to the same subroutine are run through
+    pub fn run_to_return(&mut self) -> StopReason {
+        let depth = self.call_stack.len();
+        let return_address = match self.call_stack.last() {
+            Some(frame) => frame.return_address,
+            None => return StopReason::NotInSubroutine,
+        };
+
+        loop {
+            match self.step() {
+                Ok(step_result) if step_result.halted => return StopReason::Halted,
+                Ok(_) => {
+                    if self.program_counter == return_address && self.call_stack.len() < depth {
+                        return StopReason::ReachedPc;
+                    }
+                }
+                Err(RuntimeErr::Halt) => return StopReason::Halted,
+                Err(RuntimeErr::MaximumIterationsReached) => return StopReason::StepLimit,
+                Err(error) => return StopReason::Error(error),
+            }
+        }
+    }
+
+    /// Function to run at most the given number of steps, recording
+    /// the state before each one, plus the state it finished in.
+    /// Returns the trace along with how the run ended
+    pub fn run_traced(&mut self, steps: u64) -> (Vec<TraceEntry>, Result<(), RuntimeErr>) {
+        let mut trace: Vec<TraceEntry> = Vec::new();
+        let mut result = Ok(());
+
+        for _ in 0..steps {
+            trace.push(self.trace_entry());
+
+            result = match self.step() {
+                Ok(step_result) if step_result.halted => Err(RuntimeErr::Halt),
+                step_result => step_result.map(|_| ()),
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+
+        trace.push(self.trace_entry());
+
+        (trace, result)
+    }
+
+    /// Returns the current state as a TraceEntry
+    pub fn trace_entry(&self) -> TraceEntry {
+        TraceEntry {
+            step: self.steps_taken,
+            program_counter: self.program_counter,
+            registers: self.registers.clone(),
+        }
+    }
+
     /// Matches instruction name to appropriate function and executes it.
     ///
     /// Modifies self in order to change the state of memory and registers.
@@ -862,18 +2480,17 @@ impl Simulator {
         // Clone the current program counter for use in instructions
         let pc = self.get_program_counter();
 
-        if pc > 255 {
+        if pc >= self.memory_size {
             return Err(RuntimeErr::InvalidProgramCounter);
         }
 
         // Make sure to rest just_updated_pc to false
         self.just_updated_pc = false;
-        // Clone the current instruction from memory
-        let instruction_to_run = self.get_memory(self.get_program_counter()).unwrap();
-        // Get the name of the instruction for quick reference
-        let instruction_name = instruction_to_run.instruction_type.names[0];
+        // Get the name of the instruction for quick reference,
+        // without cloning the instruction itself
+        let instruction_name = self.instruction_name(pc);
 
-        self.current_regs = self.quick_access_regs(instruction_to_run);
+        self.current_regs = self.quick_access_regs(self.memory[pc]);
 
         let result: Result<(), RuntimeErr> = match instruction_name {
             "data" => self.perform_data(),
@@ -899,7 +2516,10 @@ impl Simulator {
             "jgtzn" => self.perform_jgtzn(),
             "jltzn" => self.perform_jltzn(),
             "calln" => self.perform_calln(),
-            _ => Err(RuntimeErr::InvalidInstructionType),
+            _ => match self.custom_instructions.get(instruction_name).copied() {
+                Some(run) => run(self),
+                None => Err(RuntimeErr::InvalidInstructionType),
+            },
         };
 
         result
@@ -912,50 +2532,80 @@ impl Simulator {
 
     /// Returns the register value at the given register index
     pub fn get_register(&self, address: usize) -> Option<i16> {
-        let option = self.registers.get(address);
-
-        if option.is_none() {
-            None
-        } else {
-            Some(*option.unwrap())
-        }
+        self.registers.get(address).copied()
     }
     /// Returns the Instruction struct at memory[address] as Option
     pub fn get_memory(&self, address: usize) -> Option<Instruction> {
-        let option = self.memory.get(address);
-        if option.is_none() {
+        if address >= self.memory_size {
             None
+        } else if let Some(instruction) = self.program_instruction(address) {
+            Some(instruction.clone())
         } else {
-            Some(option.unwrap().clone())
+            Some(Instruction::new_data_word(self.memory[address]))
+        }
+    }
+
+    /// Returns true if the program as compiled has an instruction at
+    /// an address, rather than data (eg: a table after the code, or
+    /// the words skipped over by .org)
+    fn is_code(&self, address: usize) -> bool {
+        self.program
+            .get(address)
+            .map_or(false, |instruction| instruction.instruction_type.names[0] != "data")
+    }
+
+    /// Returns the name of the instruction at an address, which is
+    /// "data" for any word written while running
+    fn instruction_name(&self, address: usize) -> &'static str {
+        match self.program_instruction(address) {
+            Some(instruction) => instruction.instruction_type.names[0],
+            None => "data",
         }
     }
+
+    /// Returns the program's instruction at an address, if the word
+    /// there still matches it. Anything else was written while
+    /// running, so is data
+    fn program_instruction(&self, address: usize) -> Option<&Instruction> {
+        self.program
+            .get(address)
+            .filter(|instruction| instruction.to_u16() == self.memory[address])
+    }
+
+    /// Returns an iterator over every memory cell decoded
+    /// as a signed 16-bit word
+    pub fn memory_words(&self) -> impl Iterator<Item = i16> + '_ {
+        self.memory[..self.memory_size].iter().map(|word| *word as i16)
+    }
+
+    /// Returns an iterator over every memory cell as
+    /// (address, Instruction) pairs
+    pub fn instructions(&self) -> impl Iterator<Item = (usize, Instruction)> + '_ {
+        (0..self.memory_size).map(move |address| (address, self.get_memory(address).unwrap()))
+    }
+
     /// Returns current counter log of program counter
     pub fn get_counter_log(&self) -> Vec<usize> {
         self.counter_log.clone()
     }
 
     /// Returns the current instruction register values    
-    pub fn quick_access_regs(&self, instruction_to_run: Instruction) -> Vec<u8> {
-        // All binary of length 4 can be coerced into u8, and having all three
-        // arguments available as numbers can be useful for instructions
-        let reg_x = u8::from_str_radix(instruction_to_run.binary_contents[1].as_str(), 2).unwrap();
-        let reg_y = u8::from_str_radix(instruction_to_run.binary_contents[2].as_str(), 2).unwrap();
-        let reg_z = u8::from_str_radix(instruction_to_run.binary_contents[3].as_str(), 2).unwrap();
+    pub fn quick_access_regs(&self, word: u16) -> Vec<u8> {
+        // Having all three nibbles available as numbers
+        // can be useful for instructions
+        let reg_x = ((word >> nibble_shift(1)) & 0xF) as u8;
+        let reg_y = ((word >> nibble_shift(2)) & 0xF) as u8;
+        let reg_z = ((word >> nibble_shift(3)) & 0xF) as u8;
 
         return vec![reg_x, reg_y, reg_z];
     }
 
     // Get last data as i8
     pub fn get_ending_data(&self) -> Result<i8, RuntimeErr> {
-        let _instruction_to_run = self.get_memory(self.get_program_counter()).unwrap();
-
-        return signed_binary_conversion(
-            self.get_memory(self.get_program_counter())
-                .unwrap()
-                .binary_contents[2..]
-                .join("")
-                .as_str(),
-        );
+        match self.memory[..self.memory_size].get(self.get_program_counter()) {
+            Some(word) => Ok(*word as u8 as i8),
+            None => Err(RuntimeErr::InvalidProgramCounter),
+        }
     }
 
     // Below are the functions for each instruction
@@ -973,71 +2623,33 @@ impl Simulator {
     }
 
     pub fn perform_read(&mut self) -> Result<(), RuntimeErr> {
-        if self.is_headless() {
-            let next_number = self.get_next_input();
-            if next_number.is_none() {
-                Err(RuntimeErr::TooManyInputs)
-            } else {
-                self.write_reg(self.current_regs[0], next_number.unwrap())
-            }
-        } else {
-            loop {
-                let mut line = String::new();
-                if self.is_debug() {
-                    let w = terminal::stdout();
-                    let _ = w.act(Action::ShowCursor);
-                    let _ = w.act(Action::EnableBlinking);
-                    let _ = w.act(Action::MoveCursorTo(0, 28)).unwrap();
-                    print!("{}", "Enter number:".on_yellow().black());
-                    let _ = w.act(Action::MoveCursorTo(14, 28)).unwrap();
-                    print!("                                 ");
-                    let _ = w.act(Action::MoveCursorTo(14, 28)).unwrap();
-                    stdin().lock().read_line(&mut line).unwrap();
-                    let _ = w.act(Action::DisableBlinking);
-                    let _ = w.act(Action::HideCursor);
-                } else {
-                    println!("{}", "Enter number:".on_yellow().black());
-                    io::stdin().read_line(&mut line).unwrap();
-                }
-                line = line.trim().to_string();
-                if line == "q" {
-                    return Err(RuntimeErr::Halt);
-                }
-                let number = line.parse::<i16>();
-                if number.is_ok() {
-                    if self.is_debug() {
-                        let w = terminal::stdout();
-                        w.act(Action::MoveCursorTo(16, 29)).unwrap();
-                        print!("                                        ");
-                    }
-                    return self.write_reg(self.current_regs[0], number.unwrap());
-                }
-                if self.is_debug() {
-                    let w = terminal::stdout();
-                    w.act(Action::MoveCursorTo(16, 29)).unwrap();
-                    print!("Invalid number! Please try again...");
-                } else {
-                    println!("Invalid number! Please try again...");
+        let number = match self.input_source {
+            InputSource::Stdin => self.io_handler.read()?,
+            InputSource::Inputs => self.get_next_input().ok_or(RuntimeErr::TooManyInputs)?,
+            InputSource::Script => {
+                if self.script.is_empty() {
+                    return Err(RuntimeErr::TooManyInputs);
                 }
+
+                let line = self.script.remove(0);
+                self.parse_input(&line).ok_or(RuntimeErr::InvalidData)?
             }
-        }
+        };
+
+        self.step_result.input = Some(number);
+        self.write_reg(self.current_regs[0], number)
     }
 
     pub fn perform_write(&mut self) -> Result<(), RuntimeErr> {
-        if self.is_headless() {
-            let read_num = self.read_reg(self.current_regs[0])?;
-            self.add_output(read_num);
-        } else if self.is_debug() {
-            let w = terminal::stdout();
-            w.act(Action::MoveCursorTo(50, 8)).unwrap();
-            let to_print = format!("{:<10}", self.read_reg(self.current_regs[0])?);
-            print!("{}", to_print);
-        } else {
-            println!(
-                "{}\n{}",
-                "HMMM OUT:".on_green().black(),
-                self.read_reg(self.current_regs[0])?
-            );
+        let read_num = self.read_reg(self.current_regs[0])?;
+        self.step_result.output = Some(read_num);
+
+        match self.output_sink {
+            OutputSink::Stdout => self.io_handler.write(read_num),
+            OutputSink::Outputs => {
+                self.add_output(read_num);
+                self.output_text.push(self.format_output(read_num));
+            }
         }
         Ok(())
     }
@@ -1183,6 +2795,15 @@ impl Simulator {
         if reg_x_data < 0 {
             Err(RuntimeErr::InvalidProgramCounter)
         } else {
+            // Returning from a subroutine ends its call
+            let is_return = self.call_stack.last().map_or(false, |frame| {
+                frame.return_register == self.current_regs[0]
+                    && frame.return_address == reg_x_data as usize
+            });
+            if is_return {
+                self.call_stack.pop();
+            }
+
             self.just_updated_pc = true;
             self.update_pc(reg_x_data as usize)
         }
@@ -1239,12 +2860,435 @@ impl Simulator {
             (self.get_program_counter() + 1) as i16,
         )?;
 
+        self.call_stack.push(CallFrame {
+            return_address: self.get_program_counter() + 1,
+            return_register: self.current_regs[0],
+        });
         self.just_updated_pc = true;
 
         self.update_pc(self.get_ending_data()? as usize)
     }
 }
 
+/// Returns a fresh memory holding the words of a program,
+/// with every word after it zeroed
+fn load_program_words(program: &[Instruction]) -> [u16; MEMORY_WORDS] {
+    let mut memory = [0_u16; MEMORY_WORDS];
+
+    for (word, instruction) in memory.iter_mut().zip(program) {
+        *word = instruction.to_u16();
+    }
+
+    memory
+}
+
+/// Returns which cells of a fresh memory count as initialized,
+/// which is only those holding the program
+fn initialized_cells(code_length: usize, memory_size: usize) -> Vec<bool> {
+    (0..memory_size).map(|address| address < code_length).collect()
+}
+
+/// Checks if a line of a .hmmm file holds an instruction,
+/// rather than being blank or a comment
+pub fn is_instruction_line(line: &str) -> bool {
+    !(line.trim().starts_with('#')) && line.len() > 2 && !is_label_line(line) && !is_org_line(line)
+}
+
+/// Returns true if the line is a .org directive (eg: ".org 20"),
+/// which moves the next instruction to the given address
+pub fn is_org_line(line: &str) -> bool {
+    let without_comment = line.split('#').next().unwrap_or("").trim();
+    let directive = without_comment.split_whitespace().next().unwrap_or("");
+
+    directive.eq_ignore_ascii_case(".org")
+}
+
+/// Function to get the address a .org line moves to, which must
+/// fit in the largest memory (MEMORY_WORDS). Simulators given less
+/// memory reject programs placed past their end when built
+pub fn org_address(line: &str) -> Result<usize, CompileErr> {
+    let without_comment = line.split('#').next().unwrap_or("");
+    let mut parts = without_comment.split_whitespace().skip(1);
+
+    match (parts.next().map(str::parse::<usize>), parts.next()) {
+        (Some(Ok(address)), None) if address < MEMORY_WORDS => Ok(address),
+        (None, _) => Err(CompileErr::TooFewArguments),
+        (Some(_), Some(_)) => Err(CompileErr::TooManyArguments),
+        _ => Err(CompileErr::InvalidUnsignedNumber),
+    }
+}
+
+/// Function to find where a .org line moves to from the given
+/// address, failing if it would move backwards over placed code
+pub fn org_target(line: &str, address: usize) -> Result<usize, CompileErr> {
+    let target = org_address(line)?;
+
+    if target < address {
+        Err(CompileErr::OrgMovesBackward)
+    } else {
+        Ok(target)
+    }
+}
+
+/// Returns true if the line only defines a label (eg: "loop:"),
+/// which names the address of the next instruction
+pub fn is_label_line(line: &str) -> bool {
+    let without_comment = line.split('#').next().unwrap_or("").trim();
+
+    match without_comment.strip_suffix(':') {
+        Some(name) => is_label_name(name),
+        None => false,
+    }
+}
+
+/// Returns true if the text names a register (eg: "r3"),
+/// which a label of the same name would be confused with
+pub fn is_register_name(text: &str) -> bool {
+    let lowercase = text.to_lowercase();
+
+    match lowercase.strip_prefix('r') {
+        Some(number) => !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// Returns true if the text could name a label, starting with a
+/// letter or underscore followed by letters, digits, or underscores
+pub fn is_label_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Function to get the lowercase name defined by a label line
+pub fn label_name(line: &str) -> String {
+    line.split('#')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .trim_end_matches(':')
+        .to_lowercase()
+}
+
+/// Function to split a label at the start of an instruction line
+/// (eg: "loop: 3 add r1 r1 r2") from the instruction, returning
+/// the label's lowercase name and the rest of the line
+pub fn split_inline_label(line: &str) -> (Option<String>, &str) {
+    let trimmed = line.trim_start();
+    let mut parts = trimmed.splitn(2, char::is_whitespace);
+    let first = parts.next().unwrap_or("");
+    let rest = parts.next().unwrap_or("").trim_start();
+
+    match first.strip_suffix(':') {
+        Some(name) if is_label_name(name) && !rest.is_empty() && !rest.starts_with('#') => {
+            (Some(name.to_lowercase()), rest)
+        }
+        _ => (None, line),
+    }
+}
+
+/// Function to find the address of every label in a program,
+/// with label names being case-insensitive
+pub fn find_labels(uncompiled_text: &[String]) -> Result<HashMap<String, usize>, CompileErr> {
+    let scan = scan_labels(uncompiled_text);
+
+    match scan.errors.into_iter().next() {
+        Some((_, err)) => Err(err),
+        None => Ok(scan.labels),
+    }
+}
+
+/// The labels of a program, found by carrying on past any
+/// that could not be defined
+pub(crate) struct LabelScan {
+    pub(crate) labels: HashMap<String, usize>,
+    /// Each label defined, with the index of its line
+    pub(crate) lines: Vec<(String, usize)>,
+    /// Each label that could not be defined, with the index of its line
+    pub(crate) errors: Vec<(usize, CompileErr)>,
+}
+
+/// Function to find every label in a program, keeping each error
+/// rather than stopping at the first
+pub(crate) fn scan_labels(uncompiled_text: &[String]) -> LabelScan {
+    let mut scan = LabelScan {
+        labels: HashMap::new(),
+        lines: Vec::new(),
+        errors: Vec::new(),
+    };
+    let mut address = 0;
+
+    for (index, line) in uncompiled_text.iter().enumerate() {
+        if is_org_line(line) {
+            // A bad .org is reported when the line itself is compiled
+            if let Ok(target) = org_target(line, address) {
+                address = target;
+            }
+            continue;
+        }
+
+        let name = if is_label_line(line) {
+            Some(label_name(line))
+        } else if is_instruction_line(line) {
+            split_inline_label(line).0
+        } else {
+            None
+        };
+
+        if let Some(name) = name {
+            match define_label(&mut scan.labels, name.clone(), address) {
+                Ok(()) => scan.lines.push((name, index)),
+                Err(err) => scan.errors.push((index, err)),
+            }
+        }
+
+        if is_instruction_line(line) {
+            address += 1;
+        }
+    }
+
+    scan
+}
+
+/// Function to check a label can be defined before adding it, as
+/// each name may only be used once and a label named like a
+/// register would be swapped in for that register's arguments
+pub fn define_label(
+    labels: &mut HashMap<String, usize>,
+    name: String,
+    address: usize,
+) -> Result<(), CompileErr> {
+    if is_register_name(&name) {
+        Err(CompileErr::LabelIsRegister)
+    } else if labels.insert(name, address).is_some() {
+        Err(CompileErr::DuplicateLabel)
+    } else {
+        Ok(())
+    }
+}
+
+/// An instruction line split and encoded on its own, before its
+/// line number is checked against the lines around it
+struct LexedLine {
+    /// Index of the line in the source
+    index: usize,
+    line_parts: Vec<String>,
+    line_number: Option<i128>,
+    instruction: Result<Instruction, CompileErr>,
+    /// Whether the line is a .org directive rather than an instruction
+    is_org: bool,
+}
+
+/// Compiles every line, reporting progress as it goes
+fn compile_lines(
+    uncompiled_text: Vec<String>,
+    is_headless: bool,
+    instruction_set: &InstructionSet,
+    progress: &mut dyn FnMut(usize, usize),
+) -> Result<Vec<Instruction>, CompileErr> {
+    let labels = find_labels(&uncompiled_text)?;
+    let total = uncompiled_text.len();
+
+    let lexed_lines: Vec<LexedLine> = uncompiled_text
+        .iter()
+        .enumerate()
+        .inspect(|(index, _)| {
+            let done = index + 1;
+
+            if done % PROGRESS_INTERVAL == 0 || done == total {
+                progress(done, total);
+            }
+        })
+        .filter(|(_, line)| is_instruction_line(line) || is_org_line(line))
+        .map(|(index, line)| lex_line(index, line, &labels, instruction_set))
+        .collect();
+
+    number_lines(&uncompiled_text, lexed_lines, is_headless)
+}
+
+/// Function to split and encode a single instruction line, which
+/// does not depend on any other line once labels are found
+fn lex_line(
+    index: usize,
+    line: &str,
+    labels: &HashMap<String, usize>,
+    instruction_set: &InstructionSet,
+) -> LexedLine {
+    let line_parts = split_source_line(line, labels);
+
+    if is_org_line(line) {
+        // A .org line has no line number, so leave that column blank
+        return LexedLine {
+            index,
+            line_parts: std::iter::once(String::new()).chain(line_parts).collect(),
+            line_number: None,
+            instruction: Ok(Instruction::new_blank_data()),
+            is_org: true,
+        };
+    }
+
+    let line_number = line_parts[0].parse::<i128>().ok();
+    let cleaned_line = line_parts[1..].join(" ").to_lowercase();
+    let instruction = Instruction::new_from_text_in(cleaned_line.as_str(), instruction_set);
+
+    LexedLine {
+        index,
+        line_parts,
+        line_number,
+        instruction,
+        is_org: false,
+    }
+}
+
+/// Function to check a lexed line has the line number expected
+/// at its address, and that the address fits in memory
+fn check_line_number(lexed_line: &LexedLine, address: usize) -> Result<(), CompileErr> {
+    match lexed_line.line_number {
+        None => Err(CompileErr::LineNumberNotPresent),
+        Some(line_number) if line_number != address as i128 => Err(CompileErr::InvalidLineNumber),
+        Some(_) if address >= MEMORY_WORDS => Err(CompileErr::ProgramTooLarge),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Function to check a lexed line has the line number expected
+/// at its address, returning its instruction or the error, which
+/// is printed unless headless
+fn check_line(
+    lexed_line: LexedLine,
+    address: usize,
+    raw_line: &String,
+    is_headless: bool,
+) -> Result<Instruction, CompileErr> {
+    let result = check_line_number(&lexed_line, address).and(lexed_line.instruction);
+
+    if let Err(err) = &result {
+        if !is_headless {
+            raise_compile_error(lexed_line.index, err.clone(), raw_line, lexed_line.line_parts);
+        }
+    }
+
+    result
+}
+
+/// Function to check lexed lines are numbered in order, returning
+/// their instructions or the first error
+fn number_lines(
+    uncompiled_text: &[String],
+    lexed_lines: Vec<LexedLine>,
+    is_headless: bool,
+) -> Result<Vec<Instruction>, CompileErr> {
+    let placement = place_lines(uncompiled_text, lexed_lines);
+
+    match placement.errors.into_iter().next() {
+        Some(LineError { index, line_parts, err }) => {
+            if !is_headless {
+                raise_compile_error(index, err.clone(), &uncompiled_text[index], line_parts);
+            }
+            Err(err)
+        }
+        None => Ok(placement.instructions),
+    }
+}
+
+/// A compile error along with the line it was found on
+pub(crate) struct LineError {
+    /// Index of the line in the source
+    pub(crate) index: usize,
+    pub(crate) line_parts: Vec<String>,
+    pub(crate) err: CompileErr,
+}
+
+/// Instructions placed at their addresses, found by carrying on
+/// past any line that could not be compiled
+pub(crate) struct Placement {
+    pub(crate) instructions: Vec<Instruction>,
+    /// Index in the source of the line each instruction came from
+    pub(crate) line_indexes: Vec<usize>,
+    pub(crate) errors: Vec<LineError>,
+}
+
+/// Function to place lexed lines at their addresses, checking
+/// they are numbered in order and keeping every error found
+fn place_lines(uncompiled_text: &[String], lexed_lines: Vec<LexedLine>) -> Placement {
+    let mut placement = Placement {
+        instructions: Vec::with_capacity(lexed_lines.len()),
+        line_indexes: Vec::with_capacity(lexed_lines.len()),
+        errors: Vec::new(),
+    };
+
+    for lexed_line in lexed_lines {
+        let address = placement.instructions.len();
+
+        let result = if lexed_line.is_org {
+            org_target(&uncompiled_text[lexed_line.index], address).map(|target| {
+                // The words skipped over are left as data
+                placement.instructions.resize(target, Instruction::new_blank_data());
+                placement.line_indexes.resize(target, lexed_line.index);
+            })
+        } else {
+            placement.line_indexes.push(lexed_line.index);
+
+            match check_line_number(&lexed_line, address).and(lexed_line.instruction) {
+                Ok(instruction) => {
+                    placement.instructions.push(instruction);
+                    Ok(())
+                }
+                Err(err) => {
+                    // Keep counting as if the line was right, so one mistake
+                    // does not make every later line number wrong too
+                    placement.instructions.push(Instruction::new_blank_data());
+                    Err(err)
+                }
+            }
+        };
+
+        if let Err(err) = result {
+            placement.errors.push(LineError {
+                index: lexed_line.index,
+                line_parts: lexed_line.line_parts,
+                err,
+            });
+        }
+    }
+
+    placement
+}
+
+/// Function to compile every line of a program, keeping each
+/// error rather than stopping at the first
+pub(crate) fn place_program(
+    uncompiled_text: &[String],
+    labels: &HashMap<String, usize>,
+    instruction_set: &InstructionSet,
+) -> Placement {
+    let lexed_lines: Vec<LexedLine> = uncompiled_text
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| is_instruction_line(line) || is_org_line(line))
+        .map(|(index, line)| lex_line(index, line, labels, instruction_set))
+        .collect();
+
+    place_lines(uncompiled_text, lexed_lines)
+}
+
+/// Function to split an instruction line into its line number,
+/// name, and arguments, dropping any comment or inline label and
+/// swapping each label used as an argument for its address
+pub fn split_source_line(line: &str, labels: &HashMap<String, usize>) -> Vec<String> {
+    let (_, instruction) = split_inline_label(line);
+    let without_comment = instruction.split('#').next().unwrap_or("");
+
+    without_comment
+        .split(&[',', ' ', '\t'][..])
+        .filter(|part| !part.is_empty())
+        .enumerate()
+        .map(|(part_index, part)| match labels.get(&part.to_lowercase()) {
+            Some(address) if part_index > 1 => address.to_string(),
+            _ => String::from(part),
+        })
+        .collect()
+}
+
 pub fn signed_binary_conversion(binary: &str) -> Result<i8, RuntimeErr> {
     let is_negative: bool = { binary.starts_with('1') };
     let mut binary_mut: String = binary.to_owned();
@@ -0,0 +1,95 @@
+use super::simulator::*;
+use super::*;
+use wasm_bindgen::prelude::*;
+
+/// Converts any Debug-printable error into a JS string
+fn to_js_error<T: std::fmt::Debug>(error: T) -> JsValue {
+    JsValue::from_str(format!("{:?}", error).as_str())
+}
+
+/// Compiles HMMM source into a JS array of binary
+/// instruction strings (eg: "0001 0001 0000 0101")
+#[wasm_bindgen]
+pub fn compile(source: &str) -> Result<JsValue, JsValue> {
+    let compiled_text = super::compile(source).map_err(to_js_error)?.instructions;
+
+    let binary = js_sys::Array::new();
+    for instruction in compiled_text {
+        binary.push(&JsValue::from_str(instruction.as_binary().as_str()));
+    }
+
+    Ok(binary.into())
+}
+
+/// Simulator that can be stepped from JS, with the read
+/// and write instructions routed through JS callbacks
+#[wasm_bindgen]
+pub struct WasmSimulator {
+    sim: Simulator,
+    /// Called with no arguments, must return a number
+    read_callback: js_sys::Function,
+    /// Called with the number being written
+    write_callback: js_sys::Function,
+}
+
+#[wasm_bindgen]
+impl WasmSimulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        source: &str,
+        read_callback: js_sys::Function,
+        write_callback: js_sys::Function,
+    ) -> Result<WasmSimulator, JsValue> {
+        let compiled_text = super::compile(source).map_err(to_js_error)?.instructions;
+        let sim = Simulator::builder(compiled_text)
+            .input_source(InputSource::Inputs)
+            .output_sink(OutputSink::Outputs)
+            .build()
+            .map_err(to_js_error)?;
+
+        Ok(WasmSimulator {
+            sim,
+            read_callback,
+            write_callback,
+        })
+    }
+
+    /// Runs a single instruction, returning false once
+    /// the program has halted
+    pub fn step(&mut self) -> Result<bool, JsValue> {
+        let next_instruction = self.sim.get_memory(self.sim.get_program_counter());
+
+        // Headless simulators read from their inputs, so fill
+        // them from JS right before a read is executed
+        if next_instruction.map_or(false, |i| i.instruction_type.names[0] == "read") {
+            let input = self.read_callback.call0(&JsValue::NULL)?;
+            let number = input
+                .as_f64()
+                .ok_or_else(|| JsValue::from_str("read callback must return a number"))?;
+            self.sim.set_inputs(vec![number as i16]);
+        }
+
+        let result = self.sim.step();
+
+        for output in self.sim.outputs.drain(..) {
+            self.write_callback
+                .call1(&JsValue::NULL, &JsValue::from(output))?;
+        }
+
+        match result {
+            Ok(step_result) => Ok(!step_result.halted),
+            Err(RuntimeErr::Halt) => Ok(false),
+            Err(err) => Err(to_js_error(err)),
+        }
+    }
+
+    /// Returns the current program counter
+    pub fn program_counter(&self) -> usize {
+        self.sim.get_program_counter()
+    }
+
+    /// Returns the contents of a register
+    pub fn register(&self, index: usize) -> i16 {
+        self.sim.get_register(index).unwrap_or(0)
+    }
+}
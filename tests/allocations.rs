@@ -0,0 +1,57 @@
+use hmmm_rs::Instruction;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Number of instructions assembled
+const LINES: usize = 10_000;
+// Most allocations assembling one instruction may take: the split
+// line, its text, its open slots, and the names of its type
+const MAX_ALLOCATIONS_PER_INSTRUCTION: f64 = 4.0;
+
+// Counts every allocation, so a change that adds some to
+// assembling each instruction fails this test
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+#[test]
+fn assembling_allocates_little() {
+    let lines: Vec<String> = (0..LINES)
+        .map(|i| match i % 4 {
+            0 => format!("setn r{} {}", i % 16, i % 128),
+            1 => format!("add r1 r2 r{}", i % 16),
+            2 => format!("storen r3 {}", i % 256),
+            _ => format!("jnezn r4 {}", i % 256),
+        })
+        .collect();
+
+    // Build the instruction set first, so it is not counted
+    Instruction::new_from_text("halt").unwrap();
+
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    for line in &lines {
+        Instruction::new_from_text(line).unwrap();
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+
+    let per_instruction = allocations as f64 / LINES as f64;
+    assert!(
+        per_instruction <= MAX_ALLOCATIONS_PER_INSTRUCTION,
+        "{:.2} allocations per instruction",
+        per_instruction
+    );
+}
@@ -0,0 +1,80 @@
+use hmmm_rs::simulator::*;
+use proptest::prelude::*;
+use proptest::sample::select;
+
+fn register() -> impl Strategy<Value = String> {
+    (0_u8..16).prop_map(|register| format!("r{}", register))
+}
+
+fn signed() -> impl Strategy<Value = String> {
+    any::<i8>().prop_map(|number| number.to_string())
+}
+
+fn unsigned() -> impl Strategy<Value = String> {
+    any::<u8>().prop_map(|number| number.to_string())
+}
+
+/// Generates a random valid line of HMMM, without a line number
+fn instruction() -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just(String::from("halt")),
+        Just(String::from("nop")),
+        (select(vec!["read", "write", "jumpr"]), register())
+            .prop_map(|(name, x)| format!("{} {}", name, x)),
+        (select(vec!["setn", "addn"]), register(), signed())
+            .prop_map(|(name, x, n)| format!("{} {} {}", name, x, n)),
+        (
+            select(vec![
+                "loadn", "storen", "calln", "jeqzn", "jnezn", "jgtzn", "jltzn"
+            ]),
+            register(),
+            unsigned()
+        )
+            .prop_map(|(name, x, n)| format!("{} {} {}", name, x, n)),
+        unsigned().prop_map(|n| format!("jumpn {}", n)),
+        (
+            select(vec!["loadr", "storer", "popr", "pushr", "copy", "neg"]),
+            register(),
+            register()
+        )
+            .prop_map(|(name, x, y)| format!("{} {} {}", name, x, y)),
+        (
+            select(vec!["add", "sub", "mul", "div", "mod"]),
+            register(),
+            register(),
+            register()
+        )
+            .prop_map(|(name, x, y, z)| format!("{} {} {} {}", name, x, y, z)),
+    ]
+}
+
+/// Some encodings are shared on purpose (eg: "add rX rY r0" is
+/// "copy rX rY"), so the decoded name can differ from the source
+fn is_alias_encoding(line: &str) -> bool {
+    let parts: Vec<&str> = line.split(' ').collect();
+
+    match parts[0] {
+        "add" => parts[3] == "r0",
+        "copy" => parts[1] == "r0" && parts[2] == "r0",
+        "sub" => parts[2] == "r0",
+        "calln" => parts[1] == "r0",
+        _ => false,
+    }
+}
+
+proptest! {
+    #[test]
+    fn text_binary_round_trip(line in instruction()) {
+        let encoded = Instruction::new_from_text(line.as_str()).unwrap();
+        let decoded = Instruction::new_from_binary(encoded.as_binary().as_str()).unwrap();
+
+        prop_assert_eq!(decoded.to_u16(), encoded.to_u16());
+
+        prop_assume!(!is_alias_encoding(line.as_str()));
+
+        let source_args: Vec<&str> = line.split(' ').skip(1).collect();
+
+        prop_assert_eq!(decoded.instruction_type.names[0], encoded.instruction_type.names[0]);
+        prop_assert_eq!(decoded.arguments(), source_args);
+    }
+}
@@ -1,6 +1,35 @@
+#![cfg(feature = "cli")]
 
-use hmmm_rs::{load_file, write_compiled_hmmm, read_compiled_hmmm};
+use hmmm_rs::{
+    as_base64, as_c_array, as_callgraph_dot, as_cfg_dot, as_coe, as_commented_hmmm,
+    as_compiled_hmmm, as_csv, as_heatmap_csv, as_intel_hex, as_listing, as_markdown_table, as_mif,
+    as_raw_bytes, as_s_records, as_trace_csv, as_uncompiled_hmmm, as_verilog_rom,
+    assemble_instruction, compile, compile_in, completion_script, decompile,
+    disassemble_instruction, explain_instruction, first_divergence, format_banner,
+    format_compile_report, format_compile_summary, format_divergence, format_state_dump,
+    format_step_row, format_step_table_header, halt_exit_code, hb_header, hmmm_exit_code,
+    instruction_suggestion, link_files, load_file, load_program, load_program_streaming,
+    parse_cycle_costs, parse_data_file, parse_memory_preset, parse_memory_size,
+    parse_register_preset, program_hash, read_base64_hmmm, read_compiled_hmmm, run, run_dap,
+    run_gdb_stub, run_lsp, run_repl, run_server, set_color_choice, source_to_lines, version_string,
+    write_compiled_hmmm, BuildErr, ColorChoice, CompileErr, Diagnostic, Endian, FormatRegistry,
+    OutputFormat, Severity,
+};
 use hmmm_rs::simulator::*;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+// set_color_choice changes coloring for the whole process, so tests
+// that set it, including through the command line, take turns
+static COLOR_LOCK: Mutex<()> = Mutex::new(());
+
+/// Function to run the command line tool while holding COLOR_LOCK
+fn run_cli(args: Vec<&str>) -> Result<i32, Box<dyn std::error::Error>> {
+    let _color = COLOR_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+    run(args)
+}
 
 pub fn create_dummy_simulator() -> Simulator {
     // Load file
@@ -18,15 +47,15 @@ fn compile_uncompile_binary_test() {
     // Compile
     let initial_compiled_file = Simulator::compile_hmmm(initial_file, true).unwrap();
     // Get the binary contents
-    let binary_1: Vec<Vec<String>> = initial_compiled_file.iter().map(|x| x.binary_contents.clone()).collect();
+    let binary_1: Vec<Vec<String>> = initial_compiled_file.iter().map(|x| x.binary_contents()).collect();
     // Write compiled file
     write_compiled_hmmm("tests/test.hb", initial_compiled_file).unwrap();
     // Load compiled file
     let compiled_file = load_file("tests/test.hb").unwrap();
     // Uncompile
-    let uncompiled_file = read_compiled_hmmm(compiled_file);
+    let uncompiled_file = read_compiled_hmmm(compiled_file).unwrap();
     // Get the binary contents
-    let binary_2: Vec<Vec<String>> = uncompiled_file.iter().map(|x| x.binary_contents.clone()).collect();
+    let binary_2: Vec<Vec<String>> = uncompiled_file.iter().map(|x| x.binary_contents()).collect();
 
     assert_eq!(binary_1, binary_2);
 }
@@ -74,3 +103,2323 @@ fn perform_halt_test() {
     // Perform halt
     assert_eq!(sim.perform_halt(), Err(RuntimeErr::Halt));
 }
+
+#[test]
+fn spaced_and_unspaced_binary_decode_identically() {
+    let spaced = read_compiled_hmmm(vec![
+        String::from("0001 0001 0000 0101"),
+        String::from("0110 0010 0001 0001"),
+        String::from("0000 0000 0000 0000"),
+    ])
+    .unwrap();
+    let unspaced = read_compiled_hmmm(vec![
+        String::from("0001000100000101"),
+        String::from("0110001000010001"),
+        String::from("0000000000000000"),
+    ])
+    .unwrap();
+
+    assert_eq!(spaced, unspaced);
+}
+
+#[test]
+fn binary_with_wrong_length_is_rejected() {
+    assert_eq!(
+        read_compiled_hmmm(vec![String::from("000100010000010")]),
+        Err(CompileErr::CorruptedBinary)
+    );
+}
+
+#[test]
+fn hex_and_binary_decode_identically() {
+    let hex = read_compiled_hmmm(vec![String::from("8123")]).unwrap();
+    let binary = read_compiled_hmmm(vec![String::from("1000 0001 0010 0011")]).unwrap();
+
+    assert_eq!(hex, binary);
+    assert_eq!(hex[0].instruction_type.names[0], "mul");
+}
+
+#[test]
+fn mixed_hex_and_binary_is_rejected() {
+    assert_eq!(
+        read_compiled_hmmm(vec![
+            String::from("8123"),
+            String::from("1000 0001 0010 0011"),
+        ]),
+        Err(CompileErr::CorruptedBinary)
+    );
+}
+
+#[test]
+fn c_array_export() {
+    let program = read_compiled_hmmm(vec![
+        String::from("0001 0001 0000 0101"),
+        String::from("0000 0000 0000 0010"),
+        String::from("0000 0000 0000 0000"),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        as_c_array(&program, Endian::Big),
+        "#include <stdint.h>\n\nconst uint16_t program[] = {\n    0x1105,\n    0x0002,\n    0x0000,\n};\nconst unsigned int program_len = 3;\n"
+    );
+}
+
+#[test]
+fn verilog_rom_export() {
+    let program = read_compiled_hmmm(vec![
+        String::from("0001 0001 0000 0101"),
+        String::from("0000 0000 0000 0010"),
+    ])
+    .unwrap();
+    let rom = as_verilog_rom(&program, "hmmm_rom");
+
+    assert!(rom.starts_with("module hmmm_rom (\n"));
+    assert!(rom.contains("            8'd0: data = 16'h1105;\n"));
+    assert!(rom.contains("            8'd1: data = 16'h0002;\n"));
+    assert!(rom.contains("            default: data = 16'h0000;\n"));
+}
+
+#[test]
+fn listing_columns_line_up() {
+    let source = vec![
+        String::from("# Adds five to itself"),
+        String::from("0 setn r1 5 # five"),
+        String::from("1 add r2 r1 r1"),
+        String::from(""),
+        String::from("2 halt"),
+    ];
+    let program = Simulator::compile_hmmm(source.clone(), true).unwrap();
+    let listing = as_listing(&program, &source);
+    let lines: Vec<&str> = listing.lines().collect();
+
+    assert_eq!(lines[0], "ADDR  BINARY               SOURCE");
+    assert_eq!(lines[1], "   0  0001 0001 0000 0101  0 setn r1 5 # five");
+    assert_eq!(lines[2], "   1  0110 0010 0001 0001  1 add r2 r1 r1");
+    assert_eq!(lines[3], "   2  0000 0000 0000 0000  2 halt");
+
+    // Warnings go under the instruction they point at, and a label on
+    // its own line points at the instruction after it
+    let source = vec![
+        String::from("0 setn r1 5"),
+        String::from("unused:"),
+        String::from("1 write r1"),
+    ];
+    let program = Simulator::compile_hmmm(source.clone(), true).unwrap();
+    let listing = as_listing(&program, &source);
+    let lines: Vec<&str> = listing.lines().collect();
+    let indent = " ".repeat(27);
+
+    assert_eq!(lines[2], "   1  0000 0001 0000 0010  1 write r1");
+    assert_eq!(lines[3], format!("{}warning: label \"unused\" is never used", indent));
+    assert_eq!(lines[4], format!("{}warning: {}", indent, hmmm_rs::analysis::MISSING_HALT_MESSAGE));
+    assert_eq!(lines.len(), 5);
+}
+
+#[test]
+fn compile_from_stdin_to_stdout() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hmmm"))
+        .args(&["-i", "-", "-o", "-", "--format", "hb", "-n"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"0 setn r1 5\n1 write r1\n2 halt\n")
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "0001 0001 0000 0101\n0000 0001 0000 0010\n0000 0000 0000 0000"
+    );
+}
+
+#[test]
+fn compile_decompile_round_trip() {
+    let program = compile("0 setn r1 5 # five\n\n1 add r2 r1 r1\n2 halt").unwrap().instructions;
+
+    assert_eq!(as_uncompiled_hmmm(&program), "0 setn r1, 5\n1 add r2, r1, r1\n2 halt");
+    assert_eq!(compile(as_uncompiled_hmmm(&program).as_str()).unwrap().instructions, program);
+}
+
+#[test]
+fn missing_file_returns_err() {
+    let result = run_cli(vec!["hmmm", "-i", "tests/does-not-exist.hmmm", "-n"]);
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn compile_error_returns_err_with_exit_code() {
+    let result = run_cli(vec![
+        "hmmm",
+        "-i",
+        "tests/grading_test/test-compile-error.hmmm",
+        "-n",
+    ]);
+
+    assert_eq!(
+        hmmm_exit_code(result.unwrap_err().as_ref()),
+        Some(CompileErr::TooFewArguments.as_code())
+    );
+}
+
+#[test]
+fn compile_err_display() {
+    assert_eq!(
+        CompileErr::InvalidRegister.to_string(),
+        "register index must be r0..r15"
+    );
+    assert_eq!(
+        CompileErr::InvalidSignedNumber.to_string(),
+        "signed number must be between -128 and 127"
+    );
+}
+
+#[test]
+fn instruction_display() {
+    let program = compile("0 add r1 r2 r3\n1 setn r4 -5").unwrap().instructions;
+
+    assert_eq!(program[0].to_string(), "add r1, r2, r3");
+    assert_eq!(program[1].to_string(), "setn r4, -5");
+    assert_eq!(program[1].as_binary(), "0001 0100 1111 1011");
+}
+
+#[test]
+fn memory_words_and_instructions() {
+    let sim = create_dummy_simulator();
+    let words: Vec<i16> = sim.memory_words().take(3).collect();
+
+    // read r1, write r1, read r2
+    assert_eq!(words, vec![0x0101, 0x0102, 0x0201]);
+    assert_eq!(sim.memory_words().count(), 256);
+
+    let (address, instruction) = sim.instructions().nth(4).unwrap();
+    assert_eq!(address, 4);
+    assert_eq!(instruction.to_string(), "div r3, r1, r2");
+}
+
+#[test]
+fn builder_with_custom_inputs() {
+    let program = Simulator::compile_hmmm(load_file("tests/test.hmmm").unwrap(), true).unwrap();
+    let mut sim = Simulator::builder(program)
+        .inputs(vec![10, 2])
+        .output_sink(OutputSink::Outputs)
+        .memory_size(16)
+        .build()
+        .unwrap();
+
+    while sim.step().is_ok() {}
+
+    assert_eq!(sim.get_outputs(), vec![10, 5]);
+    assert_eq!(sim.memory_words().count(), 16);
+}
+
+#[test]
+fn builder_step_limit() {
+    let program = compile("0 jumpn 0").unwrap().instructions;
+    let mut sim = Simulator::builder(program).step_limit(5).build().unwrap();
+
+    for _ in 0..5 {
+        assert!(sim.step().is_ok());
+    }
+    assert_eq!(sim.step(), Err(RuntimeErr::MaximumIterationsReached));
+}
+
+#[test]
+fn aliases_compile_identically() {
+    let pairs = vec![
+        ("jumpr r1", "jump r1"),
+        ("copy r1 r2", "mov r1 r2"),
+        ("loadr r1 r2", "loadi r1 r2"),
+        ("loadr r1 r2", "load r1 r2"),
+        ("storer r1 r2", "storei r1 r2"),
+        ("storer r1 r2", "store r1 r2"),
+        ("calln r1 5", "call r1 5"),
+        ("jeqzn r1 5", "jeqz r1 5"),
+        ("jnezn r1 5", "jnez r1 5"),
+        ("jgtzn r1 5", "jgtz r1 5"),
+        ("jltzn r1 5", "jltz r1 5"),
+    ];
+
+    for (name, alias) in pairs {
+        assert_eq!(
+            Instruction::new_from_text(name).unwrap(),
+            Instruction::new_from_text(alias).unwrap()
+        );
+    }
+
+    assert_eq!(
+        Instruction::new_from_text("notaninstruction r1"),
+        Err(CompileErr::InstructionDoesNotExist)
+    );
+}
+
+#[test]
+fn new_from_text_spells_out_arguments() {
+    let cases = vec![
+        ("halt", ""),
+        ("write r1", "r1"),
+        ("setn R2 -5", "R2 -5"),
+        ("add r1 r2 r3", "r1, r2, r3"),
+    ];
+
+    for (line, text_contents) in cases {
+        assert_eq!(Instruction::new_from_text(line).unwrap().text_contents, text_contents);
+    }
+
+    assert_eq!(Instruction::new_from_text("add r1 r2"), Err(CompileErr::TooFewArguments));
+    assert_eq!(Instruction::new_from_text("add r1 r2 r3 r4"), Err(CompileErr::TooManyArguments));
+}
+
+#[test]
+fn instructions_can_be_put_in_a_hash_set() {
+    let mut instructions = std::collections::HashSet::new();
+
+    // jnez is another name for jnezn, so both encode to the same word
+    instructions.insert(Instruction::new_from_text("jnezn r1 3").unwrap());
+    instructions.insert(Instruction::new_from_text("jnez r1 3").unwrap());
+    assert_eq!(instructions.len(), 1);
+
+    instructions.insert(Instruction::new_from_text("jnezn r1 4").unwrap());
+    assert_eq!(instructions.len(), 2);
+}
+
+#[test]
+fn differently_spelled_instructions_are_semantically_equal() {
+    let typed = Instruction::new_from_text("setn R1 5").unwrap();
+    let decoded = Instruction::try_from(0x1105_u16).unwrap();
+
+    // The spellings differ, so == still tells them apart
+    assert_ne!(typed, decoded);
+    assert!(typed.semantic_eq(&decoded));
+    assert!(typed.semantic_eq(&Instruction::new_from_text("setn r1 05").unwrap()));
+
+    assert!(!typed.semantic_eq(&Instruction::new_from_text("setn r1 6").unwrap()));
+    // Data with the same word is not the same instruction
+    assert!(!typed.semantic_eq(&Instruction::new_data_word(0x1105)));
+}
+
+#[test]
+fn u16_round_trip() {
+    // Every 16-bit word decodes to some instruction (or data)
+    for word in 0..=u16::MAX {
+        assert_eq!(Instruction::from_u16(word).unwrap().to_u16(), word);
+    }
+
+    let program = Simulator::compile_hmmm(load_file("tests/test.hmmm").unwrap(), true).unwrap();
+    for instruction in program {
+        let decoded = Instruction::from_u16(instruction.to_u16()).unwrap();
+
+        assert_eq!(decoded.to_u16(), instruction.to_u16());
+        assert_eq!(decoded.to_string(), instruction.to_string());
+        assert_eq!(decoded.as_binary(), instruction.as_binary());
+    }
+}
+
+#[test]
+fn u16_encoding() {
+    assert_eq!(Instruction::new_from_text("setn r1 -2").unwrap().to_u16(), 0x11FE);
+    assert_eq!(Instruction::new_from_text("neg r1 r2").unwrap().to_u16(), 0x7102);
+    assert_eq!(Instruction::new_from_text("jumpn 200").unwrap().to_u16(), 0xB0C8);
+    assert_eq!(
+        Instruction::new_from_text("add r16 r1 r2"),
+        Err(CompileErr::InvalidRegister)
+    );
+    assert_eq!(
+        Instruction::from_u16(0x1105).unwrap().binary_contents(),
+        vec!["0001", "0001", "0000", "0101"]
+    );
+}
+
+#[test]
+fn signed_binary_is_corrupted() {
+    // Found by fuzzing: from_str_radix accepts a leading "+"
+    assert_eq!(
+        Instruction::new_from_binary("+000 0000 0000 0001"),
+        Err(CompileErr::CorruptedBinary)
+    );
+    assert_eq!(
+        Instruction::new_from_binary("0000 0000 0000"),
+        Err(CompileErr::CorruptedBinary)
+    );
+}
+
+#[test]
+fn link_multiple_files() {
+    let linked = link_files(&["tests/link_main.hmmm", "tests/link_double.hmmm"]).unwrap();
+    let compiled = Simulator::compile_hmmm(linked, true).unwrap();
+
+    assert_eq!(compiled.len(), 6);
+    // The label in the second file resolves to the address after the first
+    assert_eq!(compiled[1].to_string(), "calln r14, 4");
+
+    let mut simulator = Simulator::builder(compiled)
+        .inputs(vec![21])
+        .build()
+        .unwrap();
+    while simulator.step().is_ok() {}
+
+    assert_eq!(simulator.outputs, vec![42]);
+}
+
+#[test]
+fn duplicate_label_is_rejected() {
+    assert_eq!(
+        Simulator::compile_hmmm(source_to_lines("a:\n0 nop\na:\n1 halt"), true),
+        Err(CompileErr::DuplicateLabel)
+    );
+}
+
+#[test]
+fn label_named_like_a_register_is_rejected() {
+    let source = "r1:\n0 add r1 r1 r2\n1 halt";
+
+    assert_eq!(
+        Simulator::compile_hmmm(source_to_lines(source), true),
+        Err(CompileErr::LabelIsRegister)
+    );
+
+    let diagnostics = compile(source).unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].line, 1);
+    assert_eq!(diagnostics[0].message, "label is named like a register (eg: r1)");
+}
+
+#[test]
+fn load_program_matches_compile_hmmm() {
+    let (compiled, source) = load_program(&["tests/test.hmmm"], true).unwrap();
+    let expected = Simulator::compile_hmmm(load_file("tests/test.hmmm").unwrap(), true).unwrap();
+
+    assert_eq!(compiled, expected);
+    assert_eq!(source, load_file("tests/test.hmmm").unwrap());
+
+    assert!(load_program(&["tests/test.txt"], true).is_err());
+    assert!(load_program(&["tests/test.hmmm", "compiled.hb"], true).is_err());
+
+    // The command line compiles a single file as it reads it
+    let streamed = load_program_streaming(&["tests/test.hmmm"], true).unwrap();
+    assert_eq!(streamed, compiled);
+
+    let linked = ["tests/link_main.hmmm", "tests/link_double.hmmm"];
+    assert_eq!(
+        load_program_streaming(&linked, true).unwrap(),
+        load_program(&linked, true).unwrap().0
+    );
+}
+
+#[test]
+fn verbose_summary_shows_every_line() {
+    let source: Vec<String> = (0..20).map(|line| format!("{} nop", line)).collect();
+    let compiled = compile(source.join("\n").as_str()).unwrap().instructions;
+
+    let verbose = format_compile_summary(&compiled, true);
+    for line in 0..20 {
+        assert!(verbose.contains(format!("█ {:4} █", line).as_str()));
+    }
+    assert!(!verbose.contains("....."));
+
+    // Concise mode skips from line 9 to the last line
+    let concise = format_compile_summary(&compiled, false);
+    assert!(concise.contains("█    9 █"));
+    assert!(!concise.contains("█   10 █"));
+    assert!(concise.contains("█   19 █"));
+}
+
+#[test]
+fn color_never_has_no_escape_codes() {
+    let _color = COLOR_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+    set_color_choice(ColorChoice::Never);
+
+    let banner = format_banner("COMPILATION SUCCESSFUL", true);
+
+    assert!(banner.contains("COMPILATION SUCCESSFUL"));
+    assert!(!banner.contains('\x1b'));
+    assert!(!format_banner("COMPILATION UNSUCCESSFUL", false).contains('\x1b'));
+}
+
+#[test]
+fn run_steps_stops_without_error() {
+    let compiled = compile("0 setn r1 1\n1 setn r2 2\n2 add r3 r1 r2\n3 setn r1 9\n4 halt")
+        .unwrap()
+        .instructions;
+    let mut simulator = Simulator::new_headless(compiled);
+
+    assert_eq!(simulator.run_steps(3), Ok(()));
+
+    assert_eq!(simulator.get_program_counter(), 3);
+    assert_eq!(simulator.get_register(1), Some(1));
+    assert_eq!(simulator.get_register(3), Some(3));
+
+    let dump = format_state_dump(&simulator);
+    assert!(dump.contains("PC: 3"));
+    assert!(dump.contains("r3  = 3"));
+
+    // Running past the halt stops early
+    assert_eq!(simulator.run_steps(10), Err(RuntimeErr::Halt));
+}
+
+#[test]
+fn exit_register_sets_exit_code() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hmmm"))
+        .args(&["-i", "-", "-o", "-", "--format", "hb", "--exit-register", "r1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"0 setn r1 42\n1 halt\n")
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(42));
+}
+
+#[test]
+fn halt_exit_code_is_clamped() {
+    let compiled = compile("0 setn r1 100\n1 mul r1 r1 r1\n2 setn r2 -5\n3 halt")
+        .unwrap()
+        .instructions;
+    let mut simulator = Simulator::new_headless(compiled);
+    while simulator.step().is_ok() {}
+
+    assert_eq!(halt_exit_code(&simulator, None), 0);
+    assert_eq!(halt_exit_code(&simulator, Some(1)), 255);
+    assert_eq!(halt_exit_code(&simulator, Some(2)), 0);
+}
+
+#[test]
+fn unsigned_write_mode() {
+    let compiled = compile("0 setn r1 -1\n1 write r1\n2 halt").unwrap().instructions;
+    let mut signed = Simulator::new_headless(compiled.clone());
+    let mut unsigned = Simulator::builder(compiled)
+        .output_sink(OutputSink::Outputs)
+        .write_mode(WriteMode::Unsigned)
+        .build()
+        .unwrap();
+
+    assert_eq!(signed.format_output(-1), "-1");
+    assert_eq!(unsigned.format_output(-1), "65535");
+
+    // Collected outputs keep the number, and the text under the mode
+    while signed.step().is_ok() {}
+    while unsigned.step().is_ok() {}
+    assert_eq!(signed.output_text, vec!["-1"]);
+    assert_eq!(unsigned.outputs, vec![-1]);
+    assert_eq!(unsigned.output_text, vec!["65535"]);
+}
+
+#[test]
+fn ascii_write_mode() {
+    let program = "0 setn r1 65\n1 write r1\n2 setn r1 10\n3 write r1\n4 halt";
+    let mut simulator = Simulator::builder(compile(program).unwrap().instructions)
+        .output_sink(OutputSink::Outputs)
+        .write_mode(WriteMode::Ascii)
+        .build()
+        .unwrap();
+
+    assert_eq!(simulator.format_output(65), "A");
+    assert_eq!(simulator.format_output(10), "\\x000A");
+    assert_eq!(simulator.format_output(-1), "\\xFFFF");
+
+    while simulator.step().is_ok() {}
+    assert_eq!(simulator.outputs, vec![65, 10]);
+    assert_eq!(simulator.output_text, vec!["A", "\\x000A"]);
+}
+
+#[test]
+fn hex_read_mode() {
+    let compiled = compile("0 read r1\n1 read r2\n2 halt").unwrap().instructions;
+    let mut simulator = Simulator::builder(compiled)
+        .script(vec![String::from("0x1F"), String::from("ff")])
+        .read_mode(ReadMode::Hex)
+        .build()
+        .unwrap();
+
+    while simulator.step().is_ok() {}
+
+    assert_eq!(simulator.get_register(1), Some(31));
+    assert_eq!(simulator.get_register(2), Some(255));
+    assert_eq!(simulator.parse_input("0xZZ"), None);
+}
+
+#[test]
+fn self_modifying_code_warns_once() {
+    let compiled = compile("0 setn r1 1\n1 storen r1 0\n2 storen r1 1\n3 halt")
+        .unwrap()
+        .instructions;
+    let mut simulator = Simulator::new_headless(compiled.clone());
+
+    while simulator.step().is_ok() {}
+
+    assert_eq!(
+        simulator.warnings,
+        vec![RuntimeWarning::SelfModifyingCode { pc: 1, address: 0 }]
+    );
+
+    let mut breaking = Simulator::builder(compiled)
+        .inputs(vec![])
+        .output_sink(OutputSink::Outputs)
+        .break_on_self_modify(true)
+        .build()
+        .unwrap();
+
+    breaking.step().unwrap();
+    assert_eq!(breaking.step(), Err(RuntimeErr::SelfModifyingCode));
+
+    // Writing the program's own data table is not modifying its code
+    let mut table = compile("0 setn r1 4\n1 storen r1 3\n2 halt").unwrap().instructions;
+    table.push(Instruction::new_data_word(0));
+
+    let mut writing = Simulator::builder(table)
+        .inputs(vec![])
+        .output_sink(OutputSink::Outputs)
+        .break_on_self_modify(true)
+        .build()
+        .unwrap();
+    while writing.step().is_ok() {}
+    assert_eq!(writing.memory[3], 4);
+    assert!(writing.warnings.is_empty());
+}
+
+#[test]
+fn reset_restores_fresh_state() {
+    let compiled = compile("0 setn r1 7\n1 storen r1 0\n2 write r1\n3 halt").unwrap().instructions;
+    let fresh = Simulator::new_headless(compiled.clone());
+    let mut simulator = fresh.clone();
+
+    while simulator.step().is_ok() {}
+    assert_ne!(simulator, fresh);
+
+    simulator.reset();
+    assert_eq!(simulator, fresh);
+    assert_eq!(simulator.get_memory(0), Some(compiled[0].clone()));
+}
+
+#[test]
+fn save_and_load_state_round_trip() {
+    let compiled = compile("0 setn r1 7\n1 storen r1 0\n2 addn r1 1\n3 halt").unwrap().instructions;
+    let mut simulator = Simulator::new_headless(compiled.clone());
+    simulator.run_steps(2).unwrap();
+
+    let path = std::env::temp_dir().join("hmmm_rs_state_round_trip.json");
+    let path = path.to_str().unwrap();
+    simulator.save_state(path).unwrap();
+
+    let mut loaded = Simulator::new_headless(compiled);
+    loaded.load_state(path).unwrap();
+
+    assert_eq!(loaded.memory, simulator.memory);
+    assert_eq!(loaded.registers, simulator.registers);
+    assert_eq!(loaded.get_program_counter(), 2);
+    assert_eq!(loaded.steps_taken, 2);
+
+    // Picks up where it left off
+    while loaded.step().is_ok() {}
+    assert_eq!(loaded.get_register(1), Some(8));
+}
+
+#[test]
+fn loaded_state_keeps_r0_zero() {
+    let compiled = compile("0 add r1 r0 r0\n1 halt").unwrap().instructions;
+    let mut simulator = Simulator::new_headless(compiled.clone());
+    simulator.registers[0] = 9;
+
+    let path = std::env::temp_dir().join("hmmm_rs_state_r0.json");
+    let path = path.to_str().unwrap();
+    simulator.save_state(path).unwrap();
+
+    let mut loaded = Simulator::new_headless(compiled);
+    loaded.load_state(path).unwrap();
+    assert_eq!(loaded.get_register(0), Some(0));
+
+    // Even when set directly, r0 still reads as zero
+    loaded.registers[0] = 9;
+    assert_eq!(loaded.read_reg(0), Ok(0));
+    while loaded.step().is_ok() {}
+    assert_eq!(loaded.get_register(1), Some(0));
+}
+
+#[test]
+fn memory_holds_raw_words() {
+    let source = "0 setn r1 3\n1 setn r2 40\n2 storen r1 40\n3 loadn r3 40\n4 addn r3 -1\n\
+                  5 storer r3 r2\n6 write r3\n7 jnezn r3 3\n8 loadn r4 0\n9 halt";
+    let compiled = compile(source).unwrap().instructions;
+    let mut simulator = Simulator::new_headless(compiled);
+
+    let result = loop {
+        if let Err(error) = simulator.step() {
+            break error;
+        }
+    };
+
+    // Reading the program itself is still refused
+    assert_eq!(result, RuntimeErr::MemoryLocationNotData);
+    assert_eq!(simulator.get_outputs(), vec![2, 1, 0]);
+    assert_eq!(simulator.memory[40], 0);
+    assert_eq!(simulator.memory[2], 0x3128);
+    assert_eq!(simulator.get_memory(2).unwrap().to_string(), "storen r1, 40");
+    assert_eq!(simulator.get_memory(40).unwrap().instruction_type.names[0], "data");
+
+    simulator.reset();
+    assert_eq!(simulator.memory[40], 0);
+    assert_eq!(simulator.get_memory(3).unwrap().to_string(), "loadn r3, 40");
+}
+
+#[test]
+fn registers_start_zeroed() {
+    let compiled = compile("0 setn r1 5\n1 setn r0 9\n2 halt").unwrap().instructions;
+    let mut simulator = Simulator::new_headless(compiled);
+
+    assert_eq!(simulator.registers, [0; REGISTER_COUNT]);
+    assert_eq!(simulator.get_register(REGISTER_COUNT), None);
+
+    while simulator.step().is_ok() {}
+
+    // r0 is hardwired to zero, so writing it does nothing
+    assert_eq!(simulator.get_register(0), Some(0));
+    assert_eq!(simulator.get_register(1), Some(5));
+    assert_eq!(simulator.read_reg(16), Err(RuntimeErr::InvalidRegisterLocation));
+
+    simulator.reset();
+    assert_eq!(simulator.registers, [0; REGISTER_COUNT]);
+}
+
+#[test]
+fn diff_finds_first_divergence() {
+    let left = compile("0 setn r1 1\n1 setn r2 2\n2 add r3 r1 r2\n3 halt").unwrap().instructions;
+    let right = compile("0 setn r1 1\n1 setn r2 5\n2 add r3 r1 r2\n3 halt").unwrap().instructions;
+
+    let (left_trace, left_result) = Simulator::new_headless(left.clone()).run_traced(100);
+    let (right_trace, _) = Simulator::new_headless(right).run_traced(100);
+
+    assert_eq!(left_result, Err(RuntimeErr::Halt));
+
+    // State before step 2 is the first to see r2 differ
+    let divergence = first_divergence(&left_trace, &right_trace).unwrap();
+    assert_eq!(divergence.step, 2);
+    assert_eq!(divergence.left.as_ref().unwrap().registers[2], 2);
+    assert_eq!(divergence.right.as_ref().unwrap().registers[2], 5);
+    assert!(format_divergence(&divergence).contains("r2: 2 vs 5"));
+
+    let (same_trace, _) = Simulator::new_headless(left).run_traced(100);
+    assert_eq!(first_divergence(&left_trace, &same_trace), None);
+}
+
+#[test]
+fn diff_runs_one_program_with_two_inputs() {
+    let path = "tests/power.hmmm";
+    let same = run_cli(vec!["hmmm", "-i", path, "--diff", path, "--diff-inputs", "2, 3"]);
+    assert_eq!(same.unwrap(), 0);
+
+    let different = run_cli(vec![
+        "hmmm",
+        "-i",
+        path,
+        "--diff",
+        path,
+        "--diff-inputs",
+        "2, 3",
+        "--diff-other-inputs",
+        "3, 3",
+    ]);
+    assert_eq!(different.unwrap(), 1);
+}
+
+#[test]
+fn cycles_are_counted_per_instruction() {
+    // setn (1) + setn (1) + mul (4) + storen (2) + halt (1)
+    let compiled = compile("0 setn r1 3\n1 setn r2 4\n2 mul r3 r1 r2\n3 storen r3 100\n4 halt")
+        .unwrap()
+        .instructions;
+    let mut simulator = Simulator::new_headless(compiled.clone());
+    while simulator.step().is_ok() {}
+
+    assert_eq!(simulator.steps_taken, 5);
+    assert_eq!(simulator.cycles_taken, 9);
+
+    let mut slow_mul = Simulator::builder(compiled)
+        .inputs(vec![])
+        .output_sink(OutputSink::Outputs)
+        .cycle_cost("mul", 10)
+        .build()
+        .unwrap();
+    while slow_mul.step().is_ok() {}
+
+    assert_eq!(slow_mul.cycles_taken, 15);
+}
+
+#[test]
+fn cycle_costs_file_is_parsed() {
+    let costs = parse_cycle_costs("# slow multiply\nmul 10\n\nmov 3").unwrap();
+    assert_eq!(costs, vec![("mul", 10), ("copy", 3)]);
+
+    assert!(parse_cycle_costs("mull 10").is_err());
+    assert!(parse_cycle_costs("mul -1").is_err());
+    assert!(parse_cycle_costs("mul").is_err());
+}
+
+#[test]
+fn uninitialized_read_warns() {
+    let compiled = compile("0 loadn r1 200\n1 storen r1 201\n2 loadn r2 201\n3 halt")
+        .unwrap()
+        .instructions;
+    let mut simulator = Simulator::new_headless(compiled.clone());
+    while simulator.step().is_ok() {}
+
+    // Only the untouched address is reported, not the one written first
+    assert_eq!(
+        simulator.warnings,
+        vec![RuntimeWarning::UninitializedRead { pc: 0, address: 200 }]
+    );
+
+    let mut strict = Simulator::builder(compiled)
+        .inputs(vec![])
+        .output_sink(OutputSink::Outputs)
+        .strict(true)
+        .build()
+        .unwrap();
+    assert_eq!(strict.step(), Err(RuntimeErr::UninitializedMemoryRead));
+}
+
+#[test]
+fn run_until_pc_stops_at_address() {
+    let compiled = compile("0 setn r1 1\n1 setn r2 2\n2 add r3 r1 r2\n3 setn r1 9\n4 halt")
+        .unwrap()
+        .instructions;
+    let mut simulator = Simulator::new_headless(compiled.clone());
+
+    assert_eq!(simulator.run_until_pc(3), StopReason::ReachedPc);
+    assert_eq!(simulator.get_program_counter(), 3);
+    assert_eq!(simulator.get_register(3), Some(3));
+    assert_eq!(simulator.get_register(1), Some(1));
+
+    // Address 3 is never reached again
+    assert_eq!(simulator.run_until_pc(3), StopReason::Halted);
+
+    let mut limited = Simulator::builder(compiled).step_limit(2).build().unwrap();
+    assert_eq!(limited.run_until_pc(4), StopReason::StepLimit);
+}
+
+#[test]
+fn run_to_return_stops_at_caller() {
+    let compiled = compile(
+        "0 setn r1 3\n1 calln r14 4\n2 write r1\n3 halt\n4 addn r1 1\n5 addn r1 1\n6 jumpr r14",
+    )
+    .unwrap()
+    .instructions;
+    let mut simulator = Simulator::new_headless(compiled);
+
+    assert_eq!(simulator.run_to_return(), StopReason::NotInSubroutine);
+
+    // Step into the subroutine
+    simulator.run_until_pc(5);
+    assert_eq!(
+        simulator.call_stack,
+        vec![CallFrame { return_address: 2, return_register: 14 }]
+    );
+
+    assert_eq!(simulator.run_to_return(), StopReason::ReachedPc);
+    assert_eq!(simulator.get_program_counter(), 2);
+    assert_eq!(simulator.get_register(1), Some(5));
+    assert!(simulator.call_stack.is_empty());
+}
+
+#[test]
+fn cfg_dot_for_loop() {
+    // Counts r1 down from 3, then halts
+    let compiled = compile("0 setn r1 3\n1 addn r1 -1\n2 jnezn r1 1\n3 halt").unwrap().instructions;
+    let dot = as_cfg_dot(&compiled);
+
+    assert!(dot.starts_with("digraph cfg {"));
+    assert_eq!(dot.matches("[label=").count(), 3);
+    // The loop back edge, plus falling into and out of the loop
+    assert!(dot.contains("    b1 -> b1;\n"));
+    assert!(dot.contains("    b0 -> b1;\n"));
+    assert!(dot.contains("    b1 -> b3;\n"));
+}
+
+#[test]
+fn callgraph_dot_for_mutual_recursion() {
+    // is_even (3) and is_odd (7) call each other until r1 is 0
+    let compiled = compile(
+        "0 read r1\n1 calln r14 3\n2 halt\n\
+         3 jeqzn r1 6\n4 addn r1 -1\n5 calln r14 7\n6 jumpr r14\n\
+         7 jeqzn r1 10\n8 addn r1 -1\n9 calln r14 3\n10 jumpr r14",
+    )
+    .unwrap()
+    .instructions;
+    let dot = as_callgraph_dot(&compiled);
+
+    assert_eq!(dot.matches("[label=").count(), 3);
+    assert!(dot.contains("    n0 [label=\"main\"];\n"));
+    assert!(dot.contains("    n0 -> n3;\n"));
+    assert!(dot.contains("    n3 -> n7;\n"));
+    assert!(dot.contains("    n7 -> n3;\n"));
+    assert!(!dot.contains("    n0 -> n7;\n"));
+}
+
+#[test]
+fn intel_hex_export() {
+    let compiled = compile("0 setn r1 5\n1 halt").unwrap().instructions;
+    let hex = as_intel_hex(&compiled, Endian::Big);
+    let lines: Vec<&str> = hex.lines().collect();
+
+    // 0x04 + 0x00 + 0x00 + 0x00 + 0x11 + 0x05 + 0x00 + 0x00 = 0x1A, so the checksum is 0xE6
+    assert_eq!(lines[0], ":0400000011050000E6");
+    assert_eq!(lines[1], ":00000001FF");
+    assert_eq!(lines.len(), 2);
+}
+
+#[test]
+fn s_record_export() {
+    let compiled = compile("0 setn r1 5\n1 halt").unwrap().instructions;
+    let srec = as_s_records(&compiled, Endian::Big);
+    let lines: Vec<&str> = srec.lines().collect();
+
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "S0070000484D4D4DC9");
+    // 0x07 + 0x11 + 0x05 = 0x1D, so the checksum is 0xE2
+    assert_eq!(lines[1], "S107000011050000E2");
+    assert_eq!(lines[2], "S9030000FC");
+}
+
+#[test]
+fn raw_bytes_export() {
+    let compiled = compile("0 setn r1 5\n1 halt").unwrap().instructions;
+
+    assert_eq!(as_raw_bytes(&compiled, Endian::Big), vec![0x11, 0x05, 0x00, 0x00]);
+    assert_eq!(as_raw_bytes(&compiled, Endian::Little), vec![0x05, 0x11, 0x00, 0x00]);
+}
+
+#[test]
+fn endian_applies_to_every_byte_format() {
+    let compiled = compile("0 setn r1 5\n1 halt").unwrap().instructions;
+
+    // 0x04 + 0x05 + 0x11 = 0x1A, the same sum as big-endian, so the checksum is unchanged
+    let hex = as_intel_hex(&compiled, Endian::Little);
+    assert_eq!(hex.lines().next(), Some(":0400000005110000E6"));
+    assert_eq!(&as_raw_bytes(&compiled, Endian::Little)[..2], &[0x05, 0x11]);
+
+    let srec = as_s_records(&compiled, Endian::Little);
+    assert_eq!(srec.lines().nth(1), Some("S107000005110000E2"));
+    assert!(as_c_array(&compiled, Endian::Little).contains("    0x0511,\n"));
+}
+
+#[test]
+fn base64_round_trip() {
+    let compiled = compile("0 setn r1 5\n1 write r1\n2 halt").unwrap().instructions;
+    let encoded = as_base64(&compiled);
+
+    assert_eq!(encoded, "EQUBAgAA\n");
+
+    let decoded = read_base64_hmmm(encoded.as_str()).unwrap();
+    let words: Vec<u16> = decoded.iter().map(|instruction| instruction.to_u16()).collect();
+    assert_eq!(words, vec![0x1105, 0x0102, 0x0000]);
+    assert_eq!(decoded[1].instruction_type.names[0], "write");
+    assert_eq!(read_base64_hmmm("EQ=="), Err(CompileErr::CorruptedBinary));
+}
+
+#[test]
+fn csv_export() {
+    let compiled = compile("0 setn r1 5\n1 add r2 r1 r1\n2 halt").unwrap().instructions;
+    let csv = as_csv(&compiled);
+    let lines: Vec<&str> = csv.lines().collect();
+
+    assert_eq!(lines[0], "address,mnemonic,args,binary,hex");
+    assert_eq!(lines[1], "0,setn,\"r1, 5\",0001 0001 0000 0101,1105");
+    assert_eq!(lines[2], "1,add,\"r2, r1, r1\",0110 0010 0001 0001,6211");
+    assert_eq!(lines[3], "2,halt,,0000 0000 0000 0000,0000");
+}
+
+#[test]
+fn markdown_table_export() {
+    let compiled = compile("0 setn r1 5\n1 halt").unwrap().instructions;
+    let table = as_markdown_table(&compiled);
+    let lines: Vec<&str> = table.lines().collect();
+
+    assert_eq!(lines[1], "|--------:|----------|-----------|--------|");
+    assert_eq!(lines[2], "| 0 | setn | r1, 5 | `0001 0001 0000 0101` |");
+}
+
+#[test]
+fn mif_export() {
+    let compiled = compile("0 setn r1 5\n1 halt").unwrap().instructions;
+    let mif = as_mif(&compiled);
+
+    assert!(mif.contains("WIDTH=16;"));
+    assert!(mif.contains("DEPTH=256;"));
+    assert!(mif.contains("ADDRESS_RADIX=UNS;"));
+    assert!(mif.contains("DATA_RADIX=HEX;"));
+    assert!(mif.contains("CONTENT BEGIN\n\t0 : 1105;\n"));
+    assert!(mif.contains("\t[2..255] : 0000;\nEND;"));
+}
+
+#[test]
+fn coe_export() {
+    let compiled = compile("0 setn r1 5\n1 add r2 r1 r1\n2 halt").unwrap().instructions;
+    let coe = as_coe(&compiled);
+    let lines: Vec<&str> = coe.lines().collect();
+
+    assert_eq!(lines[0], "memory_initialization_radix=16;");
+    assert_eq!(lines[1], "memory_initialization_vector=");
+    assert_eq!(lines[2], "1105,");
+    assert_eq!(lines[3], "6211,");
+    assert_eq!(lines[4], "0000;");
+}
+
+#[test]
+fn gzip_binary_round_trip() {
+    let path = std::env::temp_dir().join("hmmm_rs_gzip_round_trip.hb.gz");
+    let path = path.to_str().unwrap();
+    let compiled = compile("0 setn r1 5\n1 write r1\n2 halt").unwrap().instructions;
+
+    write_compiled_hmmm(path, compiled.clone()).unwrap();
+
+    // Written compressed, so starts with the gzip magic bytes
+    let bytes = std::fs::read(path).unwrap();
+    assert_eq!(&bytes[..2], &[0x1f, 0x8b]);
+
+    let loaded = read_compiled_hmmm(load_file(path).unwrap()).unwrap();
+    let words: Vec<u16> = loaded.iter().map(|i| i.to_u16()).collect();
+    let expected: Vec<u16> = compiled.iter().map(|i| i.to_u16()).collect();
+
+    assert_eq!(words, expected);
+}
+
+#[test]
+fn program_hash_is_stable() {
+    let compiled = compile("0 setn r1 5\n1 halt").unwrap().instructions;
+
+    assert_eq!(program_hash(&compiled), 0xCF2A_2D0D);
+    assert_eq!(program_hash(&compile("0 setn r1 5\n1 halt").unwrap().instructions), 0xCF2A_2D0D);
+    assert_ne!(program_hash(&compile("0 setn r1 6\n1 halt").unwrap().instructions), 0xCF2A_2D0D);
+}
+
+#[test]
+fn hb_header_is_skipped_when_loading() {
+    let compiled = compile("0 setn r1 5\n1 halt").unwrap().instructions;
+    let contents = format!("{}{}", hb_header("test.hmmm"), as_compiled_hmmm(&compiled));
+
+    assert!(contents.starts_with("# hmmm_rs "));
+    assert!(contents.contains("# source: test.hmmm\n"));
+
+    let loaded = read_compiled_hmmm(source_to_lines(contents.as_str())).unwrap();
+    let words: Vec<u16> = loaded.iter().map(|i| i.to_u16()).collect();
+
+    assert_eq!(words, vec![0x1105, 0x0000]);
+}
+
+#[test]
+fn compile_reports_every_diagnostic() {
+    let diagnostics = compile("0 setn r1 5\nunused:\n1 bogus r1\n3 halt").unwrap_err();
+
+    assert_eq!(
+        diagnostics,
+        vec![
+            Diagnostic {
+                line: 2,
+                column: 1,
+                severity: Severity::Warning,
+                message: String::from("label \"unused\" is never used"),
+            },
+            Diagnostic {
+                line: 3,
+                column: 3,
+                severity: Severity::Error,
+                message: String::from("unknown instruction \"bogus\""),
+            },
+            Diagnostic {
+                line: 4,
+                column: 1,
+                severity: Severity::Error,
+                message: String::from("line number is out of sequence"),
+            },
+        ]
+    );
+}
+
+#[test]
+fn decompile_words_with_data() {
+    let source = decompile(&[0x1105, 0x6211, 0x0005, 0x0000]);
+
+    assert_eq!(source, "0 setn r1, 5\n1 add r2, r1, r1\n2 data 5\n3 halt");
+}
+
+#[test]
+fn step_result_describes_side_effects() {
+    let compiled = compile("0 setn r1 5\n1 add r2 r1 r1\n2 write r2\n3 halt").unwrap().instructions;
+    let mut simulator = Simulator::new_headless(compiled);
+
+    simulator.step().unwrap();
+
+    assert_eq!(
+        simulator.step(),
+        Ok(StepResult {
+            address: 1,
+            register_changes: vec![RegisterChange {
+                register: 2,
+                old: 0,
+                new: 10,
+            }],
+            ..StepResult::default()
+        })
+    );
+
+    let write = simulator.step().unwrap();
+
+    assert_eq!(write.output, Some(10));
+    assert!(write.register_changes.is_empty());
+    assert!(!write.jumped);
+    assert!(!write.halted);
+
+    let halt = simulator.step().unwrap();
+
+    assert!(halt.halted);
+    assert_eq!(halt.address, 3);
+    assert_eq!(simulator.get_program_counter(), 3);
+
+    // Nothing more runs once halted
+    assert_eq!(simulator.step(), Err(RuntimeErr::Halt));
+    assert_eq!(simulator.steps_taken, 4);
+}
+
+/// IoHandler that supplies fixed reads and records every write
+struct MockIo {
+    reads: Vec<i16>,
+    writes: Arc<Mutex<Vec<i16>>>,
+}
+
+impl IoHandler for MockIo {
+    fn read(&mut self) -> Result<i16, RuntimeErr> {
+        if self.reads.is_empty() {
+            Err(RuntimeErr::TooManyInputs)
+        } else {
+            Ok(self.reads.remove(0))
+        }
+    }
+
+    fn write(&mut self, value: i16) {
+        self.writes.lock().unwrap().push(value);
+    }
+}
+
+#[test]
+fn io_handler_intercepts_read_and_write() {
+    let writes = Arc::new(Mutex::new(Vec::new()));
+    let compiled = compile("0 read r1\n1 read r2\n2 mul r3 r1 r2\n3 write r3\n4 halt")
+        .unwrap()
+        .instructions;
+    let mut simulator = Simulator::builder(compiled)
+        .io_handler(MockIo {
+            reads: vec![6, 7, 6, 7],
+            writes: Arc::clone(&writes),
+        })
+        .build()
+        .unwrap();
+
+    while simulator.step().is_ok() {}
+
+    assert_eq!(*writes.lock().unwrap(), vec![42]);
+    assert!(simulator.outputs.is_empty());
+
+    // The handler is shared, so a simulator can move to another thread
+    simulator.reset();
+    let handle = std::thread::spawn(move || while simulator.step().is_ok() {});
+    handle.join().unwrap();
+    assert_eq!(*writes.lock().unwrap(), vec![42, 42]);
+}
+
+#[test]
+fn read_and_write_modes_parse_and_format() {
+    let handler = StdioHandler {
+        read_mode: ReadMode::Hex,
+        write_mode: WriteMode::Unsigned,
+    };
+
+    assert_eq!(handler.read_mode.parse("0xFF"), Some(255));
+    assert_eq!(handler.write_mode.format(-1), "65535");
+    assert_eq!(ReadMode::default().parse("-5"), Some(-5));
+    assert_eq!(WriteMode::default().format(-5), "-5");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn instruction_json_round_trip() {
+    let instruction = Instruction::new_from_text("setn r1 5").unwrap();
+    let json = serde_json::to_string(&instruction).unwrap();
+
+    assert!(json.contains("\"instruction_type\":\"setn\""));
+    assert_eq!(serde_json::from_str::<Instruction>(&json).unwrap(), instruction);
+}
+
+#[test]
+fn instruction_try_from_u16() {
+    let cases = [
+        (0x1105_u16, "setn r1, 5"),
+        (0x6211, "add r2, r1, r1"),
+        (0x0102, "write r1"),
+        (0x0000, "halt"),
+    ];
+
+    for (word, text) in cases.iter() {
+        let instruction = Instruction::try_from(*word).unwrap();
+
+        assert_eq!(instruction.to_string(), *text);
+        assert_eq!(u16::from(&instruction), *word);
+    }
+
+    assert_eq!(Instruction::try_from(0x0005), Err(CompileErr::InstructionDoesNotExist));
+}
+
+#[test]
+fn instruction_try_from_str() {
+    let instruction = Instruction::try_from("add r1 r2 r3").unwrap();
+
+    assert_eq!(instruction.word, 0x6123);
+    assert_eq!(instruction, Instruction::new_from_text("add r1 r2 r3").unwrap());
+    assert_eq!(Instruction::try_from("frobnicate r1"), Err(CompileErr::InstructionDoesNotExist));
+}
+
+/// Doubles the register it is given
+fn perform_double(simulator: &mut Simulator) -> Result<(), RuntimeErr> {
+    let register = simulator.current_regs[0];
+    let value = simulator.read_reg(register)?;
+
+    simulator.write_reg(register, value * 2)
+}
+
+#[test]
+fn custom_instruction_set() {
+    let instruction_set = InstructionSet::builtin().with_instruction(InstructionType::new(
+        vec!["double"],
+        "0000 0000 0000 0100",
+        "1111 0000 1111 1111",
+        "r",
+        "Double the contents of register _",
+    ));
+    let source = source_to_lines("0 setn r1 21\n1 double r1\n2 halt");
+
+    // The built-in set does not know the new instruction
+    assert_eq!(
+        Simulator::compile_hmmm(source.clone(), true),
+        Err(CompileErr::InstructionDoesNotExist)
+    );
+
+    let compiled = Simulator::compile_hmmm_in(source, true, &instruction_set).unwrap();
+    assert_eq!(compiled[1].to_u16(), 0x0104);
+
+    // Diagnostics compile through the same path and set
+    let text = "0 setn r1 21\n1 double r1\n2 halt";
+    assert_eq!(compile(text).unwrap_err()[0].line, 2);
+    assert_eq!(compile_in(text, &instruction_set).unwrap().instructions, compiled);
+
+    let mut simulator = Simulator::builder(compiled)
+        .inputs(Vec::new())
+        .output_sink(OutputSink::Outputs)
+        .custom_instruction("double", perform_double)
+        .build()
+        .unwrap();
+    while simulator.step().is_ok() {}
+
+    assert_eq!(simulator.get_register(1), Some(42));
+}
+
+#[test]
+fn event_sender_reports_each_step() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let compiled = compile("0 setn r1 7\n1 storen r1 9\n2 write r1\n3 halt")
+        .unwrap()
+        .instructions;
+    let mut simulator = Simulator::new_headless(compiled).with_event_sender(sender);
+
+    while simulator.step().is_ok() {}
+
+    let events: Vec<SimulatorEvent> = receiver.try_iter().collect();
+
+    assert_eq!(
+        events,
+        vec![
+            SimulatorEvent::StepExecuted { address: 0 },
+            SimulatorEvent::RegisterChanged(RegisterChange {
+                register: 1,
+                old: 0,
+                new: 7,
+            }),
+            SimulatorEvent::StepExecuted { address: 1 },
+            SimulatorEvent::MemoryChanged(MemoryChange {
+                address: 9,
+                old: 0,
+                new: 7,
+            }),
+            SimulatorEvent::StepExecuted { address: 2 },
+            SimulatorEvent::OutputProduced(7),
+            SimulatorEvent::StepExecuted { address: 3 },
+            SimulatorEvent::Halted,
+        ]
+    );
+}
+
+#[test]
+fn large_compile_reports_the_first_error() {
+    // As many lines as fit in memory
+    let source: Vec<String> = (0..256)
+        .map(|i| format!("{} addn r{} {} # line {}", i, i % 16, i % 100, i))
+        .collect();
+
+    assert_eq!(Simulator::compile_hmmm(source.clone(), true).unwrap().len(), 256);
+
+    // Lines are lexed before they are numbered, but the first error
+    // in the source is still the one reported
+    let mut broken = source;
+    broken[200] = String::from("200 bogus r1");
+    broken[240] = String::from("5 halt");
+
+    assert_eq!(
+        Simulator::compile_hmmm(broken, true),
+        Err(CompileErr::InstructionDoesNotExist)
+    );
+}
+
+#[test]
+#[cfg(feature = "parallel")]
+fn parallel_compile_matches_sequential() {
+    // Comments and labels between instructions, so most lines are
+    // filtered out on the way rather than encoded
+    let source: Vec<String> = (0..256)
+        .flat_map(|i| {
+            vec![
+                format!("# line {}", i),
+                format!("step{}:", i),
+                format!("{} jumpn step{} # line {}", i, (i + 1) % 256, i),
+            ]
+        })
+        .collect();
+
+    assert_eq!(
+        Simulator::compile_hmmm_parallel(source.clone(), true),
+        Simulator::compile_hmmm(source.clone(), true)
+    );
+
+    // The first error is reported, not whichever line finished first
+    let mut broken = source;
+    broken[3 * 200 + 2] = String::from("200 bogus r1");
+    broken[3 * 240 + 2] = String::from("5 halt");
+
+    assert_eq!(
+        Simulator::compile_hmmm_parallel(broken.clone(), true),
+        Err(CompileErr::InstructionDoesNotExist)
+    );
+    assert_eq!(
+        Simulator::compile_hmmm_parallel(broken.clone(), true),
+        Simulator::compile_hmmm(broken, true)
+    );
+}
+
+#[test]
+fn streaming_compile_matches_file() {
+    let source = std::fs::read_to_string("tests/test.hmmm").unwrap();
+    let streamed = Simulator::compile_hmmm_streaming(source.as_bytes(), true).unwrap();
+    let expected = Simulator::compile_hmmm(load_file("tests/test.hmmm").unwrap(), true).unwrap();
+
+    assert_eq!(streamed, expected);
+
+    // Labels can still be used before they are defined
+    let source = "0 setn r1 3\n1 jumpn done\nloop:\n2 addn r1 -1\ndone:\n3 jnezn r1 loop\n4 halt";
+    let streamed = Simulator::compile_hmmm_streaming(source.as_bytes(), true).unwrap();
+
+    assert_eq!(streamed, Simulator::compile_hmmm(source_to_lines(source), true).unwrap());
+}
+
+#[test]
+fn version_includes_package_version() {
+    let version = version_string();
+
+    assert!(!version.is_empty());
+    assert!(version.starts_with(env!("CARGO_PKG_VERSION")));
+    assert!(version.contains(&format!(
+        "{} instructions",
+        InstructionSet::builtin().instruction_count()
+    )));
+}
+
+#[test]
+fn bash_completions_include_flags() {
+    let script = completion_script("bash").unwrap();
+
+    assert!(script.contains("--input"));
+    assert!(completion_script("notashell").is_err());
+}
+
+#[test]
+fn repl_prints_encodings_and_runs() {
+    let session = "setn r1 5\nwrite r1\nbogus r1\nhalt\nrun\nquit\nsetn r2 1\n";
+    let mut output: Vec<u8> = Vec::new();
+
+    run_repl(session.as_bytes(), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output.contains("  0  0001 0001 0000 0101\n"));
+    assert!(output.contains("  1  0000 0001 0000 0010\n"));
+    assert!(output.contains("  2  0000 0000 0000 0000\n"));
+    // The mistake is reported without ending the session
+    assert!(output.contains("error: "));
+    assert!(output.contains("5\nhalted after 3 steps\n"));
+    // Nothing after quit is read
+    assert!(!output.contains("  3  "));
+}
+
+#[test]
+fn assemble_and_disassemble_one_instruction() {
+    assert_eq!(assemble_instruction("add r1, r2, r3").unwrap(), "0110 0001 0010 0011 (0x6123)");
+    assert_eq!(disassemble_instruction("0110 0001 0010 0011").unwrap(), "add r1, r2, r3");
+    assert_eq!(disassemble_instruction("0110000100100011").unwrap(), "add r1, r2, r3");
+
+    assert_eq!(assemble_instruction("bogus r1"), Err(CompileErr::InstructionDoesNotExist));
+    assert_eq!(disassemble_instruction("0110 0001"), Err(CompileErr::CorruptedBinary));
+}
+
+#[test]
+fn check_mode_exits_nonzero_on_errors() {
+    let bad = run_cli(vec!["hmmm", "-i", "tests/grading_test/test-compile-error.hmmm", "--check"]);
+    assert_eq!(bad.unwrap(), 1);
+
+    let good = run_cli(vec!["hmmm", "-i", "tests/test.hmmm", "--check"]);
+    assert_eq!(good.unwrap(), 0);
+}
+
+#[test]
+fn diagnostic_json_has_every_field() {
+    let diagnostics = compile("0 setn r1 5\n1 bogus r2").unwrap_err();
+    let json: serde_json::Value =
+        serde_json::from_str(&diagnostics[0].to_json("program.hmmm")).unwrap();
+
+    assert_eq!(json["file"], "program.hmmm");
+    assert_eq!(json["line"], 2);
+    assert_eq!(json["column"], 3);
+    assert_eq!(json["severity"], "error");
+    assert_eq!(json["message"], "unknown instruction \"bogus\"");
+}
+
+/// Wraps a JSON-RPC message in the header the language server expects
+fn lsp_frame(message: serde_json::Value) -> String {
+    let content = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", content.len(), content)
+}
+
+#[test]
+fn lsp_publishes_diagnostics_for_opened_document() {
+    let session = [
+        lsp_frame(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": {},
+        })),
+        lsp_frame(serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {
+                "textDocument": {"uri": "file:///bad.hmmm", "text": "0 setn r1 5\n1 bogus r2"},
+            },
+        })),
+        lsp_frame(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "textDocument/hover",
+            "params": {
+                "textDocument": {"uri": "file:///bad.hmmm"},
+                "position": {"line": 0, "character": 3},
+            },
+        })),
+        lsp_frame(serde_json::json!({"jsonrpc": "2.0", "method": "exit"})),
+    ]
+    .concat();
+    let mut output: Vec<u8> = Vec::new();
+
+    run_lsp(session.as_bytes(), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    let messages: Vec<serde_json::Value> = output
+        .split("Content-Length: ")
+        .filter(|frame| !frame.is_empty())
+        .map(|frame| {
+            let content = frame.splitn(2, "\r\n\r\n").nth(1).unwrap();
+            serde_json::from_str(content).unwrap()
+        })
+        .collect();
+
+    let published = messages
+        .iter()
+        .find(|message| message["method"] == "textDocument/publishDiagnostics")
+        .unwrap();
+    let diagnostic = &published["params"]["diagnostics"][0];
+
+    assert_eq!(published["params"]["uri"], "file:///bad.hmmm");
+    assert_eq!(diagnostic["range"]["start"]["line"], 1);
+    assert_eq!(diagnostic["range"]["start"]["character"], 2);
+    assert_eq!(diagnostic["severity"], 1);
+    assert_eq!(diagnostic["message"], "unknown instruction \"bogus\"");
+
+    let hover = messages.iter().find(|message| message["id"] == 2).unwrap();
+    assert!(hover["result"]["contents"]["value"].as_str().unwrap().contains("setn"));
+}
+
+#[test]
+fn lsp_positions_count_utf16_units() {
+    // The emoji is 4 bytes but 2 UTF-16 units, so character 13 is
+    // inside "setn" rather than the space before it
+    let session = [
+        lsp_frame(serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {
+                "textDocument": {"uri": "file:///emoji.hmmm", "text": "0 halt # \u{1F600} setn"},
+            },
+        })),
+        lsp_frame(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "textDocument/hover",
+            "params": {
+                "textDocument": {"uri": "file:///emoji.hmmm"},
+                "position": {"line": 0, "character": 13},
+            },
+        })),
+        lsp_frame(serde_json::json!({"jsonrpc": "2.0", "method": "exit"})),
+    ]
+    .concat();
+    let mut output: Vec<u8> = Vec::new();
+
+    run_lsp(session.as_bytes(), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    let hover = output
+        .split("Content-Length: ")
+        .filter_map(|frame| frame.splitn(2, "\r\n\r\n").nth(1))
+        .map(|content| serde_json::from_str::<serde_json::Value>(content).unwrap())
+        .find(|message| message["id"] == 1)
+        .unwrap();
+    assert!(hover["result"]["contents"]["value"].as_str().unwrap().contains("setn"));
+}
+
+#[test]
+fn compile_reports_progress() {
+    let source: Vec<String> = (0..250).map(|i| format!("{} nop", i)).collect();
+    let mut reports: Vec<(usize, usize)> = Vec::new();
+
+    let compiled = Simulator::compile_hmmm_with_progress(source, true, |done, total| {
+        reports.push((done, total))
+    })
+    .unwrap();
+
+    assert_eq!(compiled.len(), 250);
+    assert_eq!(reports, vec![(100, 250), (200, 250), (250, 250)]);
+    assert_eq!(reports.len(), (250 + PROGRESS_INTERVAL - 1) / PROGRESS_INTERVAL);
+}
+
+#[test]
+fn quiet_compile_report_is_empty() {
+    let compiled = compile("0 setn r1 5\n1 halt").unwrap().instructions;
+
+    assert!(format_compile_report(&compiled, false, false).contains("COMPILATION SUCCESSFUL"));
+    assert_eq!(format_compile_report(&compiled, false, true), "");
+}
+
+#[test]
+fn explain_describes_instruction() {
+    assert_eq!(
+        explain_instruction("mul").unwrap(),
+        "mul rX rY rZ\n\
+         Multiplies the numbers in rY and rZ, storing the product in rX.\n\
+         \n\
+         Encoding: 1000 XXXX YYYY ZZZZ\n"
+    );
+    assert!(explain_instruction("jump").unwrap().contains("Also written as: jump\n"));
+
+    assert_eq!(
+        explain_instruction("mull").unwrap_err(),
+        "unknown instruction \"mull\", did you mean \"mul\"?"
+    );
+    assert_eq!(explain_instruction("xyzzy").unwrap_err(), "unknown instruction \"xyzzy\"");
+}
+
+#[test]
+fn unknown_instruction_suggests_closest() {
+    let line_parts: Vec<String> = vec!["3".into(), "storn".into(), "r1".into(), "5".into()];
+
+    assert_eq!(
+        instruction_suggestion(&CompileErr::InstructionDoesNotExist, &line_parts).unwrap(),
+        "unknown instruction \"storn\", did you mean \"storen\"?"
+    );
+    assert_eq!(instruction_suggestion(&CompileErr::InvalidRegister, &line_parts), None);
+
+    // Diagnostics carry the same hint, for editors to show
+    let diagnostics = compile("0 storn r1 5\n1 halt").unwrap_err();
+    assert_eq!(
+        diagnostics[0].message,
+        "unknown instruction \"storn\", did you mean \"storen\"?"
+    );
+}
+
+#[test]
+fn presets_are_set_before_running() {
+    let compiled = compile("0 write r3\n1 loadn r4 10\n2 write r4\n3 halt").unwrap().instructions;
+    let mut simulator = Simulator::builder(compiled)
+        .inputs(Vec::new())
+        .output_sink(OutputSink::Outputs)
+        .preset_register(3, 42)
+        .preset_memory(10, 7)
+        .build()
+        .unwrap();
+
+    simulator.step().unwrap();
+    assert_eq!(simulator.get_outputs(), vec![42]);
+
+    while simulator.step().is_ok() {}
+    assert_eq!(simulator.get_outputs(), vec![42, 7]);
+    assert!(simulator.warnings.is_empty());
+
+    // Presets come back after a reset
+    simulator.reset();
+    assert_eq!(simulator.get_register(3), Some(42));
+
+    assert_eq!(parse_register_preset("r3=42"), Ok((3, 42)));
+    assert_eq!(parse_memory_preset("10=-7"), Ok((10, -7)));
+    assert!(parse_register_preset("r0=1").is_err());
+    assert!(parse_register_preset("r3=40000").is_err());
+    assert!(parse_memory_preset("256=1").is_err());
+    assert!(parse_memory_preset("10").is_err());
+
+    // Presets the simulator has no room for are an error, not a panic
+    let program = compile("0 halt").unwrap().instructions;
+    assert_eq!(
+        Simulator::builder(program.clone())
+            .preset_register(0, 1)
+            .build()
+            .unwrap_err(),
+        BuildErr::RegisterPresetOutOfRange {
+            register: 0,
+            register_count: 16
+        }
+    );
+    assert_eq!(
+        Simulator::builder(program)
+            .memory_size(64)
+            .preset_memory(100, 1)
+            .build()
+            .unwrap_err(),
+        BuildErr::MemoryPresetOutOfRange {
+            address: 100,
+            memory_size: 64
+        }
+    );
+}
+
+#[test]
+fn data_file_loads_an_array() {
+    let data = parse_data_file("# three numbers to sum\n100 3 4 5\n\n").unwrap();
+    assert_eq!(data, vec![(100, 3), (101, 4), (102, 5)]);
+
+    let source = "0 loadn r1 100\n1 loadn r2 101\n2 loadn r3 102\n3 add r1 r1 r2\n\
+                  4 add r1 r1 r3\n5 write r1\n6 halt";
+    let mut builder = Simulator::builder(compile(source).unwrap().instructions)
+        .inputs(Vec::new())
+        .output_sink(OutputSink::Outputs);
+    for (address, value) in data {
+        builder = builder.preset_memory(address, value);
+    }
+    let mut simulator = builder.build().unwrap();
+
+    while simulator.step().is_ok() {}
+    assert_eq!(simulator.get_outputs(), vec![12]);
+
+    assert_eq!(parse_data_file("7 -1\n8 2").unwrap(), vec![(7, -1), (8, 2)]);
+    assert!(parse_data_file("300 1").is_err());
+    assert!(parse_data_file("254 1 2 3").is_err());
+    assert!(parse_data_file("10").is_err());
+}
+
+#[test]
+fn debug_step_table_lists_each_step() {
+    let compiled = compile("0 setn r1 5\n1 addn r1 2\n2 halt").unwrap().instructions;
+    let mut simulator = Simulator::new_headless(compiled);
+    let mut table = format_step_table_header();
+
+    for _ in 0..2 {
+        let address = simulator.get_program_counter();
+        simulator.step().unwrap();
+        table.push_str(&format_step_row(&simulator, simulator.steps_taken, address));
+    }
+
+    let rows: Vec<Vec<&str>> = table.lines().map(|row| row.split_whitespace().collect()).collect();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0][..5], ["STEP", "PC", "INSTRUCTION", "r0", "r1"]);
+    assert_eq!(rows[0].len(), 3 + 16);
+    assert_eq!(rows[1][..6], ["1", "0", "setn", "r1,", "5", "0"]);
+    assert_eq!(rows[1][6], "5");
+    assert_eq!(rows[2][..6], ["2", "1", "addn", "r1,", "2", "0"]);
+    assert_eq!(rows[2][6], "7");
+    assert!(rows[2][7..].iter().all(|register| *register == "0"));
+}
+
+#[test]
+fn trace_csv_lists_register_changes() {
+    let compiled = compile("0 setn r1 5\n1 copy r2 r1\n2 halt").unwrap().instructions;
+    let mut simulator = Simulator::new_headless(compiled);
+    let mut trace = Vec::new();
+
+    loop {
+        let address = simulator.get_program_counter();
+        let instruction = simulator.get_memory(address).unwrap();
+
+        let step_result = simulator.step().unwrap();
+        let halted = step_result.halted;
+
+        trace.push((instruction, step_result));
+        if halted {
+            break;
+        }
+    }
+
+    let csv = as_trace_csv(&trace);
+    let rows: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        rows,
+        vec![
+            "step,pc,mnemonic,args,changed_reg,old,new",
+            "1,0,setn,\"r1, 5\",r1,0,5",
+            "2,1,copy,\"r2, r1\",r2,0,5",
+            "3,2,halt,,,,",
+        ]
+    );
+}
+
+#[test]
+fn heatmap_counts_memory_accesses() {
+    let source = "0 setn r1 100\n1 setn r2 0\n2 setn r3 3\n3 loadr r4 r1\n4 add r2 r2 r4\n\
+                  5 addn r1 1\n6 addn r3 -1\n7 jnezn r3 3\n8 storen r2 110\n9 halt";
+    let mut simulator = Simulator::builder(compile(source).unwrap().instructions)
+        .inputs(Vec::new())
+        .output_sink(OutputSink::Outputs)
+        .preset_memory(100, 1)
+        .preset_memory(101, 2)
+        .preset_memory(102, 3)
+        .build()
+        .unwrap();
+
+    while simulator.step().is_ok() {}
+
+    for address in 100..103 {
+        assert_eq!(simulator.memory_reads[address], 1);
+    }
+    assert_eq!(simulator.memory_writes[110], 1);
+    assert_eq!(simulator.memory[110], 6);
+    assert_eq!(simulator.memory_reads[103], 0);
+
+    assert_eq!(
+        as_heatmap_csv(&simulator),
+        "address,reads,writes\n100,1,0\n101,1,0\n102,1,0\n110,0,1\n"
+    );
+}
+
+#[test]
+fn program_data_can_be_loaded() {
+    // A table after the code, read by the code
+    let mut compiled = compile("0 loadn r1 3\n1 loadn r2 4\n2 halt").unwrap().instructions;
+    compiled.push(Instruction::new_data_word(7));
+    compiled.push(Instruction::new_data_word(8));
+
+    let mut simulator = Simulator::new_headless(compiled);
+    while simulator.step().is_ok() {}
+
+    assert_eq!(simulator.get_register(1), Some(7));
+    assert_eq!(simulator.get_register(2), Some(8));
+}
+
+#[test]
+fn jump_into_data_warns() {
+    // A jump that lands in the table after the code
+    let mut compiled = compile("0 jumpn 3\n1 halt\n2 halt").unwrap().instructions;
+    compiled.push(Instruction::new_data_word(7));
+    compiled.push(Instruction::new_data_word(8));
+
+    let mut simulator = Simulator::new_headless(compiled.clone());
+    assert_eq!(simulator.step().map(|_| ()), Ok(()));
+    assert_eq!(simulator.step(), Err(RuntimeErr::InstructionIsData));
+    assert_eq!(
+        simulator.warnings,
+        vec![RuntimeWarning::JumpIntoData { pc: 0, address: 3 }]
+    );
+
+    // Strict mode stops at the jump itself
+    let mut strict = Simulator::builder(compiled)
+        .inputs(vec![])
+        .output_sink(OutputSink::Outputs)
+        .strict(true)
+        .build()
+        .unwrap();
+    assert_eq!(strict.step(), Err(RuntimeErr::JumpIntoData));
+    assert_eq!(strict.get_program_counter(), 0);
+}
+
+#[test]
+fn jump_past_end_warns_when_compiling() {
+    let program = compile("0 setn r1 1\n1 jumpn 9\n2 halt").unwrap();
+
+    assert_eq!(
+        program.warnings,
+        vec![Diagnostic {
+            line: 2,
+            column: 3,
+            severity: Severity::Warning,
+            message: "jumpn uses address 9, past the end of the program at address 2".to_string(),
+        }]
+    );
+    assert_eq!(hmmm_rs::analysis::out_of_range_addresses(&program.instructions), vec![(1, 9)]);
+
+    let strict = run_cli(vec!["hmmm", "-i", "tests/jump_past_end.hmmm", "--strict", "-q"]);
+    assert_eq!(hmmm_exit_code(strict.unwrap_err().as_ref()), Some(22));
+}
+
+#[test]
+fn stack_pointer_starts_after_program() {
+    let source = "0 setn r1 11\n1 setn r2 22\n2 pushr r1 r15\n3 pushr r2 r15\n\
+                  4 popr r3 r15\n5 popr r4 r15\n6 halt";
+    let mut simulator = Simulator::builder(compile(source).unwrap().instructions)
+        .inputs(Vec::new())
+        .output_sink(OutputSink::Outputs)
+        .stack_pointer(15)
+        .build()
+        .unwrap();
+
+    assert_eq!(simulator.get_register(15), Some(7));
+
+    while simulator.step().is_ok() {}
+
+    // Popped in the opposite order, back to where it started
+    assert_eq!(simulator.get_register(3), Some(22));
+    assert_eq!(simulator.get_register(4), Some(11));
+    assert_eq!(simulator.get_register(15), Some(7));
+    assert_eq!(simulator.memory[7..9], [11, 22]);
+
+    simulator.reset();
+    assert_eq!(simulator.get_register(15), Some(7));
+}
+
+#[test]
+fn subroutine_clobbers_are_listed() {
+    let source: Vec<String> = "0 setn r1 3\n1 calln r14 4\n2 write r1\n3 halt\n\
+                               4 setn r2 1\n5 add r3 r1 r2\n6 jumpr r14"
+        .lines()
+        .map(String::from)
+        .collect();
+    let program = Simulator::compile_hmmm(source.clone(), true).unwrap();
+
+    assert_eq!(hmmm_rs::analysis::clobbered_registers(&program, 4), vec![2, 3]);
+    // The main program also changes r14 with its call
+    assert_eq!(hmmm_rs::analysis::clobbered_registers(&program, 0), vec![1, 2, 3, 14]);
+
+    let listing = as_listing(&program, &source);
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines[lines.len() - 2], "SUBROUTINE  MODIFIES");
+    assert_eq!(lines[lines.len() - 1], "         4  r2, r3");
+}
+
+#[test]
+fn gdb_stub_reads_registers_and_memory() {
+    use hmmm_rs::gdb::*;
+
+    assert_eq!(decode_packet("$g#67"), Some("g".to_string()));
+    assert_eq!(decode_packet("$g#00"), None);
+    assert_eq!(parse_command("g"), GdbCommand::ReadRegisters);
+    assert_eq!(parse_command("m0,4"), GdbCommand::ReadMemory { address: 0, length: 4 });
+    assert_eq!(parse_command("Z0,2,2"), GdbCommand::InsertBreakpoint(2));
+
+    let compiled = compile("0 setn r1 5\n1 halt").unwrap().instructions;
+    let mut simulator = Simulator::new_headless(compiled);
+    let mut breakpoints = Vec::new();
+
+    assert_eq!(handle_command(&mut simulator, &mut breakpoints, &GdbCommand::Step), "S05");
+
+    // r0, then r1 = 5, then zeroes, then the program counter at byte 2
+    let registers = handle_command(&mut simulator, &mut breakpoints, &GdbCommand::ReadRegisters);
+    assert_eq!(registers.len(), 17 * 4);
+    assert_eq!(&registers[..8], "00000500");
+    assert_eq!(&registers[64..], "0200");
+
+    let memory = parse_command("m0,4");
+    assert_eq!(handle_command(&mut simulator, &mut breakpoints, &memory), "05110000");
+
+    // Lengths that overflow the address, or are absurdly long, are refused
+    let overflowing = parse_command("mffffffffffffffff,ffffffffffffffff");
+    assert_eq!(handle_command(&mut simulator, &mut breakpoints, &overflowing), "E01");
+    let too_long = parse_command("m0,10000000");
+    assert_eq!(handle_command(&mut simulator, &mut breakpoints, &too_long), "E01");
+
+    // Packets go over the stream acknowledged and checksummed
+    let mut stream = GdbClient {
+        sent: std::io::Cursor::new(b"$m0,2#fb$D#44".to_vec()),
+        received: Vec::new(),
+    };
+    run_gdb_stub(&mut stream, &mut simulator).unwrap();
+    assert_eq!(
+        String::from_utf8(stream.received).unwrap(),
+        format!("+{}+{}", encode_packet("0511"), encode_packet("OK"))
+    );
+}
+
+/// Both ends of a connection to the GDB stub, as one stream
+struct GdbClient {
+    sent: std::io::Cursor<Vec<u8>>,
+    received: Vec<u8>,
+}
+
+impl std::io::Read for GdbClient {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        std::io::Read::read(&mut self.sent, buf)
+    }
+}
+
+impl std::io::Write for GdbClient {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.received.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn dap_verifies_breakpoints_on_instructions() {
+    let session = [
+        lsp_frame(serde_json::json!({"seq": 1, "type": "request", "command": "initialize"})),
+        lsp_frame(serde_json::json!({
+            "seq": 2,
+            "type": "request",
+            "command": "launch",
+            "arguments": {"program": "tests/test.hmmm", "inputs": [16, 2]},
+        })),
+        // Line 5 is blank, so has nowhere to stop
+        lsp_frame(serde_json::json!({
+            "seq": 3,
+            "type": "request",
+            "command": "setBreakpoints",
+            "arguments": {
+                "source": {"path": "tests/test.hmmm"},
+                "breakpoints": [{"line": 6}, {"line": 5}],
+            },
+        })),
+        lsp_frame(serde_json::json!({"seq": 4, "type": "request", "command": "continue"})),
+        lsp_frame(serde_json::json!({"seq": 5, "type": "request", "command": "disconnect"})),
+    ]
+    .concat();
+
+    let mut output: Vec<u8> = Vec::new();
+    run_dap(session.as_bytes(), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    let messages: Vec<serde_json::Value> = output
+        .split("Content-Length: ")
+        .skip(1)
+        .map(|frame| serde_json::from_str(frame.splitn(2, "\r\n\r\n").nth(1).unwrap()).unwrap())
+        .collect();
+
+    let breakpoints = messages
+        .iter()
+        .find(|message| message["command"] == "setBreakpoints")
+        .unwrap();
+    assert_eq!(
+        breakpoints["body"]["breakpoints"],
+        serde_json::json!([{"verified": true, "line": 6}, {"verified": false, "line": 5}])
+    );
+
+    // Continuing stops at the div on line 6, after echoing the first input
+    let stopped = messages
+        .iter()
+        .find(|message| message["event"] == "stopped")
+        .unwrap();
+    assert_eq!(stopped["body"]["reason"], "breakpoint");
+    assert!(messages
+        .iter()
+        .any(|message| message["event"] == "output" && message["body"]["output"] == "16\n"));
+}
+
+#[test]
+fn custom_output_formats_can_be_registered() {
+    struct CountFormat;
+
+    impl OutputFormat for CountFormat {
+        fn name(&self) -> &str {
+            "count"
+        }
+
+        fn extension(&self) -> &str {
+            ".count"
+        }
+
+        fn emit(&self, program: &[Instruction]) -> String {
+            format!("{} instructions\n", program.len())
+        }
+    }
+
+    let compiled = compile("0 setn r1 5\n1 write r1\n2 halt").unwrap().instructions;
+    let registry = FormatRegistry::builtin().with_format(CountFormat);
+
+    let by_name = registry.by_name("count").unwrap();
+    assert_eq!(by_name.emit(&compiled), "3 instructions\n");
+    assert_eq!(registry.by_extension("out.count").unwrap().name(), "count");
+
+    // Built-in formats are still found by name and extension
+    assert_eq!(registry.by_name("hb").unwrap().emit(&compiled), as_compiled_hmmm(&compiled));
+    assert_eq!(registry.by_extension("out.csv").unwrap().name(), "csv");
+    assert!(registry.by_name("nope").is_none());
+}
+
+#[test]
+fn serve_compiles_posted_programs() {
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || run_server(&listener));
+
+    let source = "0 setn r1 5\n1 write r1\n2 halt";
+    let body = serde_json::json!({ "source": source }).to_string();
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "POST /compile HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+    let (_, content) = response.split_once("\r\n\r\n").unwrap();
+    let content: serde_json::Value = serde_json::from_str(content).unwrap();
+    let compiled = compile(source).unwrap().instructions;
+    assert_eq!(content["success"], true);
+    assert_eq!(content["binary"], as_compiled_hmmm(&compiled));
+
+    let (status, ran) = hmmm_rs::serve::handle_request(
+        "POST",
+        "/run",
+        r#"{"source": "0 read r1\n1 write r1\n2 halt", "inputs": [7]}"#,
+    );
+    assert_eq!(status, 200);
+    assert_eq!(ran["outputs"], serde_json::json!([7]));
+    assert_eq!(ran["state"]["registers"][1], 7);
+}
+
+#[test]
+fn serve_survives_bad_requests() {
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || run_server(&listener));
+
+    // A client that never sends anything is dropped after a timeout
+    let _idle = TcpStream::connect(address).unwrap();
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "POST /run HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+        u64::MAX
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large"));
+
+    // A program too big for memory is a compile error, not a panic
+    let source: Vec<String> = (0..300).map(|line| format!("{} nop", line)).collect();
+    let body = serde_json::json!({ "source": source.join("\n") }).to_string();
+    let (status, ran) = hmmm_rs::serve::handle_request("POST", "/run", &body);
+    assert_eq!(status, 200);
+    assert_eq!(ran["success"], false);
+}
+
+#[test]
+fn simulator_display_shows_the_whole_state() {
+    let compiled = compile("0 setn r1 42\n1 storen r1 10\n2 halt").unwrap().instructions;
+    let mut simulator = Simulator::new_headless(compiled);
+    simulator.step().unwrap();
+    simulator.step().unwrap();
+
+    let shown = simulator.to_string();
+    assert!(shown.starts_with("PC: 2\n"));
+    assert!(shown.contains("r1  = 42"));
+    assert!(shown.contains("10: 42"));
+}
+
+#[test]
+fn steps_iterate_until_halt() {
+    let compiled = compile("0 setn r1 3\n1 addn r1 -1\n2 jnezn r1 1\n3 halt").unwrap().instructions;
+    let mut simulator = Simulator::new_headless(compiled);
+
+    let steps: Vec<Result<StepResult, RuntimeErr>> = simulator.steps().collect();
+
+    // setn, three rounds of addn and jnezn, then the halt
+    assert_eq!(steps.len(), 8);
+    assert!(steps.iter().all(|step| step.is_ok()));
+    assert!(steps[7].as_ref().unwrap().halted);
+    assert!(!steps[6].as_ref().unwrap().halted);
+    assert_eq!(steps[0].as_ref().unwrap().address, 0);
+}
+
+#[test]
+fn commented_hmmm_lines_up_comments() {
+    let source = "0 setn r1 5\n1 calln r14 4\n2 write r1\n3 halt\n4 add r1 r1 r1\n5 jumpr r14";
+    let program = compile(source).unwrap().instructions;
+    let comments: Vec<Option<String>> = vec![
+        Some("set up".to_string()),
+        Some("double it".to_string()),
+        None,
+        Some("done".to_string()),
+        Some("double".to_string()),
+        Some("return".to_string()),
+    ];
+
+    let commented = as_commented_hmmm(&program, &comments);
+    let columns: Vec<usize> = commented.lines().filter_map(|line| line.find('#')).collect();
+
+    assert_eq!(columns.len(), 5);
+    assert!(columns.iter().all(|column| *column == columns[0]));
+    // Comments do not change the program
+    assert_eq!(compile(&commented).unwrap().instructions, program);
+}
+
+#[test]
+fn memory_size_limits_addresses() {
+    let program = compile("0 setn r1 7\n1 storen r1 100\n2 halt").unwrap().instructions;
+
+    let mut small = Simulator::builder(program.clone()).memory_size(64).build().unwrap();
+    assert_eq!(small.memory_words().count(), 64);
+    assert!(small.step().is_ok());
+    assert_eq!(small.step(), Err(RuntimeErr::InvalidMemoryLocation));
+
+    // The default 256 words have room for address 100
+    let mut full = Simulator::new_headless(program);
+    while full.step().is_ok() {}
+    assert_eq!(full.get_memory(100).map(|word| word.word), Some(7));
+
+    assert_eq!(parse_memory_size("64"), Ok(64));
+    assert!(parse_memory_size("0").is_err());
+    assert!(parse_memory_size("257").is_err());
+}
+
+#[test]
+fn register_count_can_be_changed() {
+    let program = compile("0 setn r3 9\n1 halt").unwrap().instructions;
+
+    let mut wide = Simulator::builder(program.clone()).register_count(32).build().unwrap();
+    assert_eq!(wide.registers.len(), 32);
+    wide.write_reg(20, 5).unwrap();
+    assert_eq!(wide.get_register(20), Some(5));
+    while wide.step().is_ok() {}
+    assert_eq!(wide.get_register(3), Some(9));
+
+    // r3 does not exist with only r0 to r2
+    let mut narrow = Simulator::builder(program)
+        .register_count(3)
+        .build()
+        .unwrap();
+    assert_eq!(narrow.step(), Err(RuntimeErr::InvalidRegisterLocation));
+    assert_eq!(narrow.write_reg(3, 1), Err(RuntimeErr::InvalidRegisterLocation));
+}
+
+#[test]
+fn inline_labels_can_be_jumped_to() {
+    let source = "0 setn r1 3\nloop: 1 addn r1 -1\n2 write r1\n3 jnezn r1 loop\n4 halt";
+
+    let program = compile(source).unwrap().instructions;
+    assert_eq!(program[3], Instruction::new_from_text("jnezn r1 1").unwrap());
+
+    let lines: Vec<String> = source.lines().map(String::from).collect();
+    assert_eq!(Simulator::compile_hmmm(lines, true).unwrap(), program);
+
+    let mut simulator = Simulator::builder(program)
+        .output_sink(OutputSink::Outputs)
+        .build()
+        .unwrap();
+    while simulator.step().is_ok() {}
+    assert_eq!(simulator.get_outputs(), vec![2, 1, 0]);
+}
+
+#[test]
+fn missing_halt_warns_and_times_out() {
+    let program = compile("0 setn r1 1\n1 jumpn 0").unwrap();
+    let missing_halt = program
+        .warnings
+        .iter()
+        .find(|warning| warning.message.contains("no halt"))
+        .unwrap();
+
+    assert_eq!(missing_halt.line, 2);
+    assert_eq!(
+        missing_halt.message,
+        "program has no halt, so it will run past its last instruction"
+    );
+    assert!(!hmmm_rs::analysis::has_halt(&program.instructions));
+
+    let simulator = Simulator::new_headless(program.instructions.clone());
+    assert_eq!(simulator.step_limit, Some(DEFAULT_STEP_LIMIT));
+
+    let mut simulator = Simulator::builder(program.instructions)
+        .step_limit(1000)
+        .build()
+        .unwrap();
+    let result = loop {
+        if let Err(error) = simulator.step() {
+            break error;
+        }
+    };
+    assert_eq!(result, RuntimeErr::MaximumIterationsReached);
+    assert_eq!(simulator.steps_taken, 1000);
+
+    assert!(compile("0 halt").unwrap().warnings.is_empty());
+}
+
+#[test]
+fn basic_blocks_split_a_loop() {
+    use hmmm_rs::analysis::{basic_blocks, BasicBlock};
+
+    let source = "0 setn r1 3\n1 addn r1 -1\n2 write r1\n3 jnezn r1 1\n4 halt";
+    let program = compile(source).unwrap().instructions;
+
+    assert_eq!(
+        basic_blocks(&program),
+        vec![
+            BasicBlock { start: 0, end: 0, successors: vec![1] },
+            BasicBlock { start: 1, end: 3, successors: vec![1, 4] },
+            BasicBlock { start: 4, end: 4, successors: vec![] },
+        ]
+    );
+    assert!(basic_blocks(&[]).is_empty());
+}
+
+#[test]
+fn infinite_loops_warn_when_compiling() {
+    let mut source: Vec<String> = (0..10)
+        .map(|line| format!("{} setn r1 {}", line, line))
+        .collect();
+    source.push("10 jumpn 10".to_string());
+    source.push("11 halt".to_string());
+
+    let program = compile(&source.join("\n")).unwrap();
+    assert_eq!(hmmm_rs::analysis::infinite_loops(&program.instructions), vec![10]);
+    assert_eq!(
+        program.warnings,
+        vec![Diagnostic {
+            line: 11,
+            column: 4,
+            severity: Severity::Warning,
+            message: "the loop at address 10 can never halt or leave, so it will run forever"
+                .to_string(),
+        }]
+    );
+
+    // Loops with a way out are fine
+    let counted = compile("0 setn r1 3\n1 addn r1 -1\n2 jnezn r1 1\n3 halt").unwrap();
+    assert!(hmmm_rs::analysis::infinite_loops(&counted.instructions).is_empty());
+    assert!(counted.warnings.is_empty());
+
+    // As is waiting on a subroutine that returns
+    let calls = compile("0 calln r14 2\n1 jumpn 0\n2 jumpr r14").unwrap();
+    assert!(hmmm_rs::analysis::infinite_loops(&calls.instructions).is_empty());
+}
+
+#[test]
+fn org_places_code_at_an_address() {
+    let source = "0 setn r1 5\n1 jumpn start\n.org 20\nstart: 20 write r1\n21 halt";
+
+    let program = compile(source).unwrap().instructions;
+    assert_eq!(program.len(), 22);
+    assert_eq!(program[1], Instruction::new_from_text("jumpn 20").unwrap());
+    assert_eq!(program[20], Instruction::new_from_text("write r1").unwrap());
+    assert!(program[2..20].iter().all(|word| *word == Instruction::new_blank_data()));
+
+    let lines: Vec<String> = source.lines().map(String::from).collect();
+    assert_eq!(Simulator::compile_hmmm(lines, true).unwrap(), program);
+
+    let mut simulator = Simulator::builder(program)
+        .output_sink(OutputSink::Outputs)
+        .build()
+        .unwrap();
+    while simulator.step().is_ok() {}
+    assert_eq!(simulator.get_outputs(), vec![5]);
+
+    // The words skipped over are data, so can be stored to and loaded
+    let padded = "0 setn r1 9\n1 storen r1 5\n2 loadn r2 5\n3 halt\n.org 10\n10 halt";
+    let mut simulator = Simulator::builder(compile(padded).unwrap().instructions)
+        .inputs(vec![])
+        .output_sink(OutputSink::Outputs)
+        .break_on_self_modify(true)
+        .build()
+        .unwrap();
+    while simulator.step().is_ok() {}
+    assert_eq!(simulator.get_register(2), Some(9));
+    assert!(simulator.warnings.is_empty());
+
+    // Moving back over code already placed is an error
+    let backward = "0 setn r1 5\n1 setn r2 6\n2 setn r3 7\n.org 1\n1 halt";
+    let lines: Vec<String> = backward.lines().map(String::from).collect();
+    assert_eq!(
+        Simulator::compile_hmmm(lines, true),
+        Err(CompileErr::OrgMovesBackward)
+    );
+    assert_eq!(compile(backward).unwrap_err()[0].line, 4);
+
+    // Code placed past a smaller memory is a compile error
+    let path = "tests/org_high.hmmm";
+    let small = run_cli(vec!["hmmm", "-i", path, "--memory-size", "64", "-n", "-q"]);
+    assert_eq!(hmmm_exit_code(small.unwrap_err().as_ref()), Some(24));
+    assert_eq!(run_cli(vec!["hmmm", "-i", path, "-n", "-q"]).unwrap(), 0);
+}
+
+#[test]
+fn builder_reports_programs_that_do_not_fit() {
+    let program = compile("0 setn r1 1\n1 setn r2 2\n2 halt").unwrap().instructions;
+
+    assert_eq!(
+        Simulator::builder(program.clone()).memory_size(2).build().unwrap_err(),
+        BuildErr::ProgramTooLarge {
+            words: 3,
+            memory_size: 2
+        }
+    );
+    assert_eq!(
+        Simulator::builder(program).register_count(0).build().unwrap_err(),
+        BuildErr::NoRegisters
+    );
+
+    // Programs longer than memory are caught when compiling
+    let source: Vec<String> = (0..257).map(|line| format!("{} nop", line)).collect();
+    assert_eq!(
+        Simulator::compile_hmmm(source.clone(), true),
+        Err(CompileErr::ProgramTooLarge)
+    );
+    assert_eq!(compile(&source.join("\n")).unwrap_err()[0].line, 257);
+}
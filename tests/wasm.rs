@@ -0,0 +1,18 @@
+#![cfg(feature = "wasm")]
+
+use hmmm_rs::wasm::compile;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test]
+fn compile_returns_binary() {
+    let binary = js_sys::Array::from(&compile("0 setn r1 5\n1 halt").unwrap());
+
+    assert_eq!(binary.length(), 2);
+    assert_eq!(binary.get(0).as_string().unwrap(), "0001 0001 0000 0101");
+    assert_eq!(binary.get(1).as_string().unwrap(), "0000 0000 0000 0000");
+}
+
+#[wasm_bindgen_test]
+fn compile_error_is_returned() {
+    assert!(compile("0 notaninstruction r1").is_err());
+}